@@ -3,16 +3,13 @@ pub mod responses;
 mod routes;
 pub(crate) mod websocket_handler;
 
-use crate::api_manager::responses::{
-    not_found_response, server_error_response, unauthorized_response,
+use crate::{
+    api_manager::responses::{not_found_response, server_error_response, unauthorized_response},
+    printers::{PrinterHandle, Printers, DEFAULT_PRINTER},
 };
 
-use self::{
-    models::{AuthPermissions, EventType, StateWrapper},
-    responses::bad_request_response,
-};
+use self::{models::AuthPermissions, responses::bad_request_response};
 
-use crossbeam_channel::Sender;
 use hyper::{
     header::{self, HeaderValue, ACCESS_CONTROL_ALLOW_ORIGIN},
     upgrade::Upgraded,
@@ -38,31 +35,28 @@ pub struct ApiManager {}
     Create the socket hashmap, and setup arcs for state.
 
     Arguments:
-    - distributor: The sender for the global events channel.
-    - distributor_receiver: The receiver for the global events channel, intended for the websocket connections.
+    - printers: Registry of the running printers, routes are handled for one of them.
+    - sockets: hashmap including all websocket senders, mapped by uuid.
 
 
 */
 impl ApiManager {
     pub async fn start(
-        distributor: Sender<EventType>,
+        printers: Printers,
         sockets: Arc<Mutex<HashMap<u128, WebSocketStream<Upgraded>>>>,
-        state: Arc<Mutex<StateWrapper>>,
     ) -> () {
         let file_server = Static::new(Path::new("client"));
 
         let make_svc = make_service_fn(move |_| {
-            let distributor = distributor.clone();
-            let state = state.clone();
+            let printers = printers.clone();
             let sockets = sockets.clone();
             let file_server = file_server.clone();
             async move {
                 Ok::<_, Error>(service_fn(move |req| {
-                    let state = state.clone();
-                    let dist_clone = distributor.clone();
+                    let printers = printers.clone();
                     let sockets = sockets.clone();
                     let file_server = file_server.clone();
-                    async move { router(req, file_server, dist_clone, state, sockets).await }
+                    async move { router(req, file_server, printers, sockets).await }
                 }))
             }
         });
@@ -81,17 +75,14 @@ impl ApiManager {
 
     Arguments:
     - req: The hyper request.
-    - distributor: The sender for the global events channel.
-    - receiver: The receiver for the global events channel.
-    - state: current state arc, used by websockets.
+    - printers: Registry of the running printers, used by routes & websockets.
     - sockets: hashmap including all websocket senders, mapped by uuid.
 
 */
 async fn router(
     mut req: Request<Body>,
     file_server: Static,
-    distributor: Sender<EventType>,
-    state: Arc<Mutex<StateWrapper>>,
+    printers: Printers,
     sockets: Arc<Mutex<HashMap<u128, WebSocketStream<Upgraded>>>>,
 ) -> Result<Response<Body>, Infallible> {
     /*
@@ -155,7 +146,7 @@ async fn router(
                     if let Err(e) = websocket_handler::handler(
                         websocket.await.expect("[WS] Handshake failure"),
                         user,
-                        state,
+                        printers,
                         sockets,
                     )
                     .await
@@ -184,7 +175,7 @@ async fn router(
     } else if req.uri().path().eq("/ws") {
         return Ok(bad_request_response());
     } else if req.uri().path().starts_with("/api/") {
-        return Ok(handle_route(req, distributor, state).await);
+        return Ok(handle_route(req, printers, sockets).await);
     } else {
         if !req.uri().path().contains(".") {
             *req.uri_mut() = "/".parse().unwrap();
@@ -208,25 +199,33 @@ async fn router(
 /*
     Function gets called by the router after the request isn't a websocket upgrade request.
 
+    Routes of a printer are addressed with /api/printers/{id}/..., for example /api/printers/2/print.
+    Without the prefix (/api/print) the default printer is addressed.
+    /api/printers/{id} itself is handled as /api/printer of that printer.
+
     Arguments:
     - request: Original hyper request.
-    - distributor: Global sender to send events to.
+    - printers: Registry of the running printers.
+    - sockets: hashmap including all websocket senders, passed to the manager of a new printer.
 
 */
 async fn handle_route(
     mut request: Request<Body>,
-    distributor: Sender<EventType>,
-    state: Arc<Mutex<StateWrapper>>,
+    printers: Printers,
+    sockets: Arc<Mutex<HashMap<u128, WebSocketStream<Upgraded>>>>,
 ) -> Response<Body> {
     let path = normalize_url(&request);
     if path.is_none() {
         return bad_request_response();
     }
-    let path = path.unwrap();
+    let (printer_id, path) = match split_printer_path(&path.unwrap()) {
+        Some(result) => result,
+        None => return bad_request_response(),
+    };
 
     // In case the request is an OPTIONS request, handle with cors headers.
     if request.method() == Method::OPTIONS {
-        return handle_option_requests(&request);
+        return handle_option_requests(&path);
     }
     // Handle exact messages.
     if request.method() == Method::GET && path.eq(routes::ping::PATH) {
@@ -270,14 +269,72 @@ async fn handle_route(
         if !permissions.file_edit() || !permissions.file_access() {
             return unauthorized_response();
         }
-        return routes::upload_file::handler(&mut request, state).await;
+        return routes::upload_file::handler(&mut request, printers).await;
     }
 
     if request.method().eq(&Method::POST) && path.eq(routes::rename_file::PATH) {
         if !permissions.file_edit() || !permissions.file_access() {
             return unauthorized_response();
         }
-        return routes::rename_file::handler(request, state).await;
+        return routes::rename_file::handler(request, printers).await;
+    }
+
+    if request.method().eq(&Method::GET) && path.eq(routes::list_printers::PATH) {
+        return routes::list_printers::handler(printers).await;
+    }
+
+    if request.method().eq(&Method::POST) && path.eq(routes::create_printer::PATH) {
+        if !permissions.edit_connection() {
+            return unauthorized_response();
+        }
+        return routes::create_printer::handler(request, printers, sockets).await;
+    }
+
+    if request.method().eq(&Method::GET) && path.eq(routes::list_history::PATH) {
+        return routes::list_history::handler(request).await;
+    }
+
+    if request.method().eq(&Method::DELETE) && path.eq(routes::delete_history_job::PATH) {
+        if !permissions.print_state_edit() {
+            return unauthorized_response();
+        }
+        return routes::delete_history_job::handler(request).await;
+    }
+
+    // From this point routes of a single printer only
+    let printer = printers.lock().await.get(&printer_id).cloned();
+    if printer.is_none() {
+        return not_found_response();
+    }
+    let PrinterHandle {
+        id,
+        distributor,
+        state,
+        temperature_history,
+        terminal_log,
+        position,
+        host_prompt,
+        link_stats,
+        sd_files,
+        queue_confirm,
+        emergency_port,
+        firmware,
+        ..
+    } = printer.unwrap();
+
+    if request.method().eq(&Method::PATCH) && path.eq(routes::update_printer::PATH) {
+        if !permissions.edit_connection() {
+            return unauthorized_response();
+        }
+        return routes::update_printer::handler(request, id, printers).await;
+    }
+
+    if request.method().eq(&Method::DELETE) && path.eq(routes::delete_printer::PATH) {
+        if !permissions.edit_connection() {
+            return unauthorized_response();
+        }
+        let state = state.lock().await.state.clone();
+        return routes::delete_printer::handler(id, state, distributor, printers).await;
     }
 
     if request.method().eq(&Method::PUT) && path.eq(routes::create_connection::PATH) {
@@ -286,6 +343,7 @@ async fn handle_route(
         }
         return routes::create_connection::handler(
             request,
+            id,
             distributor,
             state.lock().await.clone(),
         )
@@ -300,26 +358,198 @@ async fn handle_route(
         return routes::disconnect_connection::handler(state, distributor).await;
     }
 
+    if request.method().eq(&Method::GET) && path.eq(routes::list_ports::PATH) {
+        if !permissions.edit_connection() {
+            return unauthorized_response();
+        }
+        return routes::list_ports::handler().await;
+    }
+
     if request.method().eq(&Method::POST) && path.eq(routes::reconnect_connection::PATH) {
         if !permissions.edit_connection() {
             return unauthorized_response();
         }
         let state = state.lock().await.state.clone();
-        return routes::reconnect_connection::handler(state, distributor).await;
+        return routes::reconnect_connection::handler(id, state, distributor).await;
     }
 
     if request.method().eq(&Method::PUT) && path.eq(routes::start_print::PATH) {
         if !permissions.print_state_edit() {
             return unauthorized_response();
         }
-        return routes::start_print::handler(request, distributor, state).await;
+        return routes::start_print::handler(request, distributor, state, permissions.username())
+            .await;
     }
 
     if request.method().eq(&Method::DELETE) && path.eq(routes::cancel_print::PATH) {
         if !permissions.print_state_edit() {
             return unauthorized_response();
         }
-        return routes::cancel_print::handler(state.lock().await.clone(), distributor);
+        let firmware = firmware.lock().await.clone();
+        return routes::cancel_print::handler(state.lock().await.clone(), distributor, firmware);
+    }
+
+    if request.method().eq(&Method::PATCH) && path.eq(routes::update_print::PATH) {
+        if !permissions.print_state_edit() {
+            return unauthorized_response();
+        }
+        let firmware = firmware.lock().await.clone();
+        return routes::update_print::handler(
+            request,
+            distributor,
+            state.lock().await.clone(),
+            firmware,
+        )
+        .await;
+    }
+
+    if request.method().eq(&Method::GET) && path.eq(routes::list_queue::PATH) {
+        return routes::list_queue::handler(id, *queue_confirm.lock().await).await;
+    }
+
+    if request.method().eq(&Method::POST) && path.eq(routes::add_queue_job::PATH) {
+        if !permissions.print_state_edit() || !permissions.file_access() {
+            return unauthorized_response();
+        }
+        return routes::add_queue_job::handler(request, id, distributor, permissions.username())
+            .await;
+    }
+
+    if request.method().eq(&Method::PATCH) && path.eq(routes::move_queue_job::PATH) {
+        if !permissions.print_state_edit() {
+            return unauthorized_response();
+        }
+        return routes::move_queue_job::handler(request, id, distributor).await;
+    }
+
+    if request.method().eq(&Method::DELETE) && path.eq(routes::remove_queue_job::PATH) {
+        if !permissions.print_state_edit() {
+            return unauthorized_response();
+        }
+        return routes::remove_queue_job::handler(request, id, distributor).await;
+    }
+
+    if request.method().eq(&Method::PUT) && path.eq(routes::start_queue::PATH) {
+        if !permissions.print_state_edit() {
+            return unauthorized_response();
+        }
+        let state = state.lock().await.state.clone();
+        return routes::start_queue::handler(id, state, distributor).await;
+    }
+
+    if request.method().eq(&Method::GET) && path.eq(routes::recovery::PATH) {
+        return routes::recovery::handler(id).await;
+    }
+
+    if request.method().eq(&Method::PUT) && path.eq(routes::recover_print::PATH) {
+        if !permissions.print_state_edit() {
+            return unauthorized_response();
+        }
+        let state = state.lock().await.state.clone();
+        return routes::recover_print::handler(id, state, distributor, permissions.username())
+            .await;
+    }
+
+    if request.method().eq(&Method::DELETE) && path.eq(routes::discard_recovery::PATH) {
+        if !permissions.print_state_edit() {
+            return unauthorized_response();
+        }
+        return routes::discard_recovery::handler(id).await;
+    }
+
+    if request.method().eq(&Method::GET) && path.eq(routes::temperature_history::PATH) {
+        return routes::temperature_history::handler(request, temperature_history).await;
+    }
+
+    if request.method().eq(&Method::POST) && path.eq(routes::emergency_stop::PATH) {
+        if !permissions.print_state_edit() {
+            return unauthorized_response();
+        }
+        let state = state.lock().await.state.clone();
+        return routes::emergency_stop::handler(
+            state,
+            distributor,
+            emergency_port,
+            permissions.username(),
+        )
+        .await;
+    }
+
+    if request.method().eq(&Method::GET) && path.eq(routes::position::PATH) {
+        return routes::position::handler(*position.lock().await);
+    }
+
+    if request.method().eq(&Method::GET) && path.eq(routes::link_stats::PATH) {
+        return routes::link_stats::handler(link_stats.lock().await.clone());
+    }
+
+    if request.method().eq(&Method::GET) && path.eq(routes::sd_files::PATH) {
+        if !permissions.file_access() {
+            return unauthorized_response();
+        }
+        return routes::sd_files::handler(sd_files.lock().await.clone());
+    }
+
+    if request.method().eq(&Method::POST) && path.eq(routes::refresh_sd_files::PATH) {
+        if !permissions.file_access() {
+            return unauthorized_response();
+        }
+        let state = state.lock().await.state.clone();
+        return routes::refresh_sd_files::handler(state, distributor);
+    }
+
+    if request.method().eq(&Method::PUT) && path.eq(routes::start_sd_print::PATH) {
+        if !permissions.print_state_edit() {
+            return unauthorized_response();
+        }
+        let state = state.lock().await.state.clone();
+        return routes::start_sd_print::handler(
+            request,
+            distributor,
+            state,
+            sd_files.lock().await.clone(),
+            permissions.username(),
+        )
+        .await;
+    }
+
+    if request.method().eq(&Method::PUT) && path.eq(routes::start_sd_upload::PATH) {
+        if !permissions.file_access() || !permissions.print_state_edit() {
+            return unauthorized_response();
+        }
+        let state = state.lock().await.state.clone();
+        return routes::start_sd_upload::handler(request, distributor, state).await;
+    }
+
+    if request.method().eq(&Method::DELETE) && path.eq(routes::cancel_sd_upload::PATH) {
+        if !permissions.file_access() || !permissions.print_state_edit() {
+            return unauthorized_response();
+        }
+        let state = state.lock().await.state.clone();
+        return routes::cancel_sd_upload::handler(state, distributor);
+    }
+
+    if request.method().eq(&Method::GET) && path.eq(routes::host_prompt::PATH) {
+        return routes::host_prompt::handler(host_prompt.lock().await.clone());
+    }
+
+    if request.method().eq(&Method::POST) && path.eq(routes::answer_host_prompt::PATH) {
+        if !permissions.print_state_edit() {
+            return unauthorized_response();
+        }
+        return routes::answer_host_prompt::handler(
+            request,
+            distributor,
+            host_prompt.lock().await.clone(),
+        )
+        .await;
+    }
+
+    if request.method().eq(&Method::GET) && path.eq(routes::terminal_log::PATH) {
+        if !permissions.terminal_read() {
+            return unauthorized_response();
+        }
+        return routes::terminal_log::handler(request, id, terminal_log).await;
     }
 
     if request.method().eq(&Method::POST) && path.eq(routes::terminal::PATH) {
@@ -368,15 +598,10 @@ async fn authenticate_route(request: &Request<Body>) -> Option<AuthPermissions>
     Based on the path, create a new response and add the appropriate corse headers.
 
     Arguments:
-    - request: Original hyper request.
+    - path: Normalized path of the request, without the printer prefix.
 
 */
-fn handle_option_requests(request: &Request<Body>) -> Response<Body> {
-    let path = normalize_url(&request);
-    if path.is_none() {
-        return bad_request_response();
-    }
-    let path = path.unwrap();
+fn handle_option_requests(path: &str) -> Response<Body> {
     if path == routes::login::PATH {
         return Response::builder()
             .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
@@ -466,6 +691,82 @@ fn handle_option_requests(request: &Request<Body>) -> Response<Body> {
             .body(Body::empty())
             .expect("Couldn't create a valid response");
     }
+    if path == routes::temperature_history::PATH {
+        return Response::builder()
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .header(
+                header::ACCESS_CONTROL_ALLOW_METHODS,
+                routes::temperature_history::METHODS,
+            )
+            .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "*")
+            .body(Body::empty())
+            .expect("Couldn't create a valid response");
+    }
+    if path == routes::emergency_stop::PATH {
+        return Response::builder()
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .header(
+                header::ACCESS_CONTROL_ALLOW_METHODS,
+                routes::emergency_stop::METHODS,
+            )
+            .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "*")
+            .body(Body::empty())
+            .expect("Couldn't create a valid response");
+    }
+    if path == routes::list_ports::PATH {
+        return Response::builder()
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .header(header::ACCESS_CONTROL_ALLOW_METHODS, routes::list_ports::METHODS)
+            .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "*")
+            .body(Body::empty())
+            .expect("Couldn't create a valid response");
+    }
+    if path == routes::position::PATH {
+        return Response::builder()
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .header(header::ACCESS_CONTROL_ALLOW_METHODS, routes::position::METHODS)
+            .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "*")
+            .body(Body::empty())
+            .expect("Couldn't create a valid response");
+    }
+    if path == routes::link_stats::PATH {
+        return Response::builder()
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .header(header::ACCESS_CONTROL_ALLOW_METHODS, routes::link_stats::METHODS)
+            .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "*")
+            .body(Body::empty())
+            .expect("Couldn't create a valid response");
+    }
+    if path == routes::sd_files::PATH {
+        return Response::builder()
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .header(header::ACCESS_CONTROL_ALLOW_METHODS, routes::sd_files::METHODS)
+            .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "*")
+            .body(Body::empty())
+            .expect("Couldn't create a valid response");
+    }
+    if path == routes::start_sd_upload::PATH {
+        return Response::builder()
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .header(
+                header::ACCESS_CONTROL_ALLOW_METHODS,
+                routes::start_sd_upload::METHODS,
+            )
+            .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "*")
+            .body(Body::empty())
+            .expect("Couldn't create a valid response");
+    }
+    if path == routes::host_prompt::PATH {
+        return Response::builder()
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .header(
+                header::ACCESS_CONTROL_ALLOW_METHODS,
+                routes::host_prompt::METHODS,
+            )
+            .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "*")
+            .body(Body::empty())
+            .expect("Couldn't create a valid response");
+    }
     if path == routes::terminal::PATH {
         return Response::builder()
             .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
@@ -477,9 +778,72 @@ fn handle_option_requests(request: &Request<Body>) -> Response<Body> {
             .body(Body::empty())
             .expect("Couldn't create a valid response");
     }
+    if path == routes::list_printers::PATH {
+        return Response::builder()
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .header(header::ACCESS_CONTROL_ALLOW_METHODS, routes::list_printers::METHODS)
+            .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "*")
+            .body(Body::empty())
+            .expect("Couldn't create a valid response");
+    }
+    if path == routes::list_queue::PATH {
+        return Response::builder()
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .header(header::ACCESS_CONTROL_ALLOW_METHODS, routes::list_queue::METHODS)
+            .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "*")
+            .body(Body::empty())
+            .expect("Couldn't create a valid response");
+    }
+    if path == routes::recovery::PATH {
+        return Response::builder()
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .header(header::ACCESS_CONTROL_ALLOW_METHODS, routes::recovery::METHODS)
+            .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "*")
+            .body(Body::empty())
+            .expect("Couldn't create a valid response");
+    }
+    if path == routes::list_history::PATH {
+        return Response::builder()
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .header(header::ACCESS_CONTROL_ALLOW_METHODS, routes::list_history::METHODS)
+            .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "*")
+            .body(Body::empty())
+            .expect("Couldn't create a valid response");
+    }
+    if path == routes::update_printer::PATH {
+        return Response::builder()
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .header(
+                header::ACCESS_CONTROL_ALLOW_METHODS,
+                routes::update_printer::METHODS,
+            )
+            .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "*")
+            .body(Body::empty())
+            .expect("Couldn't create a valid response");
+    }
     return not_found_response();
 }
 
+/*
+    Split the printer id of a path of a printer route, /api/printers/2/print => (2, /api/print).
+    Paths without a printer id belong to the default printer.
+    Returns None when the id isn't a number.
+*/
+fn split_printer_path(path: &str) -> Option<(u32, String)> {
+    if !path.starts_with("/api/printers/") {
+        return Some((DEFAULT_PRINTER, path.to_string()));
+    }
+    let rest = &path["/api/printers/".len()..];
+    let (id, route) = match rest.find('/') {
+        Some(index) => (&rest[..index], rest[index..].to_string()),
+        None => (rest, "/printer".to_string()),
+    };
+    return match id.parse::<u32>() {
+        Ok(id) => Some((id, format!("/api{}", route))),
+        Err(_) => None,
+    };
+}
+
 fn normalize_url(request: &Request<Body>) -> Option<String> {
     let mut path = request.uri().path().to_string();
     if !path.chars().all(|x| x.is_ascii()) {
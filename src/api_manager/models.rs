@@ -1,3 +1,5 @@
+use std::{collections::VecDeque, io};
+
 use chrono::{DateTime, Utc};
 use crossbeam_channel::Sender;
 use serde::{Deserialize, Serialize};
@@ -5,7 +7,12 @@ use serde::{Deserialize, Serialize};
 use sqlx::{sqlite::SqliteRow, FromRow, Row};
 use uuid::Uuid;
 
-use crate::parser::TempInfo;
+use crate::{
+    binary_transfer::BinaryTransfer,
+    parser::{Position, TempInfo},
+    print_file::PrintFile,
+    print_recovery::FileState,
+};
 
 pub fn send(sender: &Sender<EventType>, data: EventType) {
     let result = sender.send(data);
@@ -232,6 +239,9 @@ pub enum BridgeState {
     PREPARING = 5,
     PRINTING = 6,
     FINISHING = 7,
+    PAUSED = 8,
+    WAITING = 9,
+    UPLOADING = 10,
 }
 
 #[derive(Debug)]
@@ -247,13 +257,48 @@ pub enum EventType {
     KillBridge,
     PrintEnd,
     PrintStart(PrintInfo),
+    // start printing a file of the SD card, file is the name the firmware uses & filename the one shown.
+    SdPrintStart {
+        file: String,
+        filename: String,
+        user: Option<String>,
+    },
+    SdFiles(Vec<SdFile>),
+    // copy a file to the SD card, the print info has the SD upload set.
+    SdUploadStart(PrintInfo),
+    PrintPause,
+    PrintResume,
+    EmergencyStop {
+        username: String,
+    },
     TempUpdate {
         tools: Vec<TempInfo>,
+        active_tool: Option<TempInfo>,
         bed: Option<TempInfo>,
         chamber: Option<TempInfo>,
+        probe: Option<TempInfo>,
+        redundant: Option<TempInfo>,
+        ambient: Option<TempInfo>,
     },
+    PositionUpdate(Position),
+    LinkStats(LinkStats),
+    // message for the user that doesn't change the state, reason is a camelCase identifier for the clients.
+    Notification {
+        reason: String,
+        message: String,
+    },
+    // None once the prompt is closed.
+    HostPrompt(Option<HostPrompt>),
     IncomingTerminalMessage(String),
     OutGoingTerminalMessage(Message),
+    // raw data for the printer, a packet of the binary file transfer.
+    OutGoingPacket(Vec<u8>),
+    // the printer is removed from the registry, stops its manager.
+    RemovePrinter,
+    // the print queue changed, the manager sends it to the clients.
+    QueueUpdate,
+    // the next job of the queue waits until the user confirms the bed is clear.
+    QueueConfirm,
 }
 
 impl std::fmt::Display for EventType {
@@ -281,101 +326,548 @@ impl std::fmt::Display for EventType {
             EventType::PrintStart(info) => {
                 write!(f, "Start print event {}", info.filename)
             }
-            EventType::TempUpdate {
-                tools: _,
-                bed: _,
-                chamber: _,
-            } => {
+            EventType::SdPrintStart { file, .. } => {
+                write!(f, "Start SD print event {}", file)
+            }
+            EventType::SdFiles(files) => {
+                write!(f, "SD files event ({} files)", files.len())
+            }
+            EventType::SdUploadStart(info) => match &info.upload {
+                Some(upload) => write!(f, "Start SD upload event {} => {}", info.filename, upload.target),
+                None => write!(f, "Start SD upload event {}", info.filename),
+            },
+            EventType::PrintPause => {
+                write!(f, "Pause print event")
+            }
+            EventType::PrintResume => {
+                write!(f, "Resume print event")
+            }
+            EventType::EmergencyStop { username } => {
+                write!(f, "Emergency stop event by {}", username)
+            }
+            EventType::TempUpdate { .. } => {
                 write!(f, "Temp update event ")
             }
+            EventType::PositionUpdate(position) => {
+                write!(
+                    f,
+                    "Position update event X:{} Y:{} Z:{} E:{}",
+                    position.x, position.y, position.z, position.e
+                )
+            }
+            EventType::LinkStats(stats) => {
+                write!(f, "Link stats event {} resends", stats.resends)
+            }
+            EventType::Notification { reason, message } => {
+                write!(f, "Notification event ({}) | {}", reason, message)
+            }
+            EventType::HostPrompt(prompt) => match prompt {
+                Some(prompt) => write!(f, "Host prompt event | {}", prompt.message),
+                None => write!(f, "Host prompt closed event"),
+            },
             EventType::IncomingTerminalMessage(message) => {
                 write!(f, "Incoming terminal message event | {}", message)
             }
             EventType::OutGoingTerminalMessage(message) => {
                 write!(f, "Outgoing terminal message event | {:?}", message)
             }
+            EventType::OutGoingPacket(packet) => {
+                write!(f, "Outgoing packet event ({} bytes)", packet.len())
+            }
+            EventType::RemovePrinter => {
+                write!(f, "Remove printer event")
+            }
+            EventType::QueueUpdate => {
+                write!(f, "Queue update event")
+            }
+            EventType::QueueConfirm => {
+                write!(f, "Queue confirm event")
+            }
         }
     }
 }
 
+/// Get the target temperatures of a temperature report, as (name, target) pairs, the bed is named B.
+pub fn heater_targets(tools: &Vec<TempInfo>, bed: &Option<TempInfo>) -> Vec<(String, f64)> {
+    let mut targets = vec![];
+    for tool in tools {
+        targets.push((tool.name().to_string(), tool.target_temp()));
+    }
+    if let Some(bed) = bed {
+        targets.push(("B".to_string(), bed.target_temp()));
+    }
+    return targets;
+}
+
 #[derive(Debug)]
 pub struct PrintInfo {
     pub filename: String,
-    pub filesize: usize,
-    data_sent: u64,
-    // pub file_reader: Option<Lines<BufReader<File>>>,
-    pub gcode: Vec<String>,
+    file: PrintFile,
     pub start: DateTime<Utc>,
     pub end: Option<DateTime<Utc>>,
+    // last line number acknowledged by the firmware.
     line_number: usize,
+    last_sent: usize,
+    // lines that are sent, but not acknowledged yet & their size in bytes (as sent, with checksum & newline).
+    sent: VecDeque<(Line, usize)>,
+    // remaining print time according to the slicer (in seconds) & when the line of that hint was acknowledged.
+    time_hint: Option<(f64, DateTime<Utc>)>,
+    // positioning, extrusion mode & feedrate set by the acknowledged lines, restored when resuming.
+    pub file_state: FileState,
+    // configured amount of lines to keep in flight, None when the buffer reported by the firmware is used.
+    buffer_size: Option<usize>,
+    reported_buffer: usize,
+    // size of the serial receive buffer of the firmware in bytes, 0 when the bytes in flight aren't limited.
+    rx_buffer: usize,
+    // line number of the last resend request & amount of duplicate requests for it that are ignored.
+    rewind: Option<(usize, usize)>,
     resend_amount: usize,
+    checksum_errors: usize,
+    line_number_errors: usize,
+    resend_policy: ResendPolicy,
+    // resend ratio (in %) at which the resend policy is applied.
+    resend_threshold: f64,
+    resend_warned: bool,
+    heater_targets: Vec<(String, f64)>,
+    pub pause: Option<PauseInfo>,
+    // reason the firmware waits for the user & the line that has to be acknowledged before it continues.
+    waiting: Option<(WaitReason, usize)>,
+    // set when the file is copied to the SD card instead of printed.
+    pub upload: Option<SdUpload>,
+    // commands of the start or end script, sent one per ok before the print continues (or ends).
+    pub script: VecDeque<String>,
+    // the print is finished or canceled, only the end script is sent anymore.
+    pub ending: bool,
+    // every line was acknowledged when the print started ending, it wasn't canceled.
+    pub finished: bool,
+    // line the print continues after, 0 unless a print is recovered.
+    start_line: usize,
+    // height of the nozzle in the last position report.
+    pub last_z: Option<f64>,
+    // user that started the print, kept in the print history.
+    pub user: Option<String>,
+    // job of the print queue this print is started from, it's removed from the queue once the print started.
+    pub queue_job: Option<u32>,
+}
+
+/*
+    State kept while a file is written to the SD card (M28 / M29), the lines are streamed without being executed.
+
+    target: Name of the file on the SD card.
+    opened: The firmware confirmed it's writing to the file, no lines are sent before that.
+    complete: Every line has been acknowledged, M29 closes the file.
+    binary: Set when the file is copied with the binary file transfer protocol instead of as lines.
+*/
+#[derive(Debug)]
+pub struct SdUpload {
+    pub target: String,
+    pub opened: bool,
+    pub complete: bool,
+    pub binary: Option<BinaryTransfer>,
+}
+
+/*
+    State kept while a print is paused.
+
+    commands: G-code that still has to be sent before the pause (or resume) is complete,
+              one command is sent for every ok received.
+    position: Toolhead position reported by M114 right after pausing.
+    resuming: Set once a resume has been requested, the print continues after the last command.
+    by_printer: The firmware paused by itself (//action:paused) and restores its own state when resuming.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct PauseInfo {
+    pub commands: VecDeque<String>,
+    pub position: Option<Position>,
+    pub resuming: bool,
+    pub by_printer: bool,
 }
 
 impl PrintInfo {
-    pub fn new(
-        filename: String,
-        filesize: usize,
-        // file_reader: Option<Lines<BufReader<File>>>,
-        gcode: Vec<String>,
-        start: DateTime<Utc>,
-    ) -> Self {
+    pub fn new(filename: String, file: PrintFile, start: DateTime<Utc>) -> Self {
         Self {
             filename,
-            filesize,
-            data_sent: 0,
-            gcode,
-            // file_reader,
+            file,
             start,
             end: None,
             line_number: 0,
+            last_sent: 0,
+            sent: VecDeque::new(),
+            time_hint: None,
+            file_state: FileState::new(),
+            buffer_size: None,
+            reported_buffer: 0,
+            rx_buffer: 0,
+            rewind: None,
             resend_amount: 0,
+            checksum_errors: 0,
+            line_number_errors: 0,
+            resend_policy: ResendPolicy::Abort,
+            resend_threshold: 10.0,
+            resend_warned: false,
+            heater_targets: vec![],
+            pause: None,
+            waiting: None,
+            upload: None,
+            script: VecDeque::new(),
+            ending: false,
+            finished: false,
+            start_line: 0,
+            last_z: None,
+            user: None,
+            queue_job: None,
         }
     }
+
+    /// Copy the file to the SD card as target instead of printing it.
+    pub fn set_upload(&mut self, target: String) {
+        self.upload = Some(SdUpload {
+            target,
+            opened: false,
+            complete: false,
+            binary: None,
+        });
+    }
+
+    /// State of the bridge while the file is streamed, UPLOADING for an SD upload.
+    pub fn streaming_state(&self) -> BridgeState {
+        if self.upload.is_some() {
+            return BridgeState::UPLOADING;
+        }
+        return BridgeState::PRINTING;
+    }
     pub fn report_resend(&mut self) {
         self.resend_amount += 1;
     }
     pub fn get_resend_ratio(&self) -> f32 {
-        return (self.resend_amount as f32 / self.gcode.len() as f32) * 100.0;
+        if self.get_line_amount() == 0 {
+            return 0.0;
+        }
+        return (self.resend_amount as f32 / self.get_line_amount() as f32) * 100.0;
     }
     pub fn get_resend_amount(&self) -> &usize {
         return &self.resend_amount;
     }
-    pub fn get_line_amount(&self) -> usize {
-        return self.gcode.len();
+    pub fn report_link_error(&mut self, error: LinkError) {
+        match error {
+            LinkError::Checksum => self.checksum_errors += 1,
+            LinkError::LineNumber => self.line_number_errors += 1,
+        }
+    }
+    pub fn set_resend_policy(&mut self, policy: ResendPolicy, threshold: f64) {
+        self.resend_policy = policy;
+        self.resend_threshold = threshold;
     }
-    pub fn get_line_by_index(&self, index: usize) -> Option<Line> {
-        let content = self.get_line_content_by_index(index);
-        if content.is_none() {
+    /// Get the resend ratio (in %) at which the resend policy is applied.
+    pub fn resend_threshold(&self) -> f64 {
+        return self.resend_threshold;
+    }
+
+    /*
+        Check the resend ratio against the configured threshold.
+        Returns the policy to apply once it's exceeded, a warning is only given once per print.
+        The first RESEND_RATIO_MIN_LINES lines are skipped, as a single resend would exceed any threshold there.
+    */
+    pub fn resend_threshold_exceeded(&mut self) -> Option<ResendPolicy> {
+        if self.get_line_amount() < RESEND_RATIO_MIN_LINES
+            || (self.get_resend_ratio() as f64) <= self.resend_threshold
+        {
             return None;
         }
-        return Some(Line::new(content.unwrap().clone(), index));
+        match self.resend_policy {
+            ResendPolicy::Abort => Some(ResendPolicy::Abort),
+            ResendPolicy::Warn if !self.resend_warned => {
+                self.resend_warned = true;
+                Some(ResendPolicy::Warn)
+            }
+            _ => None,
+        }
     }
-    fn get_line_content_by_index(&self, index: usize) -> Option<&String> {
-        return self.gcode.get(index);
+
+    pub fn link_stats(&self) -> LinkStats {
+        return LinkStats {
+            filename: self.filename.to_string(),
+            lines: self.get_line_amount(),
+            resends: *self.get_resend_amount(),
+            resend_ratio: self.get_resend_ratio(),
+            checksum_errors: self.checksum_errors,
+            line_number_errors: self.line_number_errors,
+        };
+    }
+    /// Get the amount of lines read from the file so far.
+    pub fn get_line_amount(&self) -> usize {
+        return self.file.line_count();
+    }
+    /// Get a line of the file, Ok(None) once the index is past the end of the file.
+    pub fn get_line_by_index(&mut self, index: usize) -> io::Result<Option<Line>> {
+        let content = self.file.get_line(index)?;
+        return Ok(content.map(|content| Line::new(content, index)));
     }
 
-    pub fn set_line_number(&mut self, line_number: usize) {
-        if line_number > self.gcode.len() + 1 {
-            panic!("Cannot set line number out of bounds");
+    pub fn progress(&self) -> f64 {
+        if let Some(transfer) = self.upload.as_ref().and_then(|upload| upload.binary.as_ref()) {
+            return transfer.progress();
+        }
+        if self.file.size() == 0 {
+            return 0.0;
         }
+        return (self.file.offset() as f64 / self.file.size() as f64) * 100.0;
+    }
+
+    pub fn line_number(&mut self) -> usize {
+        return self.line_number;
+    }
+
+    /// Get the line number of the last line sent to the printer.
+    pub fn last_sent(&self) -> usize {
+        return self.last_sent;
+    }
+
+    /// Register a line as sent, bytes is its size as written to the printer.
+    pub fn mark_sent(&mut self, line: Line, bytes: usize) {
+        self.last_sent = line.line_number;
+        self.sent.push_back((line, bytes));
+    }
+
+    /// Forget the lines in flight, they're sent again starting after the last acknowledged line.
+    pub fn discard_in_flight(&mut self) {
+        self.last_sent = self.line_number;
+        self.sent.clear();
+    }
+
+    /// Register an ok of the firmware for the given line (and every line before it).
+    pub fn acknowledge(&mut self, line_number: usize) {
+        if line_number > self.line_number {
+            self.line_number = line_number;
+        }
+        if line_number > self.last_sent {
+            self.last_sent = line_number;
+        }
+        while self
+            .sent
+            .front()
+            .map_or(false, |(line, _)| line.line_number <= line_number)
+        {
+            let (line, _) = self.sent.pop_front().unwrap();
+            self.file_state.track(line.content());
+        }
+        if let Some(remaining) = self.file.take_time_hint(line_number) {
+            self.time_hint = Some((remaining, Utc::now()));
+        }
+        // once the resent line is acknowledged, every line that was in flight before it has been rejected.
+        // later resend requests for the same line are real ones.
+        if self.rewind.map_or(false, |(line, _)| line_number >= line) {
+            self.rewind = None;
+        }
+        if let Some((_, until)) = self.waiting {
+            if line_number >= until {
+                self.waiting = None;
+            }
+        }
+    }
+
+    /// Continue the print after the given line, for a print that's recovered.
+    pub fn resume_from(&mut self, line_number: usize) {
+        self.start_line = line_number;
         self.line_number = line_number;
+        self.discard_in_flight();
     }
-    pub fn progress(&self) -> f64 {
-        if self.filesize == 0 {
-            return 0.0;
+
+    /// Get the line the print continues after, 0 unless the print is recovered.
+    pub fn start_line(&self) -> usize {
+        return self.start_line;
+    }
+
+    /// Check if every line of the file is sent and acknowledged, a file that can't be read isn't finished.
+    pub fn is_finished(&mut self) -> bool {
+        return self.in_flight() == 0
+            && matches!(self.get_line_by_index(self.last_sent + 1), Ok(None));
+    }
+
+    /// Get the amount of lines that are sent, but not acknowledged yet.
+    pub fn in_flight(&self) -> usize {
+        return self.last_sent.saturating_sub(self.line_number);
+    }
+
+    /// Get the amount of bytes that are sent, but not acknowledged yet.
+    pub fn in_flight_bytes(&self) -> usize {
+        return self.sent.iter().map(|(_, bytes)| bytes).sum();
+    }
+
+    /*
+        Check if a line of the given size (in bytes) can be sent without overflowing the firmware's buffers.
+        Both the amount of lines (see window) and the amount of bytes in the serial receive buffer are limited,
+        a single line is always allowed when nothing is in flight.
+    */
+    pub fn can_send(&self, bytes: usize) -> bool {
+        if self.in_flight() == 0 {
+            return true;
         }
-        return (self.data_sent as f64 / self.filesize as f64) * 100.0;
+        return self.in_flight() < self.window()
+            && (self.rx_buffer == 0 || self.in_flight_bytes() + bytes <= self.rx_buffer);
     }
 
-    pub fn add_bytes_sent(&mut self, bytes: u64) {
-        if self.filesize == 0 {
-            return;
+    /// Set the size of the firmware's serial receive buffer in bytes, 0 doesn't limit the bytes in flight.
+    pub fn set_rx_buffer(&mut self, bytes: usize) {
+        self.rx_buffer = bytes;
+    }
+
+    /*
+        Get the amount of lines that can be in flight at once.
+        Uses the configured buffer size, or the largest free buffer space reported by the firmware (ADVANCED_OK).
+        Falls back to a single line, which waits for the ok of every line before sending the next one.
+    */
+    pub fn window(&self) -> usize {
+        return self
+            .buffer_size
+            .unwrap_or(self.reported_buffer)
+            .max(1);
+    }
+
+    /// Use a fixed amount of lines in flight, 0 uses the buffer reported by the firmware.
+    pub fn set_buffer_size(&mut self, buffer_size: usize) {
+        self.buffer_size = if buffer_size > 0 {
+            Some(buffer_size)
+        } else {
+            None
+        };
+    }
+
+    /*
+        Register the free space in the serial buffer of the firmware, as reported by ok N.. P.. B...
+        The largest value is the size of the buffer, as the firmware reports it right after taking a line from it.
+    */
+    pub fn report_buffer(&mut self, free: usize) {
+        if free > self.reported_buffer {
+            self.reported_buffer = free;
         }
-        self.data_sent = self.data_sent + bytes;
     }
 
-    pub fn line_number(&mut self) -> usize {
-        return self.line_number;
+    /*
+        Continue sending at the line the firmware requested a resend for.
+        Every line that was still in flight gets rejected by the firmware with a resend request for the same line,
+        returns false for those duplicate requests, as the lines are already being sent again.
+    */
+    pub fn rewind(&mut self, line_number: usize) -> bool {
+        if let Some((line, ignored)) = self.rewind.as_mut() {
+            if *line == line_number && *ignored > 0 {
+                *ignored -= 1;
+                return false;
+            }
+        }
+        self.rewind = Some((line_number, self.last_sent.saturating_sub(line_number)));
+        self.line_number = line_number.saturating_sub(1);
+        self.discard_in_flight();
+        return true;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        return self.pause.is_some();
+    }
+
+    /// Get the reason the firmware is waiting for the user, if it is.
+    pub fn waiting(&self) -> Option<WaitReason> {
+        return self.waiting.map(|(reason, _)| reason);
+    }
+
+    /*
+        Mark the print as waiting for the user, returns false when it already was (or when it's paused).
+        The wait is over once the command the firmware waits in is acknowledged:
+        FilamentChange: the M600 line that was sent.
+        User: the line after the last acknowledged one, the firmware reported it's blocked in it.
+        Runout: the firmware injects M600 after the command it's currently running, so one line later.
+    */
+    pub fn wait_for_user(&mut self, reason: WaitReason) -> bool {
+        if self.waiting.is_some() || self.is_paused() {
+            return false;
+        }
+        let until = match reason {
+            WaitReason::FilamentChange => match self.pending_filament_change() {
+                Some(line_number) => line_number,
+                None => self.line_number + 1,
+            },
+            WaitReason::User => self.line_number + 1,
+            WaitReason::Runout => self.line_number + 2,
+        };
+        self.waiting = Some((reason, until));
+        return true;
+    }
+
+    /// Called when the firmware reports it's blocked waiting for the user, so the wait ends with the next line.
+    pub fn waiting_confirmed(&mut self) {
+        let next = self.line_number + 1;
+        if let Some((_, until)) = self.waiting.as_mut() {
+            if *until > next {
+                *until = next;
+            }
+        }
+    }
+
+    pub fn stop_waiting(&mut self) {
+        self.waiting = None;
+    }
+
+    /// Get the line number of an M600 that is sent, but not acknowledged yet.
+    pub fn pending_filament_change(&self) -> Option<usize> {
+        return self
+            .sent
+            .iter()
+            .map(|(line, _)| line)
+            .find(|line| {
+                let command = line.content().split_whitespace().next().unwrap_or("");
+                command.eq_ignore_ascii_case("M600")
+            })
+            .map(|line| line.line_number);
+    }
+
+    /// Store the target temperatures of the heaters, used to reheat after a pause (see heater_targets).
+    pub fn set_heater_targets(&mut self, targets: Vec<(String, f64)>) {
+        self.heater_targets = targets;
+    }
+
+    /// Get a reference to the heater targets, as (name, target) pairs.
+    pub fn heater_targets(&self) -> &Vec<(String, f64)> {
+        &self.heater_targets
+    }
+
+    /*
+        Estimate when the print ends, using the print time hints of the slicer when the file contains them.
+        The remaining time of a hint counts from the moment its line was acknowledged.
+        Otherwise the time spent so far is extrapolated using the progress,
+        which needs some progress before it's meaningful.
+    */
+    pub fn estimate_end(&self) -> Option<DateTime<Utc>> {
+        let now = Utc::now();
+        // the print time of the slicer says nothing about how long writing it to the SD card takes.
+        if let (Some((remaining, acknowledged)), None) = (self.time_hint, &self.upload) {
+            return Some(acknowledged + chrono::Duration::seconds(remaining as i64));
+        }
+        let progress = self.progress();
+        if progress < 1.0 {
+            return None;
+        }
+        let elapsed = (now - self.start).num_milliseconds() as f64;
+        let remaining = elapsed * (100.0 - progress) / progress;
+        return Some(now + chrono::Duration::milliseconds(remaining as i64));
+    }
+
+    pub fn state_description(&self) -> StateDescription {
+        if let Some(upload) = &self.upload {
+            return StateDescription::Upload {
+                filename: self.filename.to_string(),
+                target: upload.target.clone(),
+                progress: self.progress(),
+                start: self.start,
+                end: self.end,
+            };
+        }
+        return StateDescription::Print {
+            filename: self.filename.to_string(),
+            progress: self.progress(),
+            start: self.start,
+            end: self.end,
+            waiting: self.waiting(),
+        };
     }
 }
 
@@ -414,6 +906,8 @@ pub struct StateWrapper {
 pub enum StateDescription {
     None,
     Capability {
+        firmware_name: String,
+        firmware_version: Option<String>,
         capabilities: Vec<String>,
     },
     Error {
@@ -424,6 +918,14 @@ pub enum StateDescription {
         progress: f64,
         start: DateTime<Utc>,
         end: Option<DateTime<Utc>>,
+        waiting: Option<WaitReason>,
+    },
+    Upload {
+        filename: String,
+        target: String,
+        progress: f64,
+        start: DateTime<Utc>,
+        end: Option<DateTime<Utc>>,
     },
 }
 
@@ -463,3 +965,299 @@ impl std::fmt::Display for BridgeAction {
         }
     }
 }
+
+/*
+    Host action command sent by the firmware, like //action:pause.
+
+    Pause / Resume: The printer asks the host to pause or resume the print.
+    Paused / Resumed: The printer paused or resumed by itself, the host should only stop or continue sending.
+    Runout: The printer paused by itself because the filament ran out, and waits for the user to load new filament.
+    Cancel: The printer asks the host to cancel the print.
+    Prompt*: Build a dialog for the user, answered with M876 S<index of the choice>.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum HostAction {
+    Pause,
+    Paused,
+    Runout,
+    Resume,
+    Resumed,
+    Cancel,
+    PromptBegin(String),
+    PromptChoice(String),
+    PromptShow,
+    PromptEnd,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct HostPrompt {
+    pub message: String,
+    pub choices: Vec<String>,
+}
+
+/*
+    Reason the firmware stopped the print until the user confirms on the printer or with M108.
+
+    FilamentChange: An M600 of the print file is being run.
+    Runout: The filament runout sensor triggered (//action:paused filament_runout).
+    User: Any other wait, like M0 / M1.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WaitReason {
+    FilamentChange,
+    Runout,
+    User,
+}
+
+impl WaitReason {
+    pub fn message(&self) -> &str {
+        match self {
+            WaitReason::FilamentChange => "Filament change: swap the filament and confirm to continue printing.",
+            WaitReason::Runout => "Filament ran out: load new filament and confirm to continue printing.",
+            WaitReason::User => "The printer is waiting for the user, confirm to continue printing.",
+        }
+    }
+}
+
+// Lines that have to be sent before the resend ratio is checked.
+pub const RESEND_RATIO_MIN_LINES: usize = 100;
+
+/*
+    What to do once the resend ratio of a print exceeds the threshold (S_resendPolicy & F_resendThreshold).
+
+    Abort: Stop the print and go to the ERRORED state.
+    Warn: Notify the clients once and keep printing.
+    Ignore: Keep printing.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResendPolicy {
+    Abort,
+    Warn,
+    Ignore,
+}
+
+impl ResendPolicy {
+    pub fn from_setting(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "abort" => Some(ResendPolicy::Abort),
+            "warn" => Some(ResendPolicy::Warn),
+            "ignore" => Some(ResendPolicy::Ignore),
+            _ => None,
+        }
+    }
+}
+
+/*
+    Transmission error reported by the firmware, these are followed by a resend request.
+
+    Checksum: The line arrived corrupted (or without a checksum).
+    LineNumber: A line went missing, the firmware received another line number than it expected.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkError {
+    Checksum,
+    LineNumber,
+}
+
+/*
+    Quality of the connection during a print, kept after the print ended until the next one starts.
+    lines: Lines read from the print file so far, resend_ratio is a percentage of those.
+*/
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkStats {
+    pub filename: String,
+    pub lines: usize,
+    pub resends: usize,
+    pub resend_ratio: f32,
+    pub checksum_errors: usize,
+    pub line_number_errors: usize,
+}
+
+/*
+    File on the SD card of the printer, as listed by M20 L.
+    name: Name (8.3) the firmware uses for the file, including the folders.
+    long_name: Full name of the file, when the firmware reports it.
+*/
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SdFile {
+    pub name: String,
+    pub size: Option<u64>,
+    pub long_name: Option<String>,
+}
+
+/*
+    Print that runs from the SD card of the printer, the firmware reports the progress in bytes (M27).
+
+    file: Name (8.3) the firmware uses for the file.
+    filename: Name shown to the user.
+    auto_report: The firmware reports the progress by itself (M27 S<interval>), otherwise it's polled.
+    user: User that started the print, kept in the print history.
+*/
+#[derive(Debug, Clone)]
+pub struct SdPrint {
+    pub file: String,
+    pub filename: String,
+    pub start: DateTime<Utc>,
+    pub end: Option<DateTime<Utc>>,
+    position: u64,
+    size: u64,
+    pub paused: bool,
+    pub auto_report: bool,
+    pub user: Option<String>,
+}
+
+impl SdPrint {
+    pub fn new(file: String, filename: String, auto_report: bool) -> Self {
+        Self {
+            file,
+            filename,
+            start: Utc::now(),
+            end: None,
+            position: 0,
+            size: 0,
+            paused: false,
+            auto_report,
+            user: None,
+        }
+    }
+
+    pub fn set_size(&mut self, size: u64) {
+        self.size = size;
+    }
+
+    /// Store the progress reported by the firmware, in bytes.
+    pub fn set_position(&mut self, position: u64, size: u64) {
+        self.position = position;
+        self.size = size;
+    }
+
+    pub fn progress(&self) -> f64 {
+        if self.size == 0 {
+            return 0.0;
+        }
+        return self.position as f64 / self.size as f64 * 100.0;
+    }
+
+    /// Estimate when the print ends by extrapolating the time spent so far, after at least 1% progress.
+    pub fn estimate_end(&self) -> Option<DateTime<Utc>> {
+        let progress = self.progress();
+        if progress < 1.0 {
+            return None;
+        }
+        let now = Utc::now();
+        let elapsed = (now - self.start).num_milliseconds() as f64;
+        let remaining = elapsed * (100.0 - progress) / progress;
+        return Some(now + chrono::Duration::milliseconds(remaining as i64));
+    }
+
+    pub fn state_description(&self) -> StateDescription {
+        return StateDescription::Print {
+            filename: self.filename.to_string(),
+            progress: self.progress(),
+            start: self.start,
+            end: self.end,
+            waiting: None,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a print of the given amount of moves, in a file of its own.
+    fn print_info(name: &str, lines: usize) -> PrintInfo {
+        let filename = format!("server_v2-{}-{}.gcode", std::process::id(), name);
+        let path = std::env::temp_dir().join(filename);
+        let content: String = (1..=lines).map(|line| format!("G1 X{}\n", line)).collect();
+        std::fs::write(&path, content).unwrap();
+        let file = PrintFile::open(&path).unwrap();
+        return PrintInfo::new(name.to_string(), file, Utc::now());
+    }
+
+    fn send_lines(info: &mut PrintInfo, lines: std::ops::RangeInclusive<usize>, bytes: usize) {
+        for index in lines {
+            let line = info.get_line_by_index(index).unwrap().unwrap();
+            info.mark_sent(line, bytes);
+        }
+    }
+
+    #[test]
+    fn rewinds_once_for_the_lines_in_flight() {
+        let mut info = print_info("rewind", 10);
+        info.set_buffer_size(4);
+        send_lines(&mut info, 1..=4, 10);
+        info.acknowledge(1);
+        assert_eq!(info.in_flight(), 3);
+
+        // line 2 is rejected, lines 3 & 4 get rejected with a request for line 2 as well.
+        assert!(info.rewind(2));
+        assert_eq!(info.line_number(), 1);
+        assert_eq!(info.in_flight(), 0);
+        assert_eq!(info.in_flight_bytes(), 0);
+        send_lines(&mut info, 2..=4, 10);
+        assert!(!info.rewind(2));
+        assert!(!info.rewind(2));
+        assert_eq!(info.in_flight(), 3);
+
+        // once the duplicates are used up, a request for the same line is a real one.
+        assert!(info.rewind(2));
+        assert_eq!(info.in_flight(), 0);
+        send_lines(&mut info, 2..=4, 10);
+        info.acknowledge(4);
+        assert_eq!(info.line_number(), 4);
+        assert_eq!(info.in_flight(), 0);
+
+        // the resent line was acknowledged, so the duplicates left over don't hide a new request.
+        send_lines(&mut info, 5..=6, 10);
+        assert!(info.rewind(5));
+        assert_eq!(info.last_sent(), 4);
+    }
+
+    #[test]
+    fn limits_the_bytes_in_flight() {
+        let mut info = print_info("rx_buffer", 10);
+        info.set_buffer_size(8);
+        info.set_rx_buffer(30);
+        assert!(info.can_send(100));
+
+        send_lines(&mut info, 1..=2, 12);
+        assert_eq!(info.in_flight_bytes(), 24);
+        assert!(info.can_send(6));
+        assert!(!info.can_send(7));
+
+        info.acknowledge(1);
+        assert_eq!(info.in_flight_bytes(), 12);
+        assert!(info.can_send(18));
+
+        info.set_rx_buffer(0);
+        assert!(info.can_send(1_000));
+    }
+
+    #[test]
+    fn limits_the_lines_in_flight() {
+        let mut info = print_info("window", 10);
+        assert_eq!(info.window(), 1);
+        send_lines(&mut info, 1..=1, 10);
+        assert!(!info.can_send(10));
+
+        info.report_buffer(3);
+        assert_eq!(info.window(), 3);
+        // less free space is reported while lines wait in the buffer, the largest value is its size.
+        info.report_buffer(1);
+        assert_eq!(info.window(), 3);
+        info.report_buffer(7);
+        assert_eq!(info.window(), 7);
+
+        info.set_buffer_size(2);
+        assert_eq!(info.window(), 2);
+        send_lines(&mut info, 2..=2, 10);
+        assert!(!info.can_send(10));
+        info.set_buffer_size(0);
+        assert_eq!(info.window(), 7);
+        assert!(info.can_send(10));
+    }
+}
@@ -53,6 +53,16 @@ pub fn forbidden_response() -> Response<Body> {
         .expect("Failed to construct a valid response");
 }
 
+pub fn not_implemented_response() -> Response<Body> {
+    return Response::builder()
+        .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(ACCESS_CONTROL_ALLOW_METHODS, "*")
+        .header(header::CONTENT_TYPE, "text/plain")
+        .status(StatusCode::NOT_IMPLEMENTED)
+        .body(Body::from("Not Implemented"))
+        .expect("Failed to construct a valid response");
+}
+
 pub fn too_large_response() -> Response<Body> {
     return Response::builder()
         .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
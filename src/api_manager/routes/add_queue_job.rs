@@ -0,0 +1,86 @@
+/*
+    Add a stored file to the end of the print queue of a printer.
+    Responds with the queued job, its id is used to move or remove it.
+
+    POST /api/queue
+
+    Body: (json)
+        printName: String
+
+
+    Permission: print_state.edit
+    State: -
+*/
+
+use std::path::Path;
+
+use crossbeam_channel::Sender;
+use hyper::{body, header, Body, Request, Response};
+use serde_json::{json, Value};
+
+use crate::{
+    api_manager::{
+        models::{send, EventType},
+        responses::{bad_request_response, not_found_response, server_error_response},
+    },
+    print_queue,
+};
+
+pub const PATH: &str = "/api/queue";
+#[allow(dead_code)]
+pub const METHODS: &str = "GET, POST, PATCH, DELETE, PUT";
+
+pub async fn handler(
+    mut req: Request<Body>,
+    printer: u32,
+    distributor: Sender<EventType>,
+    username: &str,
+) -> Response<Body> {
+    let result = body::to_bytes(req.body_mut()).await.unwrap();
+    let body = match String::from_utf8(result.to_vec()) {
+        Ok(body) => Some(body),
+        Err(e) => {
+            eprintln!("[API][ADD_QUEUE_JOB] Invalid body received: {}", e);
+            None
+        }
+    };
+    if body.is_none() {
+        return bad_request_response();
+    }
+    let json = serde_json::from_str::<Value>(&body.unwrap());
+    if json.is_err() {
+        return bad_request_response();
+    }
+    let json = json.unwrap();
+    let filename = match json.get("printName").and_then(Value::as_str) {
+        Some(filename) => filename.trim(),
+        None => return bad_request_response(),
+    };
+    if filename.is_empty() || !filename.ends_with(".gcode") || filename.contains('/') {
+        return bad_request_response();
+    }
+    if !Path::new("./files/").join(filename).is_file() {
+        return not_found_response();
+    }
+
+    let job = match print_queue::add(printer, filename, username).await {
+        Ok(job) => job,
+        Err(err) => {
+            eprintln!("[API][ADD_QUEUE_JOB] Cannot queue job: {}", err);
+            return server_error_response();
+        }
+    };
+    send(&distributor, EventType::QueueUpdate);
+
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(
+            header::ACCESS_CONTROL_ALLOW_HEADERS,
+            "Authorization, Content-Type",
+        )
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .status(201)
+        .body(Body::from(json!(job).to_string()))
+        .expect("Failed to construct valid response");
+}
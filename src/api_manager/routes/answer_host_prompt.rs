@@ -0,0 +1,78 @@
+/*
+    Answer the open prompt of the firmware by sending M876 S<choice>.
+
+    POST /api/printer/prompt
+
+    Body: (json)
+        choice: Index of the chosen option.
+
+
+    Permission: print_state.edit
+    State: A prompt is open
+*/
+
+use crossbeam_channel::Sender;
+use hyper::{body, header, Body, Request, Response};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::api_manager::{
+    models::{send, EventType, HostPrompt, Message},
+    responses::{bad_request_response, forbidden_response},
+};
+
+pub const METHODS: &str = "GET, POST";
+pub const PATH: &str = "/api/printer/prompt";
+
+pub async fn handler(
+    mut req: Request<Body>,
+    distributor: Sender<EventType>,
+    host_prompt: Option<HostPrompt>,
+) -> Response<Body> {
+    if host_prompt.is_none() {
+        return forbidden_response();
+    }
+    let host_prompt = host_prompt.unwrap();
+
+    let result = body::to_bytes(req.body_mut()).await.unwrap();
+    let body = match String::from_utf8(result.to_vec()) {
+        Ok(body) => Some(body),
+        Err(e) => {
+            eprintln!("[API][ANSWER_PROMPT] Invalid body received: {}", e);
+            None
+        }
+    };
+    if body.is_none() {
+        return bad_request_response();
+    }
+
+    let json = serde_json::from_str::<Value>(&body.unwrap());
+    if json.is_err() {
+        return bad_request_response();
+    }
+    let choice = json.unwrap().get("choice").and_then(|choice| choice.as_u64());
+    if choice.is_none() {
+        return bad_request_response();
+    }
+    let choice = choice.unwrap();
+    // a prompt without choices can only be dismissed.
+    if choice as usize >= host_prompt.choices.len().max(1) {
+        return bad_request_response();
+    }
+
+    send(
+        &distributor,
+        EventType::OutGoingTerminalMessage(Message::new(
+            format!("M876 S{}", choice),
+            Uuid::new_v4(),
+        )),
+    );
+    send(&distributor, EventType::HostPrompt(None));
+
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .body(Body::empty())
+        .expect("Failed to construct valid response");
+}
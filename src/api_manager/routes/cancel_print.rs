@@ -1,28 +1,50 @@
 /*
     Send a print end event to the bridge.
+    When the printer waits for the user, M108 is sent first to let the firmware continue.
+    That needs the firmware to support EMERGENCY_PARSER, without it the M108 would be queued behind the wait.
+    The bridge sends the cancel script (S_cancelGcode) before the print ends, canceling again ends it right away.
 
     DELETE /api/print
 
     Permission: print_state.edit
-    State: PRINTING
+    State: PRINTING | PAUSED | WAITING
 */
 
 use crossbeam_channel::Sender;
 use hyper::{header, Body, Response};
-
-use crate::api_manager::{
-    models::{send, BridgeState, EventType, StateWrapper},
-    responses::forbidden_response,
+use uuid::Uuid;
+
+use crate::{
+    api_manager::{
+        models::{send, BridgeState, EventType, Message, StateWrapper},
+        responses::forbidden_response,
+    },
+    firmware::Firmware,
 };
 
 #[allow(dead_code)]
-pub const METHODS: &str = "DELETE";
+pub const METHODS: &str = "PUT, PATCH, DELETE";
 pub const PATH: &str = "/api/print";
 
-pub fn handler(state_info: StateWrapper, distributor: Sender<EventType>) -> Response<Body> {
-    if state_info.state != BridgeState::PRINTING {
+pub fn handler(
+    state_info: StateWrapper,
+    distributor: Sender<EventType>,
+    firmware: Option<Firmware>,
+) -> Response<Body> {
+    if state_info.state != BridgeState::PRINTING
+        && state_info.state != BridgeState::PAUSED
+        && state_info.state != BridgeState::WAITING
+    {
         return forbidden_response();
     }
+    if state_info.state == BridgeState::WAITING
+        && firmware.map_or(false, |firmware| firmware.supports("EMERGENCY_PARSER"))
+    {
+        send(
+            &distributor,
+            EventType::OutGoingTerminalMessage(Message::new("M108".to_string(), Uuid::new_v4())),
+        );
+    }
 
     send(&distributor, EventType::PrintEnd);
 
@@ -0,0 +1,34 @@
+/*
+    Cancel the upload to the SD card, the partially written file is removed from the SD card.
+
+    DELETE /api/sd/upload
+
+    Permission: file.access & print_state.edit
+    State: Uploading
+*/
+
+use crossbeam_channel::Sender;
+use hyper::{header, Body, Response};
+
+use crate::api_manager::{
+    models::{send, BridgeState, EventType},
+    responses::forbidden_response,
+};
+
+#[allow(dead_code)]
+pub const METHODS: &str = "PUT, DELETE";
+pub const PATH: &str = "/api/sd/upload";
+
+pub fn handler(state: BridgeState, distributor: Sender<EventType>) -> Response<Body> {
+    if state != BridgeState::UPLOADING {
+        return forbidden_response();
+    }
+
+    send(&distributor, EventType::PrintEnd);
+
+    return Response::builder()
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .body(Body::empty())
+        .expect("Failed to construct valid response");
+}
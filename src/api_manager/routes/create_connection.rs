@@ -1,5 +1,7 @@
 /*
     Send a event to the bridge to start creating connection.
+    With auto baud set the baudrate is detected by the bridge, and saved as the baudrate of the printer.
+    Responds with 400 and a message when the device path or baudrate isn't set up (see GET /api/connection/ports).
 
     PUT /api/connection
 
@@ -9,17 +11,21 @@
 
 use crossbeam_channel::Sender;
 use hyper::{header, Body, Request, Response};
-use sqlx::{Connection, SqliteConnection};
+use serde_json::json;
 
-use crate::api_manager::{
-    models::{BridgeState, EventType, SettingRow, StateWrapper},
-    responses::forbidden_response,
+use crate::{
+    api_manager::{
+        models::{BridgeState, EventType, StateWrapper},
+        responses::forbidden_response,
+    },
+    printers::PrinterConfig,
 };
 pub const METHODS: &str = "PUT, DELETE, POST";
 pub const PATH: &str = "/api/connection";
 
 pub async fn handler(
     _request: Request<Body>,
+    printer: u32,
     distributor: Sender<EventType>,
     state_info: StateWrapper,
 ) -> Response<Body> {
@@ -28,47 +34,17 @@ pub async fn handler(
         return forbidden_response();
     }
 
-    let result = async {
-        let mut connection = (SqliteConnection::connect("storage.db")).await.unwrap();
-        let query = sqlx::query_as::<_, SettingRow>("select * from settings");
-        match query.fetch_all(&mut connection).await {
-            Ok(settings) => {
-                let mut address: Option<String> = None;
-                let mut port: Option<u32> = None;
-
-                for setting in settings.iter() {
-                    if setting.id == "S_devicePath" {
-                        address = Some(setting.raw_value.clone())
-                    }
-                    if setting.id == "N_deviceBaud" {
-                        port = Some(setting.number.unwrap().clone() as u32);
-                    }
-                }
-                if address.is_none() || port.is_none() {
-                    eprintln!("[API][ERROR] No address / port set up");
-                    return None;
-                }
-                if address.clone().unwrap().len() == 0 || port.clone().unwrap() == 0 {
-                    eprintln!("[API][ERROR] No address / port set up");
-                    return None;
-                }
-                return Some(ConnectionInfo::new(address.unwrap(), port.unwrap()));
-            }
-            Err(err) => {
-                eprintln!("[API][ERROR] {}", err);
-                return None;
-            }
-        }
-    }
-    .await;
-
-    if result.is_none() {
+    let result = load_connection_info(printer).await;
+    if let Err(message) = result {
+        eprintln!("[API][ERROR] {}", message);
         return Response::builder()
-            .header(header::CONTENT_TYPE, "text/plain")
+            .header(header::CONTENT_TYPE, "application/json")
             .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
             .header(header::ACCESS_CONTROL_ALLOW_METHODS, "PUT")
             .status(400)
-            .body(Body::from("Bad Request"))
+            .body(Body::from(
+                json!({"error": true, "message": message}).to_string(),
+            ))
             .expect("Failed to construct valid response");
     }
 
@@ -88,8 +64,9 @@ pub async fn handler(
 }
 
 #[derive(Debug, Clone)]
-struct ConnectionInfo {
+pub struct ConnectionInfo {
     pub address: String,
+    // 0 when the baudrate has to be detected.
     pub port: u32,
 }
 
@@ -98,3 +75,17 @@ impl ConnectionInfo {
         Self { address, port }
     }
 }
+
+/*
+    Load the device path & baudrate of the printer.
+    Returns a message for the user when the connection isn't set up.
+*/
+pub async fn load_connection_info(printer: u32) -> Result<ConnectionInfo, String> {
+    let config = match PrinterConfig::load(printer).await {
+        Ok(Some(config)) => config,
+        Ok(None) => return Err("Unknown printer".to_string()),
+        Err(err) => return Err(err.to_string()),
+    };
+    let (address, port) = config.connection()?;
+    return Ok(ConnectionInfo::new(address, port));
+}
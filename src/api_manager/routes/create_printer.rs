@@ -0,0 +1,82 @@
+/*
+    Add a printer to this server and start its manager, it connects like the default printer.
+    Responds with the created printer, its id is used for the printer routes (/api/printers/{id}/...).
+
+    POST /api/printers
+
+    Body: (json)
+        name: String
+        devicePath: String (optional)
+        baudRate: Number (optional)
+        autoBaud: Boolean (optional)
+        startOnBoot: Boolean (optional)
+        settings: Object (optional), overrides of the settings in printers::PRINTER_SETTINGS by name, null removes an override
+
+
+    Permission: connection.edit
+    State: -
+*/
+
+use std::{collections::HashMap, sync::Arc};
+
+use hyper::{body, header, upgrade::Upgraded, Body, Request, Response};
+use hyper_tungstenite::WebSocketStream;
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+
+use crate::{
+    api_manager::responses::{bad_request_response, server_error_response},
+    printers::{PrinterConfig, Printers},
+};
+
+pub const PATH: &str = "/api/printers";
+#[allow(dead_code)]
+pub const METHODS: &str = "GET, POST";
+
+pub async fn handler(
+    mut req: Request<Body>,
+    printers: Printers,
+    sockets: Arc<Mutex<HashMap<u128, WebSocketStream<Upgraded>>>>,
+) -> Response<Body> {
+    let result = body::to_bytes(req.body_mut()).await.unwrap();
+    let body = match String::from_utf8(result.to_vec()) {
+        Ok(body) => Some(body),
+        Err(e) => {
+            eprintln!("[API][CREATE_PRINTER] Invalid body received: {}", e);
+            None
+        }
+    };
+    if body.is_none() {
+        return bad_request_response();
+    }
+    let json = serde_json::from_str::<Value>(&body.unwrap());
+    if json.is_err() {
+        return bad_request_response();
+    }
+    let json = json.unwrap();
+    if json.get("name").is_none() {
+        return bad_request_response();
+    }
+
+    let mut config = PrinterConfig::new(String::new());
+    if !config.update(&json) {
+        return bad_request_response();
+    }
+    if let Err(err) = config.insert().await {
+        eprintln!("[API][CREATE_PRINTER] Cannot store printer: {}", err);
+        return server_error_response();
+    }
+    crate::start_printer(config.clone(), &printers, sockets).await;
+
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(
+            header::ACCESS_CONTROL_ALLOW_HEADERS,
+            "Authorization, Content-Type",
+        )
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .status(201)
+        .body(Body::from(json!(config).to_string()))
+        .expect("Failed to construct valid response");
+}
@@ -0,0 +1,62 @@
+/*
+    Remove a print from the print history.
+
+    DELETE /api/history
+
+    Body: (json)
+        id: Number
+
+
+    Permission: print_state.edit
+    State: -
+*/
+
+use hyper::{body, header, Body, Request, Response};
+use serde_json::Value;
+
+use crate::{
+    api_manager::responses::{bad_request_response, not_found_response, server_error_response},
+    print_history,
+};
+
+pub const PATH: &str = "/api/history";
+#[allow(dead_code)]
+pub const METHODS: &str = "GET, DELETE";
+
+pub async fn handler(mut req: Request<Body>) -> Response<Body> {
+    let result = body::to_bytes(req.body_mut()).await.unwrap();
+    let body = match String::from_utf8(result.to_vec()) {
+        Ok(body) => Some(body),
+        Err(e) => {
+            eprintln!("[API][DELETE_HISTORY_JOB] Invalid body received: {}", e);
+            None
+        }
+    };
+    if body.is_none() {
+        return bad_request_response();
+    }
+    let json = serde_json::from_str::<Value>(&body.unwrap());
+    if json.is_err() {
+        return bad_request_response();
+    }
+    let id = match json.unwrap().get("id").and_then(Value::as_u64) {
+        Some(id) if id <= u32::MAX as u64 => id as u32,
+        _ => return bad_request_response(),
+    };
+
+    match print_history::delete(id).await {
+        Ok(true) => (),
+        Ok(false) => return not_found_response(),
+        Err(err) => {
+            eprintln!("[API][DELETE_HISTORY_JOB] Cannot remove job: {}", err);
+            return server_error_response();
+        }
+    }
+
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .body(Body::empty())
+        .expect("Failed to construct valid response");
+}
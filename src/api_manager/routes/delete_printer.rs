@@ -0,0 +1,52 @@
+/*
+    Remove a printer from this server, its manager is stopped and its stored temperature history, terminal log, print queue, recovery point & print history removed.
+
+    ! The default printer (1) cannot be removed.
+
+    DELETE /api/printers/{id}
+
+    Permission: connection.edit
+    State: Disconnected | Errored
+*/
+
+use crossbeam_channel::Sender;
+use hyper::{header, Body, Response};
+
+use crate::{
+    api_manager::{
+        models::{send, BridgeState, EventType},
+        responses::{forbidden_response, server_error_response},
+    },
+    printers::{PrinterConfig, Printers, DEFAULT_PRINTER},
+};
+
+// the router maps /api/printers/{id} to this path.
+pub const PATH: &str = "/api/printer";
+#[allow(dead_code)]
+pub const METHODS: &str = "PATCH, DELETE";
+
+pub async fn handler(
+    id: u32,
+    state: BridgeState,
+    distributor: Sender<EventType>,
+    printers: Printers,
+) -> Response<Body> {
+    if id == DEFAULT_PRINTER
+        || !(state == BridgeState::DISCONNECTED || state == BridgeState::ERRORED)
+    {
+        return forbidden_response();
+    }
+    if let Err(err) = PrinterConfig::delete(id).await {
+        eprintln!("[API][DELETE_PRINTER] Cannot remove printer: {}", err);
+        return server_error_response();
+    }
+    printers.lock().await.remove(&id);
+    send(&distributor, EventType::RemovePrinter);
+
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .body(Body::empty())
+        .expect("Failed to construct valid response");
+}
@@ -0,0 +1,30 @@
+/*
+    Discard the saved progress of the interrupted print of a printer, it cannot be recovered after.
+
+    DELETE /api/recovery
+
+    Permission: print_state.edit
+    State: -
+*/
+
+use hyper::{header, Body, Response};
+
+use crate::{api_manager::responses::server_error_response, print_recovery};
+
+pub const PATH: &str = "/api/recovery";
+#[allow(dead_code)]
+pub const METHODS: &str = "GET, PUT, DELETE";
+
+pub async fn handler(printer: u32) -> Response<Body> {
+    if let Err(err) = print_recovery::clear(printer).await {
+        eprintln!("[API][DISCARD_RECOVERY] Cannot remove recovery point: {}", err);
+        return server_error_response();
+    }
+
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .body(Body::empty())
+        .expect("Failed to construct valid response");
+}
@@ -0,0 +1,62 @@
+/*
+    Send M112 to the printer, ahead of any queued commands, and abort the current print.
+    M112 is written to the connection right away, instead of waiting for the manager & bridge to handle the event.
+    The connection ends up errored, as the printer has to be reset after an emergency stop.
+
+    POST /api/printer/emergency-stop
+
+    Permission: print_state.edit
+    State: Connecting | Connected | Printing | Paused
+*/
+
+use std::sync::Arc;
+
+use crossbeam_channel::Sender;
+use hyper::{header, Body, Response};
+use tokio::sync::Mutex;
+
+use crate::{
+    api_manager::{
+        models::{send, BridgeState, EventType},
+        responses::forbidden_response,
+    },
+    transport::Transport,
+};
+
+pub const METHODS: &str = "POST";
+pub const PATH: &str = "/api/printer/emergency-stop";
+
+pub async fn handler(
+    state: BridgeState,
+    distributor: Sender<EventType>,
+    emergency_port: Arc<Mutex<Option<Box<dyn Transport>>>>,
+    username: &str,
+) -> Response<Body> {
+    if state.eq(&BridgeState::DISCONNECTED) || state.eq(&BridgeState::ERRORED) {
+        return forbidden_response();
+    }
+
+    match emergency_port.lock().await.as_mut() {
+        Some(port) => {
+            // the newline ends any partially written line first.
+            if let Err(err) = port.write_all(b"\nM112\n") {
+                eprintln!("[API][EMERGENCY_STOP] Cannot send emergency stop: {}", err);
+            }
+        }
+        None => eprintln!("[API][EMERGENCY_STOP] Cannot send emergency stop: port is closed"),
+    }
+
+    send(
+        &distributor,
+        EventType::EmergencyStop {
+            username: username.to_string(),
+        },
+    );
+
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .body(Body::empty())
+        .expect("Failed to construct valid response");
+}
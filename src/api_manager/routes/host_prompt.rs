@@ -0,0 +1,27 @@
+/*
+    Get the prompt of the firmware that is waiting for an answer (host action commands).
+    Returns null when there is no open prompt.
+
+    GET /api/printer/prompt
+
+    Permission: -
+    State: -
+*/
+
+use hyper::{header, Body, Response};
+use serde_json::json;
+
+use crate::api_manager::models::HostPrompt;
+
+pub const PATH: &str = "/api/printer/prompt";
+pub const METHODS: &str = "GET, POST";
+
+pub fn handler(host_prompt: Option<HostPrompt>) -> Response<Body> {
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "Authorization")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .body(Body::from(json!(host_prompt).to_string()))
+        .expect("Failed to construct valid response");
+}
@@ -0,0 +1,28 @@
+/*
+    Get the connection quality of the current print: resends, checksum errors and line number errors.
+    After a print ended, the stats of that print are returned until the next one starts.
+    Returns null when no print has been started yet.
+
+    GET /api/print/link
+
+    Permission: -
+    State: -
+*/
+
+use hyper::{header, Body, Response};
+use serde_json::json;
+
+use crate::api_manager::models::LinkStats;
+
+pub const PATH: &str = "/api/print/link";
+pub const METHODS: &str = "GET";
+
+pub fn handler(link_stats: Option<LinkStats>) -> Response<Body> {
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "Authorization")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .body(Body::from(json!(link_stats).to_string()))
+        .expect("Failed to construct valid response");
+}
@@ -0,0 +1,89 @@
+/*
+    List the prints that ran on the printers, latest first.
+
+    GET /api/history
+
+    Query: (every parameter is optional)
+        printer: Id of a printer.
+        outcome: finished, cancelled or errored.
+        filename: Only prints of files containing this text.
+        user: Only prints started by this user.
+        since: Timestamp in milliseconds, only prints started at or after this time.
+        until: Timestamp in milliseconds, only prints started before this time.
+        limit: Amount of prints to return, 50 by default & 500 at most.
+        offset: Amount of prints to skip.
+
+
+    Permission: -
+    State: -
+*/
+
+use hyper::{header, Body, Request, Response};
+use serde_json::json;
+
+use crate::{
+    api_manager::responses::{bad_request_response, server_error_response},
+    print_history::{self, HistoryFilter, PrintOutcome, MAX_LIMIT},
+};
+
+pub const PATH: &str = "/api/history";
+pub const METHODS: &str = "GET, DELETE";
+
+pub async fn handler(request: Request<Body>) -> Response<Body> {
+    let query = request.uri().query().unwrap_or("").as_bytes();
+    let mut filter = HistoryFilter::new();
+    for (key, value) in form_urlencoded::parse(query) {
+        let valid = match key.as_ref() {
+            "printer" => value.parse().map(|printer| filter.printer = Some(printer)).is_ok(),
+            "outcome" => match PrintOutcome::from_str(&value) {
+                Some(outcome) => {
+                    filter.outcome = Some(outcome);
+                    true
+                }
+                None => false,
+            },
+            "filename" => {
+                filter.filename = Some(value.into_owned());
+                true
+            }
+            "user" => {
+                filter.user = Some(value.into_owned());
+                true
+            }
+            "since" => value.parse().map(|since| filter.since = Some(since)).is_ok(),
+            "until" => value.parse().map(|until| filter.until = Some(until)).is_ok(),
+            "limit" => match value.parse::<u32>() {
+                Ok(limit) if limit <= MAX_LIMIT => {
+                    filter.limit = limit;
+                    true
+                }
+                _ => false,
+            },
+            "offset" => value.parse().map(|offset| filter.offset = offset).is_ok(),
+            _ => true,
+        };
+        if !valid {
+            return bad_request_response();
+        }
+    }
+
+    let (jobs, total) = match print_history::list(&filter).await {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("[API][LIST_HISTORY] Cannot load history: {}", err);
+            return server_error_response();
+        }
+    };
+    let json = json!({
+        "jobs": jobs,
+        "total": total,
+    });
+
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "Authorization")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .body(Body::from(json.to_string()))
+        .expect("Failed to construct valid response");
+}
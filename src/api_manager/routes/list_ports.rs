@@ -0,0 +1,40 @@
+/*
+    List the serial ports of the system, including the USB vendor, product and serial number when known.
+    Used to pick the device path (S_devicePath) of the printer.
+
+    GET /api/connection/ports
+
+    Permission: connection.edit
+    State: -
+*/
+
+use hyper::{header, Body, Response};
+use serde_json::json;
+
+use crate::{api_manager::responses::server_error_response, transport};
+
+pub const PATH: &str = "/api/connection/ports";
+pub const METHODS: &str = "GET";
+
+pub async fn handler() -> Response<Body> {
+    let ports = tokio::task::spawn_blocking(transport::available_ports).await;
+    let ports = match ports {
+        Ok(Ok(ports)) => ports,
+        Ok(Err(err)) => {
+            eprintln!("[API][LIST_PORTS] Cannot list ports: {}", err);
+            return server_error_response();
+        }
+        Err(err) => {
+            eprintln!("[API][LIST_PORTS] Cannot list ports: {}", err);
+            return server_error_response();
+        }
+    };
+
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "Authorization")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .body(Body::from(json!(ports).to_string()))
+        .expect("Failed to construct valid response");
+}
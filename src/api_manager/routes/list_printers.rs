@@ -0,0 +1,48 @@
+/*
+    List the printers of this server, including their connection settings, setting overrides and current state.
+
+    GET /api/printers
+
+    Permission: -
+    State: -
+*/
+
+use hyper::{header, Body, Response};
+use serde_json::json;
+
+use crate::{
+    api_manager::{responses::server_error_response, websocket_handler::describe_state},
+    printers::{PrinterConfig, Printers},
+};
+
+pub const PATH: &str = "/api/printers";
+pub const METHODS: &str = "GET, POST";
+
+pub async fn handler(printers: Printers) -> Response<Body> {
+    let configs = match PrinterConfig::load_all().await {
+        Ok(configs) => configs,
+        Err(err) => {
+            eprintln!("[API][LIST_PRINTERS] Cannot load printers: {}", err);
+            return server_error_response();
+        }
+    };
+
+    let mut result = vec![];
+    for config in configs {
+        let mut info = json!(config);
+        if let Some(printer) = printers.lock().await.get(&config.id) {
+            let (state, description) = describe_state(&*printer.state.lock().await);
+            info["state"] = json!(state);
+            info["description"] = description;
+        }
+        result.push(info);
+    }
+
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "Authorization")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .body(Body::from(json!(result).to_string()))
+        .expect("Failed to construct valid response");
+}
@@ -0,0 +1,41 @@
+/*
+    List the print queue of a printer, the first job is printed next.
+    awaitingConfirmation is set when the next job waits for the user to confirm the bed is clear.
+
+    GET /api/queue
+
+    Permission: -
+    State: -
+*/
+
+use hyper::{header, Body, Response};
+use serde_json::json;
+
+use crate::{api_manager::responses::server_error_response, print_queue};
+
+pub const PATH: &str = "/api/queue";
+pub const METHODS: &str = "GET, POST, PATCH, DELETE, PUT";
+
+pub async fn handler(printer: u32, awaiting_confirmation: bool) -> Response<Body> {
+    let jobs = match print_queue::list(printer).await {
+        Ok(jobs) => jobs,
+        Err(err) => {
+            eprintln!("[API][LIST_QUEUE] Cannot load queue: {}", err);
+            return server_error_response();
+        }
+    };
+
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "Authorization")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .body(Body::from(
+            json!({
+                "jobs": jobs,
+                "awaitingConfirmation": awaiting_confirmation
+            })
+            .to_string(),
+        ))
+        .expect("Failed to construct valid response");
+}
@@ -1,14 +1,42 @@
+pub mod add_queue_job;
+pub mod answer_host_prompt;
 pub mod cancel_print;
+pub mod cancel_sd_upload;
 pub mod create_connection;
+pub mod create_printer;
+pub mod delete_history_job;
+pub mod delete_printer;
+pub mod discard_recovery;
 pub mod disconnect_connection;
 pub mod dsn;
+pub mod emergency_stop;
+pub mod host_prompt;
+pub mod link_stats;
 pub mod list_files;
+pub mod list_history;
+pub mod list_ports;
+pub mod list_printers;
+pub mod list_queue;
 pub mod list_settings;
 pub mod login;
+pub mod move_queue_job;
 pub mod ping;
+pub mod position;
 pub mod reconnect_connection;
+pub mod recover_print;
+pub mod recovery;
+pub mod refresh_sd_files;
+pub mod remove_queue_job;
 pub mod rename_file;
+pub mod sd_files;
 pub mod start_print;
+pub mod start_queue;
+pub mod start_sd_print;
+pub mod start_sd_upload;
+pub mod temperature_history;
 pub mod terminal;
+pub mod terminal_log;
+pub mod update_print;
+pub mod update_printer;
 pub mod update_settings;
 pub mod upload_file;
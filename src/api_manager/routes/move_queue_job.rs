@@ -0,0 +1,74 @@
+/*
+    Move a job to another position in the print queue of a printer.
+    Position 0 is printed next, positions past the end move the job to the end.
+
+    PATCH /api/queue
+
+    Body: (json)
+        id: Number
+        position: Number
+
+
+    Permission: print_state.edit
+    State: -
+*/
+
+use crossbeam_channel::Sender;
+use hyper::{body, header, Body, Request, Response};
+use serde_json::Value;
+
+use crate::{
+    api_manager::{
+        models::{send, EventType},
+        responses::{bad_request_response, not_found_response, server_error_response},
+    },
+    print_queue,
+};
+
+pub const PATH: &str = "/api/queue";
+#[allow(dead_code)]
+pub const METHODS: &str = "GET, POST, PATCH, DELETE, PUT";
+
+pub async fn handler(
+    mut req: Request<Body>,
+    printer: u32,
+    distributor: Sender<EventType>,
+) -> Response<Body> {
+    let result = body::to_bytes(req.body_mut()).await.unwrap();
+    let body = match String::from_utf8(result.to_vec()) {
+        Ok(body) => Some(body),
+        Err(e) => {
+            eprintln!("[API][MOVE_QUEUE_JOB] Invalid body received: {}", e);
+            None
+        }
+    };
+    if body.is_none() {
+        return bad_request_response();
+    }
+    let json = serde_json::from_str::<Value>(&body.unwrap());
+    if json.is_err() {
+        return bad_request_response();
+    }
+    let json = json.unwrap();
+    let id = json.get("id").and_then(Value::as_u64);
+    let position = json.get("position").and_then(Value::as_u64);
+    if id.is_none() || id.unwrap() > u32::MAX as u64 || position.is_none() {
+        return bad_request_response();
+    }
+
+    match print_queue::move_job(printer, id.unwrap() as u32, position.unwrap() as usize).await {
+        Ok(true) => send(&distributor, EventType::QueueUpdate),
+        Ok(false) => return not_found_response(),
+        Err(err) => {
+            eprintln!("[API][MOVE_QUEUE_JOB] Cannot move job: {}", err);
+            return server_error_response();
+        }
+    }
+
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .body(Body::empty())
+        .expect("Failed to construct valid response");
+}
@@ -0,0 +1,27 @@
+/*
+    Get the last reported position of the toolhead.
+    Returns null when no position has been reported since connecting.
+
+    GET /api/printer/position
+
+    Permission: -
+    State: -
+*/
+
+use hyper::{header, Body, Response};
+use serde_json::json;
+
+use crate::parser::Position;
+
+pub const PATH: &str = "/api/printer/position";
+pub const METHODS: &str = "GET";
+
+pub fn handler(position: Option<Position>) -> Response<Body> {
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "Authorization")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .body(Body::from(json!(position).to_string()))
+        .expect("Failed to construct valid response");
+}
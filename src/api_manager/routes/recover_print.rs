@@ -0,0 +1,80 @@
+/*
+    Recover the print that was interrupted on a printer, it continues after the last acknowledged line.
+    The bridge lifts the nozzle, reheats, homes X & Y and moves back to the height of that line first.
+    The moves the firmware had planned but not executed yet when it stopped are skipped.
+
+    ! The nozzle has to be at the height it stopped at, as Z isn't homed.
+    ! Cannot recover a print of which the file was changed or removed since.
+
+    PUT /api/recovery
+
+    Permission: print_state.edit
+    State: Connected
+*/
+
+use std::path::Path;
+
+use chrono::Utc;
+use crossbeam_channel::Sender;
+use hyper::{header, Body, Response};
+
+use crate::{
+    api_manager::{
+        models::{send, BridgeState, EventType, PrintInfo},
+        responses::{forbidden_response, not_found_response, server_error_response},
+    },
+    print_file::PrintFile,
+    print_recovery,
+};
+
+pub const PATH: &str = "/api/recovery";
+#[allow(dead_code)]
+pub const METHODS: &str = "GET, PUT, DELETE";
+
+pub async fn handler(
+    printer: u32,
+    state: BridgeState,
+    distributor: Sender<EventType>,
+    username: &str,
+) -> Response<Body> {
+    if state != BridgeState::CONNECTED {
+        return forbidden_response();
+    }
+    let point = match print_recovery::load(printer).await {
+        Ok(Some(point)) => point,
+        Ok(None) => return not_found_response(),
+        Err(err) => {
+            eprintln!("[API][RECOVER_PRINT] Cannot load recovery point: {}", err);
+            return server_error_response();
+        }
+    };
+    if print_recovery::file_changed(&point).await {
+        return forbidden_response();
+    }
+    let file = match PrintFile::open(&Path::new("./files/").join(&point.filename)) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("[API][RECOVER_PRINT] Cannot open file: {}", err);
+            return server_error_response();
+        }
+    };
+
+    println!(
+        "[API][RECOVER_PRINT] Recovering {} after line {}",
+        point.filename, point.line
+    );
+    let mut info = PrintInfo::new(point.filename, file, Utc::now());
+    info.resume_from(point.line);
+    info.set_heater_targets(point.heater_targets);
+    info.last_z = point.z;
+    info.user = Some(username.to_string());
+    send(&distributor, EventType::PrintStart(info));
+
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .status(201)
+        .body(Body::empty())
+        .expect("Failed to construct valid response");
+}
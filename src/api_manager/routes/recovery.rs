@@ -0,0 +1,54 @@
+/*
+    Get the saved progress of the print that was interrupted (by a restart or a power loss) on a printer.
+    Returns null when there is no print to recover.
+    fileChanged is set when the file was changed or removed since, the print cannot be recovered then.
+
+    GET /api/recovery
+
+    Permission: -
+    State: -
+*/
+
+use hyper::{header, Body, Response};
+use serde_json::{json, Map, Value};
+
+use crate::{api_manager::responses::server_error_response, print_recovery};
+
+pub const PATH: &str = "/api/recovery";
+pub const METHODS: &str = "GET, PUT, DELETE";
+
+pub async fn handler(printer: u32) -> Response<Body> {
+    let point = match print_recovery::load(printer).await {
+        Ok(point) => point,
+        Err(err) => {
+            eprintln!("[API][RECOVERY] Cannot load recovery point: {}", err);
+            return server_error_response();
+        }
+    };
+    let result = match point {
+        Some(point) => {
+            let heater_targets: Map<String, Value> = point
+                .heater_targets
+                .iter()
+                .map(|(name, target)| (name.clone(), json!(target)))
+                .collect();
+            json!({
+                "filename": point.filename,
+                "line": point.line,
+                "heaterTargets": heater_targets,
+                "z": point.z,
+                "time": point.time,
+                "fileChanged": print_recovery::file_changed(&point).await,
+            })
+        }
+        None => Value::Null,
+    };
+
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "Authorization")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .body(Body::from(result.to_string()))
+        .expect("Failed to construct valid response");
+}
@@ -0,0 +1,43 @@
+/*
+    Ask the firmware to list the files on the SD card again (M20 L).
+    The new list is sent to the websockets as an sd_files event once the firmware answered.
+
+    POST /api/sd
+
+    Permission: file.access
+    State: Connected | Printing | Paused | Waiting
+*/
+
+use crossbeam_channel::Sender;
+use hyper::{header, Body, Response};
+use uuid::Uuid;
+
+use crate::api_manager::{
+    models::{send, BridgeState, EventType, Message},
+    responses::forbidden_response,
+};
+
+#[allow(dead_code)]
+pub const METHODS: &str = "GET, POST, PUT";
+pub const PATH: &str = "/api/sd";
+
+pub fn handler(state: BridgeState, distributor: Sender<EventType>) -> Response<Body> {
+    if state == BridgeState::DISCONNECTED
+        || state == BridgeState::CONNECTING
+        || state == BridgeState::ERRORED
+    {
+        return forbidden_response();
+    }
+
+    send(
+        &distributor,
+        EventType::OutGoingTerminalMessage(Message::new("M20 L".to_string(), Uuid::new_v4())),
+    );
+
+    return Response::builder()
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .status(202)
+        .body(Body::empty())
+        .expect("Failed to construct valid response");
+}
@@ -0,0 +1,70 @@
+/*
+    Remove a job from the print queue of a printer.
+
+    DELETE /api/queue
+
+    Body: (json)
+        id: Number
+
+
+    Permission: print_state.edit
+    State: -
+*/
+
+use crossbeam_channel::Sender;
+use hyper::{body, header, Body, Request, Response};
+use serde_json::Value;
+
+use crate::{
+    api_manager::{
+        models::{send, EventType},
+        responses::{bad_request_response, not_found_response, server_error_response},
+    },
+    print_queue,
+};
+
+pub const PATH: &str = "/api/queue";
+#[allow(dead_code)]
+pub const METHODS: &str = "GET, POST, PATCH, DELETE, PUT";
+
+pub async fn handler(
+    mut req: Request<Body>,
+    printer: u32,
+    distributor: Sender<EventType>,
+) -> Response<Body> {
+    let result = body::to_bytes(req.body_mut()).await.unwrap();
+    let body = match String::from_utf8(result.to_vec()) {
+        Ok(body) => Some(body),
+        Err(e) => {
+            eprintln!("[API][REMOVE_QUEUE_JOB] Invalid body received: {}", e);
+            None
+        }
+    };
+    if body.is_none() {
+        return bad_request_response();
+    }
+    let json = serde_json::from_str::<Value>(&body.unwrap());
+    if json.is_err() {
+        return bad_request_response();
+    }
+    let id = match json.unwrap().get("id").and_then(Value::as_u64) {
+        Some(id) if id <= u32::MAX as u64 => id as u32,
+        _ => return bad_request_response(),
+    };
+
+    match print_queue::remove(printer, id).await {
+        Ok(true) => send(&distributor, EventType::QueueUpdate),
+        Ok(false) => return not_found_response(),
+        Err(err) => {
+            eprintln!("[API][REMOVE_QUEUE_JOB] Cannot remove job: {}", err);
+            return server_error_response();
+        }
+    }
+
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .body(Body::empty())
+        .expect("Failed to construct valid response");
+}
@@ -1,7 +1,8 @@
 /*
     Rename a gcode file.
 
-    ! Cannot rename a file that is currently printing.
+    ! Cannot rename a file that any printer is currently printing.
+    Queued jobs of the file are renamed as well.
 
     POST /api/files/rename
 
@@ -9,15 +10,18 @@
     State: -
 */
 
-use std::{path::Path, sync::Arc};
+use std::path::Path;
 
 use hyper::{body, header, Body, Request, Response};
 use regex::Regex;
-use tokio::sync::Mutex;
 
-use crate::api_manager::{
-    models::{StateDescription, StateWrapper},
-    responses::{bad_request_response, forbidden_response, server_error_response},
+use crate::{
+    api_manager::{
+        models::{send, EventType},
+        responses::{bad_request_response, forbidden_response, server_error_response},
+    },
+    print_queue,
+    printers::{file_in_use, Printers},
 };
 use lazy_static::lazy_static;
 use serde::Deserialize;
@@ -31,7 +35,7 @@ pub const PATH: &str = "/api/files/rename";
 
 pub async fn handler(
     mut request: Request<Body>,
-    state: Arc<Mutex<StateWrapper>>,
+    printers: Printers,
 ) -> Response<Body> {
     let result = body::to_bytes(request.body_mut()).await.unwrap();
     let body = match String::from_utf8(result.to_vec()) {
@@ -51,30 +55,26 @@ pub async fn handler(
     }
     let json = json.unwrap();
 
-    let state = state.lock().await;
-
-    if let StateDescription::Print {
-        filename,
-        progress: _,
-        start: _,
-        end: _,
-    } = state.description.clone()
-    {
-        if filename == json.new_name || filename == json.old_name {
-            return forbidden_response();
-        }
+    if file_in_use(&printers, &json.new_name).await || file_in_use(&printers, &json.old_name).await {
+        return forbidden_response();
     }
     if !NAME_REGEX.is_match(&json.new_name) || !NAME_REGEX.is_match(&json.old_name) {
         return bad_request_response();
     }
 
     let result = std::fs::rename(
-        Path::new("./files").join(json.old_name),
-        Path::new("./files").join(json.new_name),
+        Path::new("./files").join(&json.old_name),
+        Path::new("./files").join(&json.new_name),
     );
     if result.is_err() {
         return server_error_response();
     }
+    if let Err(err) = print_queue::rename_file(&json.old_name, &json.new_name).await {
+        eprintln!("[API][RENAME_FILE] Cannot rename queued jobs: {}", err);
+    }
+    for printer in printers.lock().await.values() {
+        send(&printer.distributor, EventType::QueueUpdate);
+    }
     return Response::builder()
         .header(header::CONTENT_TYPE, "text/plain")
         .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
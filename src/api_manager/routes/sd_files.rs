@@ -0,0 +1,27 @@
+/*
+    Get the files on the SD card of the printer, as listed by the firmware (M20).
+    Returns null when the SD card hasn't been listed yet since connecting.
+
+    GET /api/sd
+
+    Permission: file.access
+    State: -
+*/
+
+use hyper::{header, Body, Response};
+use serde_json::json;
+
+use crate::api_manager::models::SdFile;
+
+pub const PATH: &str = "/api/sd";
+pub const METHODS: &str = "GET, POST, PUT";
+
+pub fn handler(sd_files: Option<Vec<SdFile>>) -> Response<Body> {
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "Authorization")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .body(Body::from(json!(sd_files).to_string()))
+        .expect("Failed to construct valid response");
+}
@@ -1,6 +1,8 @@
 /*
-    Reads a file from the files folder. Load it into memory.
+    Opens a file from the files folder, which gets streamed from disk while printing.
     Constructs a print info file and start a print
+    The start script (S_startGcode) is sent before the first line of the file.
+    The bridge doesn't start the print when a temperature variable of the script can't be filled in.
 
     PUT /api/print
 
@@ -12,12 +14,7 @@
     State: Connected
 */
 
-use std::{
-    fs::File,
-    io::{BufRead, BufReader},
-    path::Path,
-    sync::Arc,
-};
+use std::{io::ErrorKind, path::Path, sync::Arc};
 
 use chrono::Utc;
 use crossbeam_channel::Sender;
@@ -26,20 +23,24 @@ use serde::Deserialize;
 use serde_json::Value;
 use tokio::sync::Mutex;
 
-use crate::api_manager::{
-    models::{send, BridgeState, EventType, PrintInfo, StateWrapper},
-    responses::{
-        self, bad_request_response, forbidden_response, not_found_response, server_error_response,
+use crate::{
+    api_manager::{
+        models::{send, BridgeState, EventType, PrintInfo, StateWrapper},
+        responses::{
+            bad_request_response, forbidden_response, not_found_response, server_error_response,
+        },
     },
+    print_file::PrintFile,
 };
 
 pub const PATH: &str = "/api/print";
-pub const METHODS: &str = "PUT";
+pub const METHODS: &str = "PUT, PATCH, DELETE";
 
 pub async fn handler(
     mut req: Request<Body>,
     distributor: Sender<EventType>,
     state: Arc<Mutex<StateWrapper>>,
+    username: &str,
 ) -> Response<Body> {
     let result = body::to_bytes(req.body_mut()).await.unwrap();
     let body = match String::from_utf8(result.to_vec()) {
@@ -75,59 +76,21 @@ pub async fn handler(
         return forbidden_response();
     }
 
-    let path = Path::new("./files/")
-        .join(filename)
-        .to_string_lossy()
-        .into_owned();
-
-    let file = File::open(path.clone());
-
-    if file.is_err() {
-        return not_found_response();
-    }
-    let file = file.unwrap();
-
-    let reader = BufReader::new(file);
-    let file_reader = reader.lines();
-    let mut gcode: Vec<String> = Vec::with_capacity(file_reader.count() + 1);
-
-    let file = File::open(path);
+    let path = Path::new("./files/").join(filename);
 
+    let file = PrintFile::open(&path);
     if file.is_err() {
+        let err = file.unwrap_err();
+        if err.kind() == ErrorKind::NotFound {
+            return not_found_response();
+        }
+        eprintln!("[API][START_PRINT] Cannot open file: {}", err);
         return server_error_response();
     }
-    let file = file.unwrap();
-    let file_reader = BufReader::new(file).lines();
-    let mut size: usize = 0;
-    gcode.push("M110 N0".to_string());
-    for line in file_reader {
-        if line.is_err() {
-            return responses::server_error_response();
-        }
-        let line = line.unwrap();
 
-        let line = line.split_terminator(";").next();
-        if line.is_none() {
-            continue;
-        }
-        let line = line.unwrap().trim();
-        if line.len() == 0 {
-            continue;
-        }
-        size += line.as_bytes().len();
-        gcode.push(line.to_string());
-    }
-    gcode.shrink_to_fit();
-
-    send(
-        &distributor,
-        EventType::PrintStart(PrintInfo::new(
-            filename.to_string(),
-            size,
-            gcode,
-            Utc::now(),
-        )),
-    );
+    let mut info = PrintInfo::new(filename.to_string(), file.unwrap(), Utc::now());
+    info.user = Some(username.to_string());
+    send(&distributor, EventType::PrintStart(info));
 
     return Response::builder()
         .header(header::CONTENT_TYPE, "application/json")
@@ -0,0 +1,51 @@
+/*
+    Start printing the first job of the print queue of a printer.
+    This also confirms the bed is clear, when a finished print waits for it (B_queueConfirmBedClear).
+    Responds with 404 when the queue is empty.
+
+    PUT /api/queue
+
+    Permission: print_state.edit
+    State: Connected
+*/
+
+use crossbeam_channel::Sender;
+use hyper::{header, Body, Response};
+
+use crate::{
+    api_manager::{
+        models::{BridgeState, EventType},
+        responses::{forbidden_response, not_found_response, server_error_response},
+    },
+    print_queue,
+};
+
+pub const PATH: &str = "/api/queue";
+#[allow(dead_code)]
+pub const METHODS: &str = "GET, POST, PATCH, DELETE, PUT";
+
+pub async fn handler(
+    printer: u32,
+    state: BridgeState,
+    distributor: Sender<EventType>,
+) -> Response<Body> {
+    if state != BridgeState::CONNECTED {
+        return forbidden_response();
+    }
+    match print_queue::start_next(printer, &distributor).await {
+        Ok(Some(_)) => (),
+        Ok(None) => return not_found_response(),
+        Err(err) => {
+            eprintln!("[API][START_QUEUE] Cannot start the next job: {}", err);
+            return server_error_response();
+        }
+    }
+
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .status(201)
+        .body(Body::empty())
+        .expect("Failed to construct valid response");
+}
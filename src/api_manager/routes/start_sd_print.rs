@@ -0,0 +1,93 @@
+/*
+    Select a file on the SD card and let the firmware print it (M23, M24).
+    The file has to be in the last listing of the SD card, use the short (8.3) name the firmware reported.
+    Pausing, resuming and canceling the print goes through PATCH / DELETE /api/print like any other print.
+
+    PUT /api/sd
+
+    Body: (json)
+        file: String
+
+
+    Permission: print_state.edit
+    State: Connected
+*/
+
+use crossbeam_channel::Sender;
+use hyper::{body, header, Body, Request, Response};
+use serde_json::Value;
+
+use crate::api_manager::{
+    models::{send, BridgeState, EventType, SdFile},
+    responses::{bad_request_response, forbidden_response, not_found_response},
+};
+
+#[allow(dead_code)]
+pub const METHODS: &str = "GET, POST, PUT";
+pub const PATH: &str = "/api/sd";
+
+pub async fn handler(
+    mut req: Request<Body>,
+    distributor: Sender<EventType>,
+    state: BridgeState,
+    sd_files: Option<Vec<SdFile>>,
+    username: &str,
+) -> Response<Body> {
+    let result = body::to_bytes(req.body_mut()).await.unwrap();
+    let body = match String::from_utf8(result.to_vec()) {
+        Ok(body) => Some(body),
+        Err(e) => {
+            eprintln!("[API][START_SD_PRINT] Invalid body received: {}", e);
+            None
+        }
+    };
+    if body.is_none() {
+        return bad_request_response();
+    }
+    let json = serde_json::from_str::<Value>(&body.unwrap());
+    if json.is_err() {
+        return bad_request_response();
+    }
+    let json = json.unwrap();
+    let file = json.get("file").and_then(|file| file.as_str());
+    if file.is_none() {
+        return bad_request_response();
+    }
+    let file = file.unwrap().trim();
+    if file.is_empty() {
+        return bad_request_response();
+    }
+    if state != BridgeState::CONNECTED {
+        return forbidden_response();
+    }
+
+    let sd_file = sd_files
+        .unwrap_or_default()
+        .into_iter()
+        .find(|sd_file| sd_file.name.eq_ignore_ascii_case(file));
+    if sd_file.is_none() {
+        return not_found_response();
+    }
+    let sd_file = sd_file.unwrap();
+
+    send(
+        &distributor,
+        EventType::SdPrintStart {
+            filename: sd_file.long_name.unwrap_or(sd_file.name.clone()),
+            file: sd_file.name,
+            user: Some(username.to_string()),
+        },
+    );
+
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(
+            header::ACCESS_CONTROL_ALLOW_HEADERS,
+            "Authorization, Content-Type",
+        )
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .status(201)
+        .body(Body::empty())
+        .expect("Failed to construct valid response");
+}
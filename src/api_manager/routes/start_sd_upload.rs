@@ -0,0 +1,127 @@
+/*
+    Copy a file from the files folder to the SD card of the printer (M28 / M29).
+    The lines are streamed like a print, but the firmware writes them to the file instead of executing them.
+    Firmware that supports it (Cap:BINARY_FILE_TRANSFER) gets the file with the binary file transfer instead,
+    which is a lot faster (see binary_transfer).
+    The progress is sent to the websockets as state updates (Uploading), the SD card is listed again afterwards.
+
+    PUT /api/sd/upload
+
+    Body: (json)
+        printName: String
+        target: String (optional, 8.3 name on the SD card, derived from printName by default)
+
+
+    Permission: file.access & print_state.edit
+    State: Connected
+*/
+
+use std::{io::ErrorKind, path::Path};
+
+use chrono::Utc;
+use crossbeam_channel::Sender;
+use hyper::{body, header, Body, Request, Response};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::Value;
+
+use crate::{
+    api_manager::{
+        models::{send, BridgeState, EventType, PrintInfo},
+        responses::{
+            bad_request_response, forbidden_response, not_found_response, server_error_response,
+        },
+    },
+    print_file::PrintFile,
+};
+
+lazy_static! {
+    static ref TARGET_REGEX: Regex = Regex::new(r"^[A-Za-z0-9_~\-]{1,8}\.[A-Za-z0-9]{1,3}$").unwrap();
+}
+
+pub const PATH: &str = "/api/sd/upload";
+pub const METHODS: &str = "PUT, DELETE";
+
+pub async fn handler(
+    mut req: Request<Body>,
+    distributor: Sender<EventType>,
+    state: BridgeState,
+) -> Response<Body> {
+    let result = body::to_bytes(req.body_mut()).await.unwrap();
+    let body = match String::from_utf8(result.to_vec()) {
+        Ok(body) => Some(body),
+        Err(e) => {
+            eprintln!("[API][START_SD_UPLOAD] Invalid body received: {}", e);
+            None
+        }
+    };
+    if body.is_none() {
+        return bad_request_response();
+    }
+    let json = serde_json::from_str::<Value>(&body.unwrap());
+    if json.is_err() {
+        return bad_request_response();
+    }
+    let json = json.unwrap();
+    let filename = json.get("printName").and_then(|name| name.as_str());
+    if filename.is_none() {
+        return bad_request_response();
+    }
+    let filename = filename.unwrap().trim();
+    if filename.is_empty() || !filename.ends_with(".gcode") || filename.contains('/') {
+        return bad_request_response();
+    }
+    let target = match json.get("target") {
+        Some(target) => match target.as_str() {
+            Some(target) => target.trim().to_string(),
+            None => return bad_request_response(),
+        },
+        None => short_name(filename),
+    };
+    if !TARGET_REGEX.is_match(&target) {
+        return bad_request_response();
+    }
+    if state != BridgeState::CONNECTED {
+        return forbidden_response();
+    }
+
+    let file = PrintFile::open(&Path::new("./files/").join(filename));
+    if file.is_err() {
+        let err = file.unwrap_err();
+        if err.kind() == ErrorKind::NotFound {
+            return not_found_response();
+        }
+        eprintln!("[API][START_SD_UPLOAD] Cannot open file: {}", err);
+        return server_error_response();
+    }
+
+    let mut info = PrintInfo::new(filename.to_string(), file.unwrap(), Utc::now());
+    info.set_upload(target.to_uppercase());
+    send(&distributor, EventType::SdUploadStart(info));
+
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(
+            header::ACCESS_CONTROL_ALLOW_HEADERS,
+            "Authorization, Content-Type",
+        )
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .status(201)
+        .body(Body::empty())
+        .expect("Failed to construct valid response");
+}
+
+/// Derive an 8.3 name from the name of the file, for example "3DBenchy v2.gcode" => "3DBENCHY.GCO".
+fn short_name(filename: &str) -> String {
+    let stem = filename.trim_end_matches(".gcode");
+    let mut name: String = stem
+        .chars()
+        .filter(|char| char.is_ascii_alphanumeric() || *char == '_' || *char == '-')
+        .take(8)
+        .collect();
+    if name.is_empty() {
+        name = "UPLOAD".to_string();
+    }
+    return format!("{}.GCO", name.to_uppercase());
+}
@@ -0,0 +1,54 @@
+/*
+    List the temperature history of every heater, downsampled to one sample per interval.
+
+    GET /api/temperature/history
+
+    Query:
+        since: Timestamp in milliseconds, only samples starting at this time are returned. (optional)
+
+
+    Permission: -
+    State: -
+*/
+
+use std::sync::Arc;
+
+use hyper::{header, Body, Request, Response};
+use serde_json::json;
+use tokio::sync::Mutex;
+
+use crate::{
+    api_manager::responses::bad_request_response,
+    temperature_history::{TemperatureHistory, SAMPLE_INTERVAL},
+};
+
+pub const PATH: &str = "/api/temperature/history";
+pub const METHODS: &str = "GET";
+
+pub async fn handler(
+    request: Request<Body>,
+    temperature_history: Arc<Mutex<TemperatureHistory>>,
+) -> Response<Body> {
+    let mut since = 0;
+    for parameter in request.uri().query().unwrap_or("").split('&') {
+        if let Some(value) = parameter.strip_prefix("since=") {
+            match value.parse::<i64>() {
+                Ok(value) => since = value,
+                Err(_) => return bad_request_response(),
+            }
+        }
+    }
+
+    let json = json!({
+        "interval": SAMPLE_INTERVAL,
+        "heaters": temperature_history.lock().await.since(since),
+    });
+
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "Authorization")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .body(Body::from(json.to_string()))
+        .expect("Failed to construct valid response");
+}
@@ -20,7 +20,7 @@ use hyper::{body, header, Body, Request, Response};
 use serde_json::{json, Value};
 use uuid::Uuid;
 
-pub const METHODS: &str = "POST";
+pub const METHODS: &str = "GET, POST";
 pub const PATH: &str = "/api/terminal";
 
 pub async fn handler(
@@ -0,0 +1,78 @@
+/*
+    List earlier terminal traffic of a printer, for scrolling back & searching.
+
+    GET /api/terminal
+
+    Query:
+        before: Number of a line, only lines before it are returned. (optional, the latest lines by default)
+        limit: Amount of lines to return, 100 by default & 1000 at most. (optional)
+        search: Only lines containing this text, case insensitive. (optional)
+
+    Lines are returned oldest first, use the line number of the first line as before to get the previous page.
+    Lines older than the in memory log are only available when B_savePrinterNotifications is enabled for the printer.
+
+
+    Permission: terminal.read
+    State: -
+*/
+
+use std::sync::Arc;
+
+use hyper::{header, Body, Request, Response};
+use serde_json::json;
+use tokio::sync::Mutex;
+
+use crate::{
+    api_manager::responses::{bad_request_response, server_error_response},
+    terminal_log::TerminalLog,
+};
+
+pub const PATH: &str = "/api/terminal";
+#[allow(dead_code)]
+pub const METHODS: &str = "GET, POST";
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1_000;
+
+pub async fn handler(
+    request: Request<Body>,
+    printer: u32,
+    terminal_log: Arc<Mutex<TerminalLog>>,
+) -> Response<Body> {
+    let query = request.uri().query().unwrap_or("").as_bytes();
+    let mut before = None;
+    let mut limit = DEFAULT_LIMIT;
+    let mut search = None;
+    for (key, value) in form_urlencoded::parse(query) {
+        match key.as_ref() {
+            "before" => match value.parse::<i64>() {
+                Ok(value) => before = Some(value),
+                Err(_) => return bad_request_response(),
+            },
+            "limit" => match value.parse::<usize>() {
+                Ok(value) if value <= MAX_LIMIT => limit = value,
+                _ => return bad_request_response(),
+            },
+            "search" if value.len() > 0 => search = Some(value.into_owned()),
+            _ => (),
+        }
+    }
+
+    let lines = TerminalLog::before(&terminal_log, printer, before, limit, search.as_deref()).await;
+    let lines = match lines {
+        Ok(lines) => lines,
+        Err(err) => {
+            eprintln!("[API][TERMINAL_LOG] Cannot load terminal log: {}", err);
+            return server_error_response();
+        }
+    };
+    let json = json!({ "lines": lines });
+
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "Authorization")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .body(Body::from(json.to_string()))
+        .expect("Failed to construct valid response");
+}
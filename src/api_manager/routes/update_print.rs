@@ -0,0 +1,97 @@
+/*
+    Pause or resume the current print,
+    or confirm the printer can continue when it waits for the user (filament change, runout).
+
+    PATCH /api/print
+
+    Body: (json)
+        action: "pause" | "resume" | "confirm"
+
+
+    Permission: print_state.edit
+    State: Printing (pause) | Paused (resume) | Waiting (confirm)
+
+    Confirming needs the firmware to support EMERGENCY_PARSER, as it doesn't read new commands while waiting.
+    Otherwise 501 is returned and the user has to confirm on the printer itself.
+*/
+
+use crossbeam_channel::Sender;
+use hyper::{body, header, Body, Request, Response};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::{
+    api_manager::{
+        models::{send, BridgeState, EventType, Message, StateWrapper},
+        responses::{bad_request_response, forbidden_response, not_implemented_response},
+    },
+    firmware::Firmware,
+};
+
+pub const METHODS: &str = "PUT, PATCH, DELETE";
+pub const PATH: &str = "/api/print";
+
+pub async fn handler(
+    mut req: Request<Body>,
+    distributor: Sender<EventType>,
+    state_info: StateWrapper,
+    firmware: Option<Firmware>,
+) -> Response<Body> {
+    let result = body::to_bytes(req.body_mut()).await.unwrap();
+    let body = match String::from_utf8(result.to_vec()) {
+        Ok(body) => Some(body),
+        Err(e) => {
+            eprintln!("[API][UPDATE_PRINT] Invalid body received: {}", e);
+            None
+        }
+    };
+    if body.is_none() {
+        return bad_request_response();
+    }
+
+    let json = serde_json::from_str::<Value>(&body.unwrap());
+    if json.is_err() {
+        return bad_request_response();
+    }
+    let json = json.unwrap();
+    let action = json.get("action").and_then(|action| action.as_str());
+    if action.is_none() {
+        return bad_request_response();
+    }
+
+    match action.unwrap() {
+        "pause" => {
+            if state_info.state != BridgeState::PRINTING {
+                return forbidden_response();
+            }
+            send(&distributor, EventType::PrintPause);
+        }
+        "resume" => {
+            if state_info.state != BridgeState::PAUSED {
+                return forbidden_response();
+            }
+            send(&distributor, EventType::PrintResume);
+        }
+        "confirm" => {
+            if state_info.state != BridgeState::WAITING {
+                return forbidden_response();
+            }
+            if !firmware.map_or(false, |firmware| firmware.supports("EMERGENCY_PARSER")) {
+                return not_implemented_response();
+            }
+            // M108 is handled right away by the firmware, even though it's blocked in the wait.
+            send(
+                &distributor,
+                EventType::OutGoingTerminalMessage(Message::new("M108".to_string(), Uuid::new_v4())),
+            );
+        }
+        _ => return bad_request_response(),
+    }
+
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .body(Body::empty())
+        .expect("Failed to construct valid response");
+}
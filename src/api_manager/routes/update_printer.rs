@@ -0,0 +1,80 @@
+/*
+    Change the name, connection settings or setting overrides of a printer, missing fields are kept.
+    The connection settings are used the next time the printer connects.
+
+    PATCH /api/printers/{id}
+
+    Body: (json)
+        name: String (optional)
+        devicePath: String (optional)
+        baudRate: Number (optional)
+        autoBaud: Boolean (optional)
+        startOnBoot: Boolean (optional)
+        settings: Object (optional), overrides of the settings in printers::PRINTER_SETTINGS by name, null removes an override
+
+
+    Permission: connection.edit
+    State: -
+*/
+
+use hyper::{body, header, Body, Request, Response};
+use serde_json::{json, Value};
+
+use crate::{
+    api_manager::responses::{bad_request_response, not_found_response, server_error_response},
+    printers::{PrinterConfig, Printers},
+};
+
+// the router maps /api/printers/{id} to this path.
+pub const PATH: &str = "/api/printer";
+pub const METHODS: &str = "PATCH, DELETE";
+
+pub async fn handler(mut req: Request<Body>, id: u32, printers: Printers) -> Response<Body> {
+    let result = body::to_bytes(req.body_mut()).await.unwrap();
+    let body = match String::from_utf8(result.to_vec()) {
+        Ok(body) => Some(body),
+        Err(e) => {
+            eprintln!("[API][UPDATE_PRINTER] Invalid body received: {}", e);
+            None
+        }
+    };
+    if body.is_none() {
+        return bad_request_response();
+    }
+    let json = serde_json::from_str::<Value>(&body.unwrap());
+    if json.is_err() {
+        return bad_request_response();
+    }
+    let json = json.unwrap();
+
+    let config = match PrinterConfig::load(id).await {
+        Ok(Some(config)) => config,
+        Ok(None) => return not_found_response(),
+        Err(err) => {
+            eprintln!("[API][UPDATE_PRINTER] Cannot load printer: {}", err);
+            return server_error_response();
+        }
+    };
+    let mut config = config;
+    if !config.update(&json) {
+        return bad_request_response();
+    }
+    if let Err(err) = config.save().await {
+        eprintln!("[API][UPDATE_PRINTER] Cannot store printer: {}", err);
+        return server_error_response();
+    }
+    if let Some(printer) = printers.lock().await.get_mut(&id) {
+        printer.name = config.name.clone();
+    }
+
+    return Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(
+            header::ACCESS_CONTROL_ALLOW_HEADERS,
+            "Authorization, Content-Type",
+        )
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, METHODS)
+        .body(Body::from(json!(config).to_string()))
+        .expect("Failed to construct valid response");
+}
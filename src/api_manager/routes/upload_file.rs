@@ -1,7 +1,7 @@
 /*
     Upload a .gcode file to the files directory.
 
-    ! Cannot upload a file that is currently printing.
+    ! Cannot upload a file that any printer is currently printing.
 
     POST /api/files
     multipart/form-data
@@ -15,20 +15,18 @@ use std::{
     fs::{self, File},
     io::Write,
     path::Path,
-    sync::Arc,
     usize,
 };
 
 use futures::StreamExt;
 use hyper::{header, Body, Request, Response, StatusCode};
 use regex::Regex;
-use tokio::sync::Mutex;
 
-use crate::api_manager::{
-    models::{StateDescription, StateWrapper},
-    responses::{
+use crate::{
+    api_manager::responses::{
         self, bad_request_response, forbidden_response, server_error_response, too_large_response,
     },
+    printers::{file_in_use, Printers},
 };
 use lazy_static::lazy_static;
 
@@ -43,7 +41,7 @@ lazy_static! {
 
 pub async fn handler(
     req: &mut Request<Body>,
-    state_info: Arc<Mutex<StateWrapper>>,
+    printers: Printers,
 ) -> Response<Body> {
     if !req.headers().contains_key("content-type") {
         return bad_request_response();
@@ -107,19 +105,8 @@ pub async fn handler(
                         let capture = captures.unwrap().get(1);
                         if capture.is_some() {
                             let name = capture.unwrap().as_str();
-                            let state = state_info.lock().await;
-                            match &state.description {
-                                StateDescription::Print {
-                                    filename,
-                                    progress: _,
-                                    start: _,
-                                    end: _,
-                                } => {
-                                    if filename == name {
-                                        return forbidden_response();
-                                    }
-                                }
-                                _ => (),
+                            if file_in_use(&printers, name).await {
+                                return forbidden_response();
                             }
 
                             match File::create(Path::new("./files/").join(name)) {
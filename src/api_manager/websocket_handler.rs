@@ -6,13 +6,22 @@ use hyper_tungstenite::tungstenite::{
 };
 use hyper_tungstenite::WebSocketStream;
 use serde_json::{json, Value};
+use chrono::Utc;
+use sqlx::{Connection, SqliteConnection};
 use std::{collections::HashMap, sync::Arc};
 use tokio::{sync::Mutex, task::yield_now};
 use uuid::Uuid;
 
-use crate::api_manager::models::{self, BridgeState};
+use crate::{
+    api_manager::models::{self, BridgeState, SettingRow},
+    print_queue, print_recovery,
+    printers::{PrinterHandle, Printers, DEFAULT_PRINTER},
+};
 
 use super::models::{AuthPermissions, StateWrapper};
+
+// Length of the temperature history sent in the ready event (in milliseconds).
+const READY_TEMPERATURE_HISTORY: i64 = 10 * 60 * 1000;
 /*
     Function gets called by the router after the request has been upgraded to a websocket connection.
     The function keeps loaded as long as a connection is created
@@ -21,15 +30,18 @@ use super::models::{AuthPermissions, StateWrapper};
     Arguments:
     - websocket: The websocket object
     - user: parsed user including permissions.
-    - receiver: Global receiver to catch events related to websockets.
-    - state: current state arc, used for sending intial ready event.
+    - printers: registry of the running printers, their state is sent in the intial ready event.
     - sockets: hashmap including all websocket senders, mapped by uuid.
 
+    The ready event lists every printer (state, the last few minutes of the temperature history, host prompt,
+    link stats, SD card listing & the last N_clientTerminalAmount terminal lines when the user can read the terminal).
+    The state of the default printer is also sent at the top level.
+
 */
 pub async fn handler(
     websocket: WebSocketStream<Upgraded>,
     user: AuthPermissions,
-    state: Arc<Mutex<StateWrapper>>,
+    printers: Printers,
     sockets: Arc<Mutex<HashMap<u128, WebSocketStream<Upgraded>>>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let id = Uuid::new_v4();
@@ -51,6 +63,11 @@ pub async fn handler(
                     "content": {}
     });
     let content = json.get_mut("content").unwrap();
+    let terminal_amount = if *user.terminal_read() {
+        terminal_amount().await
+    } else {
+        0
+    };
     let user = json!({
     "username": user.username(),
     "permissions" : {
@@ -68,50 +85,141 @@ pub async fn handler(
              "update.manage": user.update()
             }
     });
-    let state_info = state.lock().await;
-    let state_info = state_info.clone();
+    *content = json!({ "user": user });
 
-    match state_info.state {
-        BridgeState::DISCONNECTED => {
-            *content = json!({
-                    "user": user,
-                    "state": "Disconnected",
-            });
+    let mut handles: Vec<PrinterHandle> = printers.lock().await.values().cloned().collect();
+    handles.sort_by_key(|printer| printer.id);
+    let since = Utc::now().timestamp_millis() - READY_TEMPERATURE_HISTORY;
+    let mut infos = vec![];
+    for printer in handles.iter() {
+        let (state, description) = describe_state(&*printer.state.lock().await);
+        let jobs = print_queue::list(printer.id).await.unwrap_or_else(|err| {
+            eprintln!("[ERROR][WS] Cannot load the print queue: {}", err);
+            vec![]
+        });
+        // the interrupted print that can be recovered, see GET /api/recovery for the details.
+        let recovery = match print_recovery::load(printer.id).await {
+            Ok(point) => point.map(|point| {
+                json!({
+                    "filename": point.filename,
+                    "line": point.line,
+                    "time": point.time
+                })
+            }),
+            Err(err) => {
+                eprintln!("[ERROR][WS] Cannot load the recovery point: {}", err);
+                None
+            }
+        };
+        infos.push(json!({
+                "id": printer.id,
+                "name": printer.name,
+                "state": state,
+                "description": description,
+                "temperatureHistory": printer.temperature_history.lock().await.since(since),
+                "hostPrompt": *printer.host_prompt.lock().await,
+                "linkStats": *printer.link_stats.lock().await,
+                "sdFiles": *printer.sd_files.lock().await,
+                "queue": {
+                        "jobs": jobs,
+                        "awaitingConfirmation": *printer.queue_confirm.lock().await
+                },
+                "recovery": recovery,
+                "terminal": printer.terminal_log.lock().await.last(terminal_amount),
+        }));
+    }
+    if let Some(info) = infos.iter().find(|info| info["id"] == DEFAULT_PRINTER) {
+        for key in [
+            "state",
+            "description",
+            "temperatureHistory",
+            "hostPrompt",
+            "linkStats",
+            "sdFiles",
+            "queue",
+            "recovery",
+            "terminal",
+        ]
+        .iter()
+        {
+            content[*key] = info[*key].clone();
         }
-        BridgeState::CONNECTING => {
-            *content = json!({
-                    "user": user,
-                    "state": "Connecting",
-            });
+    }
+    content["printers"] = json!(infos);
+
+    let mut guard = sockets.lock().await;
+    let socket = guard.get_mut(&id.as_u128());
+    if socket.is_some() {
+        let socket = socket.unwrap();
+
+        socket
+            .send(Message::text(json.to_string()))
+            .await
+            .expect("Cannot send message");
+    }
+    return Ok(());
+}
+
+/// Get the amount of terminal lines sent to new clients (N_clientTerminalAmount).
+async fn terminal_amount() -> usize {
+    let mut connection = (SqliteConnection::connect("storage.db")).await.unwrap();
+    let query = sqlx::query_as::<_, SettingRow>(
+        "SELECT * FROM settings where id = 'N_clientTerminalAmount'",
+    );
+    match query.fetch_optional(&mut connection).await {
+        Ok(row) => row.and_then(|row| row.number).unwrap_or(500) as usize,
+        Err(err) => {
+            eprintln!("[ERROR][WS] Cannot load N_clientTerminalAmount: {}", err);
+            500
         }
+    }
+}
+
+/*
+    Get the name of the state & its description, like they're sent to the clients.
+*/
+pub fn describe_state(state_info: &StateWrapper) -> (&'static str, Value) {
+    match state_info.state {
+        BridgeState::DISCONNECTED => ("Disconnected", Value::Null),
+        BridgeState::CONNECTING => ("Connecting", Value::Null),
         BridgeState::CONNECTED => {
-            *content = json!({
-                    "user": user,
-                    "state": "Connected",
-            });
+            let description = match state_info.description.clone() {
+                models::StateDescription::Capability {
+                    firmware_name,
+                    firmware_version,
+                    capabilities,
+                } => json!({
+                        "firmware": {
+                                "name": firmware_name,
+                                "version": firmware_version
+                        },
+                        "capabilities": capabilities
+                }),
+                _ => serde_json::Value::Null,
+            };
+            ("Connected", description)
         }
         BridgeState::ERRORED => {
             let description = match state_info.description.clone() {
                 models::StateDescription::Error { message } => message,
                 _ => "Unknown error".to_string(),
             };
-
-            *content = json!({
-                    "user": user,
-                    "state": "Errored",
-                    "description": {
-                            "errorDescription": description
-                    }
-            });
+            (
+                "Errored",
+                json!({
+                        "errorDescription": description
+                }),
+            )
         }
         BridgeState::PREPARING => todo!(),
-        BridgeState::PRINTING => {
+        BridgeState::PRINTING | BridgeState::PAUSED | BridgeState::WAITING => {
             let description = match state_info.description.clone() {
                 models::StateDescription::Print {
                     filename,
                     progress,
                     start,
                     end,
+                    waiting,
                 } => {
                     let mut end_string = None;
                     if end.is_some() {
@@ -125,29 +233,48 @@ pub async fn handler(
                             "progress": format!("{:.2}", progress),
                             "startTime": start.to_rfc3339(),
                             "estEndTime": end_string
+                    },
+                    "waitReason": waiting})
+                }
+                _ => Value::Null,
+            };
+            let state = match state_info.state {
+                BridgeState::PAUSED => "Paused",
+                BridgeState::WAITING => "Waiting",
+                _ => "Printing",
+            };
+            (state, description)
+        }
+        BridgeState::UPLOADING => {
+            let description = match state_info.description.clone() {
+                models::StateDescription::Upload {
+                    filename,
+                    target,
+                    progress,
+                    start,
+                    end,
+                } => {
+                    let mut end_string = None;
+                    if end.is_some() {
+                        end_string = Some(end.unwrap().to_rfc3339());
+                    }
+                    json!({
+                            "uploadInfo": {
+                                    "file": {
+                                            "name": filename,
+                                    },
+                            "target": target,
+                            "progress": format!("{:.2}", progress),
+                            "startTime": start.to_rfc3339(),
+                            "estEndTime": end_string
                     }})
                 }
                 _ => Value::Null,
             };
-            *content = json!({
-                    "user": user,
-                    "state": "Printing",
-                    "description": description
-            });
+            ("Uploading", description)
         }
         BridgeState::FINISHING => todo!(),
-    };
-    let mut guard = sockets.lock().await;
-    let socket = guard.get_mut(&id.as_u128());
-    if socket.is_some() {
-        let socket = socket.unwrap();
-
-        socket
-            .send(Message::text(json.to_string()))
-            .await
-            .expect("Cannot send message");
     }
-    return Ok(());
 }
 
 pub async fn check_incoming_messages(
@@ -0,0 +1,324 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref ACKNOWLEDGED: Regex = Regex::new(r"^ok(\d+)$").unwrap();
+    static ref RESEND: Regex = Regex::new(r"^rs(\d+)$").unwrap();
+    static ref SYNCED: Regex = Regex::new(r"^ss(\d+),(\d+),").unwrap();
+    static ref RESULT: Regex = Regex::new(r"^PFT:(\w+)").unwrap();
+    static ref FATAL: Regex = Regex::new(r"^fe\d*$").unwrap();
+}
+
+// Start of every packet, written little endian.
+pub const HEADER_TOKEN: u16 = 0xB5AD;
+// Size of the header, the payload and its checksum follow it.
+pub const HEADER_SIZE: usize = 8;
+// Protocols & their packet types.
+pub const CONTROL: u8 = 0;
+pub const CONTROL_SYNC: u8 = 1;
+pub const CONTROL_CLOSE: u8 = 2;
+pub const FILE_TRANSFER: u8 = 1;
+pub const FILE_QUERY: u8 = 0;
+pub const FILE_OPEN: u8 = 1;
+pub const FILE_CLOSE: u8 = 2;
+pub const FILE_WRITE: u8 = 3;
+pub const FILE_ABORT: u8 = 4;
+// Payload size used until the firmware reported its buffer size.
+const DEFAULT_BLOCK_SIZE: usize = 512;
+// Resend requests & errors in a row after which the transfer is given up.
+const MAX_RETRIES: u32 = 10;
+
+// M28 B1 switches the firmware to the binary protocol, until the control close packet.
+pub const ENTER_COMMAND: &str = "M28 B1";
+pub const ENTERED_MESSAGE: &str = "Switching to Binary Protocol";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    // M28 B1 is sent, waiting for the firmware to switch.
+    Switching,
+    Syncing,
+    Opening,
+    Writing,
+    Closing,
+    Aborting,
+    // the control close packet is sent, the firmware switches back to G-code once it's acknowledged.
+    Leaving,
+    Finished,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TransferStep {
+    Wait,
+    // write the packet to the printer.
+    Send(Vec<u8>),
+    // the firmware is back to G-code, the transfer is complete, aborted or failed (see BinaryTransfer::error).
+    Finished,
+}
+
+/*
+    Copies a file to the SD card using Marlin's binary file transfer protocol (Cap:BINARY_FILE_TRANSFER),
+    which is a lot faster than writing the file as G-code with M28 / M29.
+
+    Every packet is made of a header (token, sync, protocol & type, payload size, checksum),
+    the payload and a checksum of the whole packet. The firmware acknowledges a packet with ok<sync>
+    and asks for it again with rs<sync>, the sync is incremented for every acknowledged packet.
+    One packet is in flight at a time, the file is written in blocks of the firmware's buffer size.
+
+    The transfer is driven by the responses of the firmware (see handle_response),
+    every step returns the packet to write next.
+*/
+#[derive(Debug)]
+pub struct BinaryTransfer {
+    file: File,
+    target: String,
+    size: u64,
+    // bytes acknowledged by the firmware.
+    written: u64,
+    stage: Stage,
+    sync: u8,
+    block_size: usize,
+    // the packet waiting for its ok & the size of its payload, it's sent again on a resend request.
+    pending: Option<(Vec<u8>, usize)>,
+    retries: u32,
+    aborted: bool,
+    complete: bool,
+    error: Option<String>,
+}
+
+impl BinaryTransfer {
+    pub fn new(file: File, target: String) -> io::Result<Self> {
+        let size = file.metadata()?.len();
+        return Ok(Self {
+            file,
+            target,
+            size,
+            written: 0,
+            stage: Stage::Switching,
+            sync: 0,
+            block_size: DEFAULT_BLOCK_SIZE,
+            pending: None,
+            retries: 0,
+            aborted: false,
+            complete: false,
+            error: None,
+        });
+    }
+
+    /// Get the progress of the transfer in %.
+    pub fn progress(&self) -> f64 {
+        if self.size == 0 {
+            return 0.0;
+        }
+        return self.written as f64 / self.size as f64 * 100.0;
+    }
+
+    /// Check if every byte was written and the file was closed.
+    pub fn is_complete(&self) -> bool {
+        return self.complete;
+    }
+
+    /// Check if the transfer ended, the firmware is back to G-code unless it kept rejecting packets.
+    pub fn is_finished(&self) -> bool {
+        return self.stage == Stage::Finished;
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        return self.aborted;
+    }
+
+    /// Get the reason the transfer failed.
+    pub fn error(&self) -> Option<&String> {
+        return self.error.as_ref();
+    }
+
+    /*
+        Stop the transfer, the file is removed from the SD card.
+        The packet in flight is acknowledged first, an abort before the file is opened only leaves the binary protocol.
+        A file that's being closed is kept, as it's complete.
+    */
+    pub fn abort(&mut self) {
+        self.aborted = true;
+    }
+
+    /*
+        Handle a line received while the transfer runs.
+        Lines that aren't part of the protocol (temperature reports, the ok of M28 B1) are ignored.
+    */
+    pub fn handle_response(&mut self, line: &str) -> TransferStep {
+        let line = line.trim();
+        if self.stage == Stage::Switching {
+            if !line.starts_with(ENTERED_MESSAGE) {
+                return TransferStep::Wait;
+            }
+            self.stage = Stage::Syncing;
+            // the sync packet is the only one the firmware accepts with any sync.
+            return TransferStep::Send(packet(0, CONTROL, CONTROL_SYNC, &[]));
+        }
+        if let Some(captures) = SYNCED.captures(line) {
+            if self.stage != Stage::Syncing {
+                return TransferStep::Wait;
+            }
+            self.sync = captures[1].parse().unwrap_or(0);
+            self.block_size = captures[2].parse().unwrap_or(DEFAULT_BLOCK_SIZE);
+            if self.aborted {
+                return self.leave();
+            }
+            self.stage = Stage::Opening;
+            // no dummy transfer & no compression.
+            let mut payload = vec![0, 0];
+            payload.extend(self.target.as_bytes());
+            payload.push(0);
+            return self.send(FILE_TRANSFER, FILE_OPEN, payload);
+        }
+        if let Some(captures) = ACKNOWLEDGED.captures(line) {
+            let sync: u8 = match captures[1].parse() {
+                Ok(sync) => sync,
+                Err(_) => return TransferStep::Wait,
+            };
+            if self.pending.is_none() || sync != self.sync {
+                return TransferStep::Wait;
+            }
+            let (_, size) = self.pending.take().unwrap();
+            self.sync = self.sync.wrapping_add(1);
+            self.retries = 0;
+            return match self.stage {
+                Stage::Writing => {
+                    self.written += size as u64;
+                    self.write_next()
+                }
+                Stage::Leaving => {
+                    self.stage = Stage::Finished;
+                    TransferStep::Finished
+                }
+                // the result of opening, closing or aborting the file follows the ok.
+                _ => TransferStep::Wait,
+            };
+        }
+        if let Some(captures) = RESEND.captures(line) {
+            let sync: u8 = captures[1].parse().unwrap_or(self.sync);
+            return self.resend(sync);
+        }
+        // the packet was corrupted beyond a resend request.
+        if FATAL.is_match(line) {
+            return self.resend(self.sync);
+        }
+        if let Some(captures) = RESULT.captures(line) {
+            let success = &captures[1] == "success";
+            return match self.stage {
+                Stage::Opening if success => {
+                    self.stage = Stage::Writing;
+                    self.write_next()
+                }
+                Stage::Opening => {
+                    self.error = Some(format!("Cannot create {} on the SD card.", self.target));
+                    self.leave()
+                }
+                // the next block is already in flight, the file is removed once it's acknowledged.
+                Stage::Writing => {
+                    self.error = Some(format!("Cannot write to {} on the SD card.", self.target));
+                    self.aborted = true;
+                    TransferStep::Wait
+                }
+                Stage::Closing if success => {
+                    self.complete = self.error.is_none();
+                    self.leave()
+                }
+                Stage::Closing => {
+                    self.error = Some(format!("Cannot close {} on the SD card.", self.target));
+                    self.leave()
+                }
+                Stage::Aborting => self.leave(),
+                _ => TransferStep::Wait,
+            };
+        }
+        return TransferStep::Wait;
+    }
+
+    /*
+        Send the next block of the file, or close the file once every byte is written.
+        A file that can't be read aborts the transfer.
+    */
+    fn write_next(&mut self) -> TransferStep {
+        if self.aborted {
+            return self.send(FILE_TRANSFER, FILE_ABORT, vec![]);
+        }
+        let mut block = vec![0; self.block_size.min((self.size - self.written) as usize)];
+        if block.len() == 0 {
+            self.stage = Stage::Closing;
+            return self.send(FILE_TRANSFER, FILE_CLOSE, vec![]);
+        }
+        if let Err(err) = self.file.read_exact(&mut block) {
+            self.error = Some(format!("Cannot read the file: {}", err));
+            self.aborted = true;
+            return self.send(FILE_TRANSFER, FILE_ABORT, vec![]);
+        }
+        return self.send(FILE_TRANSFER, FILE_WRITE, block);
+    }
+
+    fn send(&mut self, protocol: u8, packet_type: u8, payload: Vec<u8>) -> TransferStep {
+        if protocol == FILE_TRANSFER && packet_type == FILE_ABORT {
+            self.stage = Stage::Aborting;
+        }
+        let size = if protocol == FILE_TRANSFER && packet_type == FILE_WRITE {
+            payload.len()
+        } else {
+            0
+        };
+        let packet = packet(self.sync, protocol, packet_type, &payload);
+        self.pending = Some((packet.clone(), size));
+        return TransferStep::Send(packet);
+    }
+
+    /// Switch the firmware back to G-code.
+    fn leave(&mut self) -> TransferStep {
+        self.stage = Stage::Leaving;
+        return self.send(CONTROL, CONTROL_CLOSE, vec![]);
+    }
+
+    fn resend(&mut self, sync: u8) -> TransferStep {
+        let packet = match &self.pending {
+            Some((packet, _)) if sync == self.sync => packet.clone(),
+            _ => return TransferStep::Wait,
+        };
+        self.retries += 1;
+        if self.retries > MAX_RETRIES {
+            // the firmware is left in binary mode, it returns to G-code once it's reset.
+            self.error = Some("The printer kept rejecting the transferred data.".to_string());
+            self.stage = Stage::Finished;
+            return TransferStep::Finished;
+        }
+        return TransferStep::Send(packet);
+    }
+}
+
+/// Build a packet, the payload checksum is only added when there's a payload.
+pub fn packet(sync: u8, protocol: u8, packet_type: u8, payload: &[u8]) -> Vec<u8> {
+    let mut packet = vec![];
+    packet.extend(&HEADER_TOKEN.to_le_bytes());
+    packet.push(sync);
+    packet.push(protocol << 4 | packet_type);
+    packet.extend(&(payload.len() as u16).to_le_bytes());
+    let header_checksum = checksum(0, &packet);
+    packet.extend(&header_checksum.to_le_bytes());
+    if payload.len() > 0 {
+        packet.extend(payload);
+        let packet_checksum = checksum(0, &packet);
+        packet.extend(&packet_checksum.to_le_bytes());
+    }
+    return packet;
+}
+
+/// Fletcher-16 checksum, as used by the firmware.
+pub fn checksum(checksum: u16, data: &[u8]) -> u16 {
+    let mut checksum = checksum;
+    for byte in data {
+        let low = ((checksum & 0xFF) + *byte as u16) % 255;
+        checksum = ((((checksum >> 8) + low) % 255) << 8) | low;
+    }
+    return checksum;
+}
@@ -1,93 +1,181 @@
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use crossbeam_channel::{Receiver, Sender};
+use futures::FutureExt;
 use lazy_static::lazy_static;
-use regex::Regex;
-use serialport::{self, SerialPort};
-use std::{collections::VecDeque, io::Write, sync::Arc, time::Duration};
-use tokio::{spawn, sync::Mutex, task::yield_now, time::sleep};
+use regex::{Captures, Regex};
+use serialport;
+use sqlx::{Connection, SqliteConnection};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    future::Future,
+    io::{self, Write},
+    panic::AssertUnwindSafe,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    spawn,
+    sync::Mutex,
+    task::{spawn_blocking, yield_now, JoinHandle},
+    time::sleep,
+};
 use uuid::Uuid;
 
 use crate::{
     api_manager::{
         self,
         models::{
-            send, BridgeAction, BridgeState, EventType, Message, PrintInfo, StateDescription,
-            StateWrapper,
+            self, send, BridgeAction, BridgeState, EventType, HostAction, HostPrompt, Message,
+            LinkError, PauseInfo, PrintInfo, ResendPolicy, SdFile, SdPrint, SettingRow,
+            StateDescription, StateWrapper, WaitReason,
         },
     },
+    binary_transfer::{self, BinaryTransfer, TransferStep},
+    firmware::{Firmware, FirmwareProfile},
     parser::Parser,
+    print_history::{self, PrintJob, PrintOutcome},
+    print_queue,
+    print_recovery::{self, FileState, RecoveryPoint},
+    printers::PrinterConfig,
+    transport::{self, Transport},
+    watchdog::{Watchdog, WatchdogAction, PROBE_ATTEMPTS},
 };
 
+lazy_static! {
+    static ref SCRIPT_VARIABLE: Regex = Regex::new(r"\{([A-Za-z0-9_]+)\}").unwrap();
+}
+
 pub struct Bridge {
     address: String,
     baudrate: u32,
+    receiver: Receiver<EventType>,
+    emergency_port: Arc<Mutex<Option<Box<dyn Transport>>>>,
+    shared: Shared,
+}
+
+/*
+    State of the connection the bridge shares with its manager & the api, it's kept when the bridge stops.
+
+    emergency_port: Separate handle on the connection, written to directly for an emergency stop.
+    firmware: Firmware detected from the M115 response, None until it arrived.
+*/
+#[derive(Clone)]
+pub struct ConnectionState {
+    pub state: Arc<Mutex<StateWrapper>>,
+    pub emergency_port: Arc<Mutex<Option<Box<dyn Transport>>>>,
+    pub firmware: Arc<Mutex<Option<Firmware>>>,
+}
+
+/*
+    State shared by the tasks of a bridge (event listener, serial reader, pollers & watchdog).
+    Cloning the struct shares the state, every field is a channel or an arc.
+*/
+#[derive(Clone)]
+struct Shared {
+    // id of the printer this bridge connects to.
+    printer: u32,
+    distributor: Sender<EventType>,
+    // events for the bridge itself, sent without going through the manager.
+    bridge_sender: Sender<EventType>,
     state: Arc<Mutex<StateWrapper>>,
     print_info: Arc<Mutex<Option<PrintInfo>>>,
-    distributor: Sender<EventType>,
-    sender: Sender<EventType>,
-    receiver: Receiver<EventType>,
+    canceled: Arc<Mutex<bool>>,
     message_queue: Arc<Mutex<VecDeque<Message>>>,
     ready: Arc<Mutex<bool>>,
+    firmware: Arc<Mutex<Option<Firmware>>>,
+    busy: Arc<Mutex<bool>>,
+    watchdog: Arc<Mutex<Watchdog>>,
+    sd_print: Arc<Mutex<Option<SdPrint>>>,
+    // last reported target temperatures, used by the start script.
+    heater_targets: Arc<Mutex<Vec<(String, f64)>>>,
 }
 
-lazy_static! {
-    static ref TOOLTEMPREGEX: Regex = Regex::new(r"((T\d?):([\d\.]+) ?/([\d\.]+))+").unwrap();
-}
 impl Bridge {
     pub fn new(
+        printer: u32,
         distibutor: Sender<EventType>,
         sender: Sender<EventType>,
         receiver: Receiver<EventType>,
         address: String,
         baudrate: u32,
-        state: Arc<Mutex<StateWrapper>>,
+        connection: ConnectionState,
     ) -> Self {
         println!("[BRIDGE] Created new Bridge instance");
         return Self {
             address,
             baudrate,
-            state,
-            print_info: Arc::new(Mutex::new(None)),
-            distributor: distibutor,
-            sender,
             receiver,
-            message_queue: Arc::new(Mutex::new(VecDeque::new())),
-            ready: Arc::new(Mutex::new(true)),
+            emergency_port: connection.emergency_port,
+            shared: Shared {
+                printer,
+                distributor: distibutor,
+                bridge_sender: sender,
+                state: connection.state,
+                print_info: Arc::new(Mutex::new(None)),
+                canceled: Arc::new(Mutex::new(false)),
+                message_queue: Arc::new(Mutex::new(VecDeque::new())),
+                ready: Arc::new(Mutex::new(true)),
+                firmware: connection.firmware,
+                busy: Arc::new(Mutex::new(false)),
+                watchdog: Arc::new(Mutex::new(Watchdog::new())),
+                sd_print: Arc::new(Mutex::new(None)),
+                heater_targets: Arc::new(Mutex::new(vec![])),
+            },
         };
     }
 
     // if port fails, emit failure message to distributor.
     pub async fn start(&mut self) {
-        let is_canceled = Arc::new(Mutex::new(false));
         send(
-            &self.distributor,
+            &self.shared.distributor,
             EventType::StateUpdate(StateWrapper {
                 state: BridgeState::CONNECTING,
                 description: api_manager::models::StateDescription::None,
             }),
         );
 
-        let port_result = serialport::new(&self.address, self.baudrate).open();
+        // Events meant for a previous connection attempt (like KillBridge) shouldn't affect this one.
+        while self.receiver.try_recv().is_ok() {}
+
+        // a baudrate of 0 means the baudrate has to be detected.
+        if self.baudrate == 0 {
+            match Bridge::detect_baudrate(self.shared.printer, &self.address).await {
+                Ok(baudrate) => self.baudrate = baudrate,
+                Err(error) => {
+                    return send(&self.shared.distributor, EventType::CreateBridgeError { error });
+                }
+            }
+        }
+
+        let port_result = transport::open(&self.address, self.baudrate);
 
         if port_result.is_err() {
             let err = port_result.err().unwrap();
 
             match err.kind {
                 serialport::ErrorKind::NoDevice => send(
-                    &self.distributor,
+                    &self.shared.distributor,
+                    EventType::CreateBridgeError {
+                        error: err.description,
+                    },
+                ),
+                // a malformed device path or network address.
+                serialport::ErrorKind::InvalidInput => send(
+                    &self.shared.distributor,
                     EventType::CreateBridgeError {
                         error: err.description,
                     },
                 ),
-                serialport::ErrorKind::InvalidInput => todo!("INV. INPUT"),
                 serialport::ErrorKind::Unknown => send(
-                    &self.distributor,
+                    &self.shared.distributor,
                     EventType::CreateBridgeError {
                         error: err.description,
                     },
                 ),
                 serialport::ErrorKind::Io(_) => send(
-                    &self.distributor,
+                    &self.shared.distributor,
                     EventType::CreateBridgeError {
                         error: err.description,
                     },
@@ -96,112 +184,118 @@ impl Bridge {
             return;
         }
 
-        Bridge::spawn_timeout(10, self.distributor.clone(), self.state.clone());
+        Bridge::spawn_timeout(10, self.shared.distributor.clone(), self.shared.state.clone());
 
         let mut port = port_result.unwrap();
 
         port.set_timeout(Duration::from_millis(10))
             .expect("Cannot set timeout on port");
+        println!("[BRIDGE] Connecting to {} with {} baudrate", port.name(), self.baudrate);
+        // separate handle, so an emergency stop doesn't have to wait for the event listener.
+        *self.emergency_port.lock().await = port.try_clone().ok();
+        // the firmware of a previous connection may have been replaced since.
+        *self.shared.firmware.lock().await = None;
         Bridge::spawn_event_listener(
+            self.shared.clone(),
             port.try_clone().expect("Cannot clone serialport"),
             self.receiver.clone(),
-            self.distributor.clone(),
-            self.print_info.clone(),
-            self.state.clone(),
-            is_canceled.clone(),
-            self.message_queue.clone(),
-            self.ready.clone(),
         );
-        Bridge::spawn_bridge_serial_reader(
-            self.distributor.clone(),
-            self.sender.clone(),
-            self.print_info.clone(),
-            self.state.clone(),
-            is_canceled.clone(),
-            self.message_queue.clone(),
-            self.ready.clone(),
-            port,
+        Bridge::spawn_bridge_serial_reader(self.shared.clone(), port);
+        Bridge::spawn_recovery_saver(
+            self.shared.printer,
+            self.shared.distributor.clone(),
+            self.shared.print_info.clone(),
+            self.shared.canceled.clone(),
+            Bridge::load_seconds(self.shared.printer, "N_recoveryInterval", 5).await,
+        );
+        Bridge::spawn_watchdog(
+            self.shared.distributor.clone(),
+            self.shared.bridge_sender.clone(),
+            self.shared.state.clone(),
+            self.shared.canceled.clone(),
+            self.shared.watchdog.clone(),
+            Bridge::load_seconds(self.shared.printer, "N_communicationTimeout", 30).await,
         );
 
         send(
-            &self.distributor,
+            &self.shared.distributor,
             EventType::OutGoingTerminalMessage(Message::new("M115".to_string(), Uuid::new_v4())),
         );
     }
 
     async fn handle_ok_response(
-        distributor: &Sender<EventType>,
-        bridge_sender: &Sender<EventType>,
+        shared: &Shared,
         collected_responses: &Mutex<Vec<String>>,
         collected: &mut String,
-        state: &Mutex<StateWrapper>,
-        print_info: &Mutex<Option<PrintInfo>>,
-        queue: &Mutex<VecDeque<Message>>,
-        ready: &Mutex<bool>,
+        firmware: &FirmwareProfile,
     ) {
-        let action = Parser::parse_responses(collected_responses.lock().await.clone());
+        let Shared {
+            distributor,
+            bridge_sender,
+            state,
+            print_info,
+            message_queue: queue,
+            ready,
+            ..
+        } = shared;
+        let responses = collected_responses.lock().await.clone();
         *collected_responses.lock().await = vec![];
         *collected = "".to_string();
+        let errors: Vec<LinkError> = responses
+            .iter()
+            .filter_map(|response| Parser::parse_link_error(response))
+            .collect();
+        if errors.len() > 0 {
+            if let Some(info) = print_info.lock().await.as_mut() {
+                for error in errors {
+                    info.report_link_error(error);
+                }
+            }
+        }
+        let action = Parser::parse_responses(responses, firmware);
         match action {
             BridgeAction::Continue(line_number) => {
                 let state = state.lock().await.state;
-                if state.eq(&BridgeState::PRINTING) {
+                if state.eq(&BridgeState::PRINTING)
+                    || state.eq(&BridgeState::PAUSED)
+                    || state.eq(&BridgeState::WAITING)
+                    || state.eq(&BridgeState::UPLOADING)
+                {
                     let mut guard = print_info.lock().await;
                     if guard.is_none() {
                         return;
                     }
                     let print_info = guard.as_mut().unwrap();
-                    let line;
-                    if line_number.is_some() {
-                        let line_number = line_number.unwrap();
-                        line = print_info.get_line_by_index(line_number + 1);
-                        print_info.set_line_number(line_number);
-                    } else if print_info.line_number() == 0 {
-                        line = print_info.get_line_by_index(1);
-                        print_info.set_line_number(1);
-                    } else {
-                        // skip line as it's probably just some unrelated echo without line nr.
+                    // the oks of M110 / M28 arrive before the firmware writes to the file,
+                    // a binary transfer is driven by the acknowledgements of its packets instead.
+                    if print_info.upload.as_ref().map_or(false, |upload| !upload.opened) {
                         return;
                     }
-
-                    if line.is_none() {
-                        send(distributor, EventType::PrintEnd);
-
-                        return;
+                    if line_number.is_some() {
+                        let waiting = print_info.waiting().is_some();
+                        print_info.acknowledge(line_number.unwrap());
+                        if waiting && print_info.waiting().is_none() {
+                            Bridge::send_wait_state(distributor, print_info);
+                        }
                     }
-
-                    let line = line.unwrap();
-                    let prev_progress = format!("{:.1}", print_info.progress());
-
-                    print_info.add_bytes_sent(line.content().len() as u64);
-                    let difference = format!("{:.1}", print_info.progress())
-                        .parse::<f64>()
-                        .unwrap()
-                        - prev_progress.parse::<f64>().unwrap();
-
-                    if difference > 0.1 {
-                        send(
-                            &distributor,
-                            EventType::StateUpdate(StateWrapper {
-                                state: BridgeState::PRINTING,
-                                description: crate::api_manager::models::StateDescription::Print {
-                                    filename: print_info.filename.to_string(),
-                                    progress: print_info.progress(),
-                                    start: print_info.start,
-                                    end: print_info.end,
-                                },
-                            }),
+                    // the start & end scripts are sent one command per ok, before anything else.
+                    if let Some(command) = print_info.script.pop_front() {
+                        return send(
+                            distributor,
+                            EventType::OutGoingTerminalMessage(Message::new(command, Uuid::new_v4())),
                         );
                     }
-
-                    send(
-                        &distributor,
-                        EventType::OutGoingTerminalMessage(Message::new(
-                            Parser::add_checksum(line.line_number(), line.content()),
-                            Uuid::new_v4(),
-                        )),
-                    );
-                    return;
+                    if print_info.ending {
+                        return send(distributor, EventType::PrintEnd);
+                    }
+                    if print_info.is_paused() {
+                        return Bridge::advance_pause(distributor, bridge_sender, print_info);
+                    }
+                    if line_number.is_none() && print_info.last_sent() > print_info.start_line() {
+                        // skip line as it's probably just some unrelated echo without line nr.
+                        return;
+                    }
+                    return Bridge::send_print_lines(distributor, bridge_sender, print_info);
                 } else if state.eq(&BridgeState::CONNECTED) {
                     let message = queue.lock().await.pop_front();
 
@@ -227,137 +321,1284 @@ impl Bridge {
             ),
 
             BridgeAction::Resend(line_number) => {
-                if state.lock().await.state.eq(&BridgeState::PRINTING) {
+                let state = state.lock().await.state;
+                if state.eq(&BridgeState::PRINTING)
+                    || state.eq(&BridgeState::PAUSED)
+                    || state.eq(&BridgeState::WAITING)
+                    || state.eq(&BridgeState::UPLOADING)
+                {
                     let mut guard = print_info.lock().await;
                     if guard.is_none() {
                         return;
                     }
                     let print_info = guard.as_mut().unwrap();
+                    if !print_info.rewind(line_number) {
+                        return;
+                    }
                     print_info.report_resend();
-                    if print_info.get_resend_ratio() > 0.1 {
-                        // TODO: replace this with a notification / setting to ignore this.
-                        return send(&distributor, EventType::StateUpdate(
-                            StateWrapper {
-                                state: BridgeState::ERRORED,
-                                description: StateDescription::Error {message: "Resend ratio went above 10%.\n Consider checking your connection".to_string()},
-                            },
-                        ));
+                    send(&distributor, EventType::LinkStats(print_info.link_stats()));
+                    match print_info.resend_threshold_exceeded() {
+                        Some(ResendPolicy::Abort) => {
+                            return send(&distributor, EventType::StateUpdate(
+                                StateWrapper {
+                                    state: BridgeState::ERRORED,
+                                    description: StateDescription::Error {
+                                        message: format!(
+                                            "Resend ratio went above {}%.\n Consider checking your connection",
+                                            print_info.resend_threshold()
+                                        ),
+                                    },
+                                },
+                            ));
+                        }
+                        Some(ResendPolicy::Warn) => {
+                            let message = format!(
+                                "Resend ratio went above {}%, consider checking your connection.",
+                                print_info.resend_threshold()
+                            );
+                            println!("[BRIDGE][PRINT][WARNING] {}", message);
+                            send(
+                                &distributor,
+                                EventType::Notification {
+                                    reason: "resendRatio".to_string(),
+                                    message,
+                                },
+                            );
+                        }
+                        _ => (),
                     }
-                    let line = print_info.get_line_by_index(line_number);
-                    print_info.set_line_number(line_number);
-                    if line.is_some() {
-                        let line = line.unwrap();
-
-                        send(
-                            &bridge_sender,
-                            EventType::OutGoingTerminalMessage(Message::new(
-                                Parser::add_checksum(line.line_number(), line.content()),
-                                Uuid::new_v4(),
-                            )),
-                        );
-                    } else {
-                        send(
+                    if print_info.is_paused() {
+                        // the line gets sent again once the print is resumed.
+                        return Bridge::advance_pause(distributor, bridge_sender, print_info);
+                    }
+                    let message = match print_info.get_line_by_index(line_number) {
+                        Ok(Some(_)) => None,
+                        Ok(None) => Some("Cannot resend line".to_string()),
+                        Err(err) => Some(format!("Cannot resend line {}: {}", line_number, err)),
+                    };
+                    if let Some(message) = message {
+                        return send(
                             &distributor,
                             EventType::StateUpdate(StateWrapper {
                                 state: BridgeState::ERRORED,
-                                description: StateDescription::Error {
-                                    message: "Cannot resend line".to_string(),
-                                },
+                                description: StateDescription::Error { message },
                             }),
-                        )
+                        );
                     }
+                    Bridge::send_print_lines(distributor, bridge_sender, print_info);
                 }
                 return;
             }
         };
     }
 
-    fn spawn_timeout(
-        timeout_amount: u64,
-        distributor: Sender<EventType>,
-        state: Arc<Mutex<StateWrapper>>,
+    /*
+        Handle a host action command of the firmware (see HostAction).
+        Prompts are collected until prompt_show, and then sent to the clients.
+    */
+    async fn handle_host_action(
+        distributor: &Sender<EventType>,
+        state: &Mutex<StateWrapper>,
+        print_info: &Mutex<Option<PrintInfo>>,
+        prompt: &mut Option<HostPrompt>,
+        action: HostAction,
     ) {
-        spawn(async move {
-            sleep(Duration::from_secs(timeout_amount)).await;
-            if state.lock().await.state == BridgeState::CONNECTING {
+        let current_state = state.lock().await.state;
+        match action {
+            HostAction::Pause => {
+                if current_state.eq(&BridgeState::PRINTING) {
+                    send(distributor, EventType::PrintPause);
+                }
+            }
+            HostAction::Paused => {
+                if current_state.ne(&BridgeState::PRINTING) {
+                    return;
+                }
+                let mut guard = print_info.lock().await;
+                if let Some(info) = guard.as_mut() {
+                    if info.is_paused() || info.waiting().is_some() {
+                        return;
+                    }
+                    // M600 pauses the printer as well, but it continues by itself once the filament is swapped.
+                    if info.pending_filament_change().is_some() {
+                        if info.wait_for_user(WaitReason::FilamentChange) {
+                            Bridge::send_wait_state(distributor, info);
+                        }
+                        return;
+                    }
+                    info.pause = Some(PauseInfo {
+                        by_printer: true,
+                        ..Default::default()
+                    });
+                    println!("[BRIDGE][PRINT] Print paused by printer at line {}", info.line_number());
+                    send(
+                        distributor,
+                        EventType::StateUpdate(StateWrapper {
+                            state: BridgeState::PAUSED,
+                            description: info.state_description(),
+                        }),
+                    );
+                }
+            }
+            HostAction::Runout => {
+                if current_state.ne(&BridgeState::PRINTING) {
+                    return;
+                }
+                if let Some(info) = print_info.lock().await.as_mut() {
+                    if info.wait_for_user(WaitReason::Runout) {
+                        Bridge::send_wait_state(distributor, info);
+                    }
+                }
+            }
+            HostAction::Resume | HostAction::Resumed => {
+                if let Some(info) = print_info.lock().await.as_mut() {
+                    if action == HostAction::Resumed && info.waiting().is_some() {
+                        info.stop_waiting();
+                        return Bridge::send_wait_state(distributor, info);
+                    }
+                }
+                if current_state.eq(&BridgeState::PAUSED) {
+                    send(distributor, EventType::PrintResume);
+                }
+            }
+            HostAction::Cancel => {
+                if current_state.eq(&BridgeState::PRINTING)
+                    || current_state.eq(&BridgeState::PAUSED)
+                    || current_state.eq(&BridgeState::WAITING)
+                {
+                    send(distributor, EventType::PrintEnd);
+                }
+            }
+            HostAction::PromptBegin(message) => {
+                *prompt = Some(HostPrompt {
+                    message,
+                    choices: vec![],
+                });
+            }
+            HostAction::PromptChoice(choice) => {
+                if let Some(prompt) = prompt.as_mut() {
+                    prompt.choices.push(choice);
+                }
+            }
+            HostAction::PromptShow => {
+                if prompt.is_some() {
+                    send(distributor, EventType::HostPrompt(prompt.clone()));
+                }
+            }
+            HostAction::PromptEnd => {
+                *prompt = None;
+                send(distributor, EventType::HostPrompt(None));
+            }
+        }
+    }
+
+    /*
+        Handle a busy: paused for user message, sent while the firmware waits for the user (M0, M1, M600).
+        The message repeats during the wait, so a print that's already waiting only notes the firmware is blocked.
+    */
+    async fn handle_user_wait(
+        distributor: &Sender<EventType>,
+        state: &Mutex<StateWrapper>,
+        print_info: &Mutex<Option<PrintInfo>>,
+    ) {
+        let current_state = state.lock().await.state;
+        if current_state.ne(&BridgeState::PRINTING) && current_state.ne(&BridgeState::WAITING) {
+            return;
+        }
+        let mut guard = print_info.lock().await;
+        if let Some(info) = guard.as_mut() {
+            if info.waiting().is_some() {
+                return info.waiting_confirmed();
+            }
+            let reason = if info.pending_filament_change().is_some() {
+                WaitReason::FilamentChange
+            } else {
+                WaitReason::User
+            };
+            if info.wait_for_user(reason) {
+                Bridge::send_wait_state(distributor, info);
+            }
+        }
+    }
+
+    /// Send the state after the user wait of the print changed, WAITING while it lasts and PRINTING afterwards.
+    fn send_wait_state(distributor: &Sender<EventType>, print_info: &mut PrintInfo) {
+        let state = match print_info.waiting() {
+            Some(reason) => {
+                println!(
+                    "[BRIDGE][PRINT] Printer waits for the user ({:?}) at line {}",
+                    reason,
+                    print_info.line_number() + 1
+                );
+                BridgeState::WAITING
+            }
+            None => {
+                println!("[BRIDGE][PRINT] Printer continues at line {}", print_info.line_number() + 1);
+                BridgeState::PRINTING
+            }
+        };
+        send(
+            distributor,
+            EventType::StateUpdate(StateWrapper {
+                state,
+                description: print_info.state_description(),
+            }),
+        );
+    }
+
+    /*
+        Handle the SD card messages of the firmware: the file list (M20), the file that got opened (M23),
+        the progress of an SD print (M27) and the end of it.
+    */
+    async fn handle_sd_message(
+        printer: u32,
+        distributor: &Sender<EventType>,
+        bridge_sender: &Sender<EventType>,
+        firmware: &Mutex<Option<Firmware>>,
+        sd_print: &Mutex<Option<SdPrint>>,
+        sd_listing: &mut Option<Vec<SdFile>>,
+        message: &str,
+    ) {
+        if message.starts_with("Begin file list") {
+            *sd_listing = Some(vec![]);
+            return;
+        }
+        if message.starts_with("End file list") {
+            if let Some(files) = sd_listing.take() {
+                send(distributor, EventType::SdFiles(files));
+            }
+            return;
+        }
+        if let Some(files) = sd_listing.as_mut() {
+            if let Some(file) = Parser::parse_sd_file(message) {
+                files.push(file);
+            }
+            return;
+        }
+
+        let mut guard = sd_print.lock().await;
+        if guard.is_none() {
+            return;
+        }
+        let sd = guard.as_mut().unwrap();
+        if let Some(size) = Parser::parse_sd_opened(message) {
+            sd.set_size(size);
+        } else if let Some((position, size)) = Parser::parse_sd_progress(message) {
+            let prev_progress = format!("{:.1}", sd.progress());
+            sd.set_position(position, size);
+            if format!("{:.1}", sd.progress()) != prev_progress {
+                sd.end = sd.estimate_end();
                 send(
-                    &distributor,
+                    distributor,
                     EventType::StateUpdate(StateWrapper {
-                        state: BridgeState::ERRORED,
-                        description: api_manager::models::StateDescription::Error {
-                            message: "Timed out".to_string(),
+                        state: if sd.paused {
+                            BridgeState::PAUSED
+                        } else {
+                            BridgeState::PRINTING
                         },
+                        description: sd.state_description(),
                     }),
-                )
+                );
             }
-        });
+        } else if message.starts_with("Done printing file") || message.starts_with("open failed") {
+            let sd = guard.take().unwrap();
+            if message.starts_with("open failed") {
+                eprintln!("[BRIDGE][PRINT][ERROR] Cannot open {} on the SD card", sd.file);
+                let message = format!("Cannot open {} on the SD card.", sd.filename);
+                send(
+                    distributor,
+                    EventType::Notification {
+                        reason: "sdOpenFailed".to_string(),
+                        message: message.clone(),
+                    },
+                );
+                Bridge::log_sd_print_result(printer, &sd, PrintOutcome::Errored, Some(message)).await;
+            } else {
+                Bridge::log_sd_print_result(printer, &sd, PrintOutcome::Finished, None).await;
+            }
+            if sd.auto_report {
+                send(
+                    bridge_sender,
+                    EventType::OutGoingTerminalMessage(Message::new("M27 S0".to_string(), Uuid::new_v4())),
+                );
+            }
+            send(
+                distributor,
+                EventType::StateUpdate(StateWrapper {
+                    state: BridgeState::CONNECTED,
+                    description: Bridge::connected_description(&*firmware.lock().await),
+                }),
+            );
+        }
     }
 
-    fn spawn_bridge_serial_reader(
-        distributor: Sender<EventType>,
-        bridge_sender: Sender<EventType>,
-        print_info: Arc<Mutex<Option<PrintInfo>>>,
-        state: Arc<Mutex<StateWrapper>>,
-        canceled: Arc<Mutex<bool>>,
-        queue: Arc<Mutex<VecDeque<Message>>>,
-        ready: Arc<Mutex<bool>>,
-        mut incoming: Box<dyn SerialPort>,
+    /*
+        Handle the messages of the firmware about the file an upload writes to (M28).
+        The lines are only streamed once the firmware confirmed the file is opened.
+        A binary transfer gets every message, it answers with the next packet (see BinaryTransfer).
+    */
+    async fn handle_upload_message(
+        distributor: &Sender<EventType>,
+        bridge_sender: &Sender<EventType>,
+        firmware: &Mutex<Option<Firmware>>,
+        print_info: &Mutex<Option<PrintInfo>>,
+        message: &str,
     ) {
-        spawn(async move {
-            let mut serial_buf: Vec<u8> = vec![0; 1];
-            let mut collected = String::new();
-            let mut has_collected_capabilities = false;
-            let mut commands_left_to_send: Vec<String> = vec![];
-            let cloned_dist = distributor.clone();
-            let collected_responses: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(vec![]));
-            loop {
-                match incoming.read(serial_buf.as_mut_slice()) {
-                    Ok(t) => {
-                        if *canceled.lock().await {
-                            break;
-                        }
-                        let data = String::from_utf8_lossy(&serial_buf[..t]);
-                        let string = data.into_owned();
-                        if string == "\n" {
-                            if state.lock().await.state.eq(&BridgeState::CONNECTING) {
-                                if !TOOLTEMPREGEX.is_match(&collected) {
-                                    send(
-                                        &distributor,
-                                        EventType::IncomingTerminalMessage(collected.clone()),
-                                    );
-                                }
-                                if collected.to_lowercase().starts_with("error") {
-                                    send(
-                                        &distributor,
-                                        EventType::StateUpdate(StateWrapper {
-                                            state: BridgeState::ERRORED,
-                                            description: StateDescription::Error {
-                                                message: collected.clone(),
-                                            },
-                                        }),
-                                    );
-                                }
-                                if has_collected_capabilities
-                                    && state.lock().await.state.eq(&BridgeState::CONNECTING)
-                                {
-                                    collected = String::new();
-                                    if commands_left_to_send.len() == 0 {
-                                        send(&distributor, EventType::StateUpdate(
-                                            StateWrapper {
-                                                state: BridgeState::CONNECTED,
-                                                description: api_manager::models::StateDescription::Capability {
-                                                    capabilities: collected_responses.lock().await.clone()
-                                                },
-                                            },
-                                        ));
-                                        *collected_responses.lock().await = vec![];
-                                        continue;
-                                    }
+        let mut guard = print_info.lock().await;
+        let opened = match guard.as_ref().and_then(|info| info.upload.as_ref()) {
+            Some(upload) if upload.binary.is_some() => {
+                let info = guard.as_mut().unwrap();
+                return Bridge::handle_transfer_message(distributor, bridge_sender, info, message);
+            }
+            Some(upload) => upload.opened,
+            None => return,
+        };
+        if opened {
+            return;
+        }
+        if message.starts_with("Writing to file") {
+            let info = guard.as_mut().unwrap();
+            println!(
+                "[BRIDGE][UPLOAD] Writing {} to the SD card as {}",
+                info.filename,
+                info.upload.as_ref().unwrap().target
+            );
+            info.upload.as_mut().unwrap().opened = true;
+            Bridge::send_print_lines(distributor, bridge_sender, info);
+        } else if message.starts_with("open failed") {
+            let info = guard.take().unwrap();
+            let target = info.upload.unwrap().target;
+            eprintln!("[BRIDGE][UPLOAD][ERROR] Cannot create {} on the SD card", target);
+            send(
+                distributor,
+                EventType::Notification {
+                    reason: "sdUploadFailed".to_string(),
+                    message: format!("Cannot create {} on the SD card.", target),
+                },
+            );
+            send(
+                distributor,
+                EventType::StateUpdate(StateWrapper {
+                    state: BridgeState::CONNECTED,
+                    description: Bridge::connected_description(&*firmware.lock().await),
+                }),
+            );
+        }
+    }
 
-                                    let command = commands_left_to_send.pop().unwrap();
-                                    send(
-                                        &distributor,
-                                        EventType::OutGoingTerminalMessage(Message::new(
+    /*
+        Pass a message of the firmware to the binary transfer of an upload and write the packet it answers with.
+        The upload ends once the firmware switched back to G-code, a failed transfer is reported to the user.
+    */
+    fn handle_transfer_message(
+        distributor: &Sender<EventType>,
+        bridge_sender: &Sender<EventType>,
+        info: &mut PrintInfo,
+        message: &str,
+    ) {
+        let prev_progress = format!("{:.1}", info.progress());
+        let upload = info.upload.as_mut().unwrap();
+        let transfer = upload.binary.as_mut().unwrap();
+        match transfer.handle_response(message) {
+            TransferStep::Wait => return,
+            TransferStep::Send(packet) => send(bridge_sender, EventType::OutGoingPacket(packet)),
+            TransferStep::Finished => {
+                upload.complete = transfer.is_complete();
+                if let Some(error) = transfer.error() {
+                    eprintln!("[BRIDGE][UPLOAD][ERROR] {}", error);
+                    send(
+                        distributor,
+                        EventType::Notification {
+                            reason: "sdUploadFailed".to_string(),
+                            message: error.clone(),
+                        },
+                    );
+                }
+                return send(distributor, EventType::PrintEnd);
+            }
+        }
+        if format!("{:.1}", info.progress()) != prev_progress {
+            info.end = info.estimate_end();
+            send(
+                distributor,
+                EventType::StateUpdate(StateWrapper {
+                    state: BridgeState::UPLOADING,
+                    description: info.state_description(),
+                }),
+            );
+        }
+    }
+
+    /// Send commands for an SD print, the progress report is turned off as well when the print is aborted.
+    fn send_sd_commands(distributor: &Sender<EventType>, sd: &SdPrint, commands: &[&str]) {
+        for command in commands {
+            send(
+                distributor,
+                EventType::OutGoingTerminalMessage(Message::new(command.to_string(), Uuid::new_v4())),
+            );
+            if *command == "M524" && sd.auto_report {
+                send(
+                    distributor,
+                    EventType::OutGoingTerminalMessage(Message::new("M27 S0".to_string(), Uuid::new_v4())),
+                );
+            }
+        }
+    }
+
+    /*
+        Send the next pending pause / resume command.
+        When a resume was requested and all commands are sent, continue the print at the saved line.
+    */
+    fn advance_pause(
+        distributor: &Sender<EventType>,
+        bridge_sender: &Sender<EventType>,
+        print_info: &mut PrintInfo,
+    ) {
+        let pause = print_info.pause.as_mut().unwrap();
+        let command = pause.commands.pop_front();
+        if command.is_some() {
+            return send(
+                distributor,
+                EventType::OutGoingTerminalMessage(Message::new(command.unwrap(), Uuid::new_v4())),
+            );
+        }
+        if !pause.resuming {
+            return;
+        }
+        print_info.pause = None;
+        send(
+            distributor,
+            EventType::StateUpdate(StateWrapper {
+                state: BridgeState::PRINTING,
+                description: print_info.state_description(),
+            }),
+        );
+
+        // lines that weren't acknowledged before pausing are sent again.
+        print_info.discard_in_flight();
+        Bridge::send_print_lines(distributor, bridge_sender, print_info);
+    }
+
+    /*
+        Send the next lines of the print, until the firmware's buffers are full (see PrintInfo::can_send).
+        Lines are sent to the bridge directly, instead of through the distributor, to keep the firmware's buffer filled.
+        Ends the print once every line is sent and acknowledged,
+        a file that can't be read moves the bridge to ERRORED instead of ending the print.
+    */
+    fn send_print_lines(
+        distributor: &Sender<EventType>,
+        bridge_sender: &Sender<EventType>,
+        print_info: &mut PrintInfo,
+    ) {
+        let prev_progress = format!("{:.1}", print_info.progress());
+        loop {
+            let line = match print_info.get_line_by_index(print_info.last_sent() + 1) {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(err) => {
+                    eprintln!("[BRIDGE][PRINT][ERROR] Cannot read {}: {}", print_info.filename, err);
+                    return send(
+                        distributor,
+                        EventType::StateUpdate(StateWrapper {
+                            state: BridgeState::ERRORED,
+                            description: StateDescription::Error {
+                                message: format!("Cannot read {}: {}", print_info.filename, err),
+                            },
+                        }),
+                    );
+                }
+            };
+            let message = Parser::add_checksum(line.line_number(), line.content());
+            // the newline is part of what's written to the firmware's buffer.
+            if !print_info.can_send(message.len() + 1) {
+                break;
+            }
+            print_info.mark_sent(line, message.len() + 1);
+            send(
+                bridge_sender,
+                EventType::OutGoingTerminalMessage(Message::new(message, Uuid::new_v4())),
+            );
+        }
+        if print_info.in_flight() == 0 {
+            if let Some(upload) = print_info.upload.as_mut() {
+                upload.complete = true;
+            }
+            return send(distributor, EventType::PrintEnd);
+        }
+
+        let difference = format!("{:.1}", print_info.progress())
+            .parse::<f64>()
+            .unwrap()
+            - prev_progress.parse::<f64>().unwrap();
+
+        if difference > 0.1 {
+            print_info.end = print_info.estimate_end();
+            send(
+                &distributor,
+                EventType::StateUpdate(StateWrapper {
+                    state: print_info.streaming_state(),
+                    description: print_info.state_description(),
+                }),
+            );
+        }
+    }
+
+    /*
+        Build the commands that restore the printer to the state it had before pausing:
+        reheat, run the resume script, move back to the saved position and restore the extruder position.
+        The moves change the positioning mode & feedrate, those are restored to what the acknowledged lines
+        of the file set (the script may change them as well).
+    */
+    fn resume_commands(print_info: &PrintInfo, script: Vec<String>) -> VecDeque<String> {
+        let mut commands = Bridge::heat_commands(print_info.heater_targets());
+        commands.extend(script);
+
+        if let Some(position) = print_info.pause.as_ref().and_then(|pause| pause.position) {
+            commands.push_back("G90".to_string());
+            commands.push_back(format!("G1 X{:.3} Y{:.3} F3000", position.x, position.y));
+            commands.push_back(format!("G1 Z{:.3} F600", position.z));
+            commands.push_back(format!("G92 E{:.5}", position.e));
+        }
+        let state = &print_info.file_state;
+        commands.push_back(if state.relative_positioning { "G91" } else { "G90" }.to_string());
+        commands.push_back(if state.relative_extrusion { "M83" } else { "M82" }.to_string());
+        if let Some(feedrate) = state.feedrate {
+            commands.push_back(format!("G1 F{}", feedrate));
+        }
+        return commands;
+    }
+
+    /*
+        Build the commands that continue a recovered print after its start line:
+        lift the nozzle, set the heaters & wait for them, home X & Y, move to the height of the start line
+        and restore the positioning, extrusion & fan state the file set up before it.
+        The printer lost its position, the nozzle is assumed to still be at the last reported height.
+        The start line is the last acknowledged one, moves the firmware still had planned weren't executed
+        and are skipped (see RecoveryPoint).
+    */
+    fn recovery_commands(print_info: &mut PrintInfo) -> VecDeque<String> {
+        let mut state = FileState::new();
+        for index in 1..=print_info.start_line() {
+            match print_info.get_line_by_index(index) {
+                Ok(Some(line)) => state.track(line.content()),
+                _ => break,
+            }
+        }
+        let (heat_commands, wait_commands) = Bridge::heater_commands(print_info.heater_targets());
+        let mut commands = VecDeque::new();
+        commands.push_back(format!("G92 Z{:.3}", print_info.last_z.unwrap_or(state.z)));
+        commands.push_back("G91".to_string());
+        commands.push_back("G1 Z5 F600".to_string());
+        commands.push_back("G90".to_string());
+        // the heaters are only set after lifting, so the nozzle doesn't melt into the print while waiting.
+        commands.extend(heat_commands);
+        commands.extend(wait_commands);
+        commands.push_back("G28 X Y".to_string());
+        commands.push_back(format!("G1 Z{:.3} F600", state.z));
+        if state.relative_positioning {
+            commands.push_back("G91".to_string());
+        }
+        if state.relative_extrusion {
+            commands.push_back("M83".to_string());
+        } else {
+            commands.push_back("M82".to_string());
+        }
+        commands.push_back(format!("G92 E{:.5}", state.e));
+        if state.fan > 0 {
+            commands.push_back(format!("M106 S{}", state.fan));
+        }
+        if let Some(feedrate) = state.feedrate {
+            commands.push_back(format!("G1 F{}", feedrate));
+        }
+        // a pause restores the state of the lines before the start line as well.
+        print_info.file_state = state;
+        return commands;
+    }
+
+    /// Build the commands that heat every heater to its target, the commands that wait for the heaters come last.
+    fn heat_commands(targets: &[(String, f64)]) -> VecDeque<String> {
+        let (mut commands, wait_commands) = Bridge::heater_commands(targets);
+        commands.extend(wait_commands);
+        return commands;
+    }
+
+    /// Build the commands that set every heater to its target & the commands that wait for them, as (set, wait).
+    fn heater_commands(targets: &[(String, f64)]) -> (VecDeque<String>, Vec<String>) {
+        let mut commands = VecDeque::new();
+        let mut wait_commands = vec![];
+        for (name, target) in targets {
+            if *target <= 0.0 {
+                continue;
+            }
+            if name == "B" {
+                commands.push_back(format!("M140 S{}", target));
+                wait_commands.push(format!("M190 S{}", target));
+            } else if name.len() > 1 {
+                commands.push_back(format!("M104 {} S{}", name, target));
+                wait_commands.push(format!("M109 {} S{}", name, target));
+            } else {
+                commands.push_back(format!("M104 S{}", target));
+                wait_commands.push(format!("M109 S{}", target));
+            }
+        }
+        return (commands, wait_commands);
+    }
+
+    /*
+        Load a G-code script stored in the settings, the printer may override it (see load_setting).
+        Comments and empty lines are removed, the variables are filled in (see fill_script_variables).
+        Returns a message for the user when a temperature variable can't be filled in.
+    */
+    async fn load_script(
+        printer: u32,
+        setting: &str,
+        filename: &str,
+        targets: &[(String, f64)],
+    ) -> Result<Vec<String>, String> {
+        let script = match Bridge::load_setting(printer, setting).await {
+            Some(row) => row.raw_value,
+            None => return Ok(vec![]),
+        };
+        let defaults = [
+            ("T", Bridge::load_float(printer, "F_defaultToolTemp", 0.0).await),
+            ("B", Bridge::load_float(printer, "F_defaultBedTemp", 0.0).await),
+        ];
+        let mut lines = vec![];
+        for line in script.lines().filter_map(|line| line.split(';').next()) {
+            let line = Bridge::fill_script_variables(line.trim(), filename, targets, &defaults)
+                .map_err(|variable| {
+                    format!(
+                        "{} uses {{{}}}, but no target temperature is set for it and there's no default temperature (F_default{}Temp).",
+                        setting,
+                        variable,
+                        if variable == "bed_temp" { "Bed" } else { "Tool" }
+                    )
+                })?;
+            if line.len() > 0 {
+                lines.push(line);
+            }
+        }
+        return Ok(lines);
+    }
+
+    /*
+        Load a G-code script that's run while printing (see load_script).
+        A script that can't be filled in is skipped instead of sent with a wrong temperature, the user is notified.
+    */
+    async fn load_script_or_skip(
+        printer: u32,
+        distributor: &Sender<EventType>,
+        setting: &str,
+        filename: &str,
+        targets: &[(String, f64)],
+    ) -> Vec<String> {
+        match Bridge::load_script(printer, setting, filename, targets).await {
+            Ok(script) => script,
+            Err(message) => {
+                eprintln!("[BRIDGE][PRINT][ERROR] Skipping {}: {}", setting, message);
+                send(
+                    distributor,
+                    EventType::Notification {
+                        reason: "scriptSkipped".to_string(),
+                        message,
+                    },
+                );
+                vec![]
+            }
+        }
+    }
+
+    /*
+        Replace the variables of a script line:
+        {filename}: Name of the printed file.
+        {tool_temp} & {bed_temp}: Last known target temperature of the (first heated) tool and the bed.
+        {T0_temp}, {T1_temp}..: Last known target temperature of a specific tool.
+        Heaters without a target use the default temperature of their kind (T or B),
+        returns the name of the variable when there's none either.
+        Unknown variables are kept as they are.
+    */
+    fn fill_script_variables(
+        line: &str,
+        filename: &str,
+        targets: &[(String, f64)],
+        defaults: &[(&str, f64)],
+    ) -> Result<String, String> {
+        let mut missing = None;
+        let line = SCRIPT_VARIABLE
+            .replace_all(line, |captures: &Captures| {
+                let target = |heater: Option<&(String, f64)>, kind: &str| {
+                    heater
+                        .map(|(_, target)| *target)
+                        .filter(|target| *target > 0.0)
+                        .or_else(|| {
+                            defaults
+                                .iter()
+                                .find(|(default, _)| *default == kind)
+                                .map(|(_, target)| *target)
+                                .filter(|target| *target > 0.0)
+                        })
+                };
+                let find = |name: &str| targets.iter().find(|(heater, _)| heater == name);
+                let temperature = match &captures[1] {
+                    "filename" => return filename.to_string(),
+                    "tool_temp" => target(
+                        targets
+                            .iter()
+                            .find(|(heater, target)| heater.starts_with('T') && *target > 0.0),
+                        "T",
+                    ),
+                    "bed_temp" => target(find("B"), "B"),
+                    variable => match variable.strip_suffix("_temp") {
+                        Some(heater) if heater.starts_with('T') => target(find(heater), "T"),
+                        _ => return captures[0].to_string(),
+                    },
+                };
+                match temperature {
+                    Some(temperature) => temperature.to_string(),
+                    None => {
+                        missing = Some(captures[1].to_string());
+                        captures[0].to_string()
+                    }
+                }
+            })
+            .to_string();
+        return match missing {
+            Some(variable) => Err(variable),
+            None => Ok(line),
+        };
+    }
+
+    /*
+        Spawn a task of the bridge, a panic in it errors the connection of this printer only.
+        The panic itself is logged & reported by the hook installed in main.
+    */
+    pub fn spawn_task<F>(distributor: Sender<EventType>, task: F) -> JoinHandle<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        return spawn(async move {
+            if AssertUnwindSafe(task).catch_unwind().await.is_err() {
+                send(
+                    &distributor,
+                    EventType::StateUpdate(StateWrapper {
+                        state: BridgeState::ERRORED,
+                        description: StateDescription::Error {
+                            message: "An internal error occurred\nCheck the logs for more info."
+                                .to_string(),
+                        },
+                    }),
+                );
+            }
+        });
+    }
+
+    fn spawn_timeout(
+        timeout_amount: u64,
+        distributor: Sender<EventType>,
+        state: Arc<Mutex<StateWrapper>>,
+    ) {
+        Bridge::spawn_task(distributor.clone(), async move {
+            sleep(Duration::from_secs(timeout_amount)).await;
+            if state.lock().await.state == BridgeState::CONNECTING {
+                send(
+                    &distributor,
+                    EventType::StateUpdate(StateWrapper {
+                        state: BridgeState::ERRORED,
+                        description: api_manager::models::StateDescription::Error {
+                            message: "Timed out".to_string(),
+                        },
+                    }),
+                )
+            }
+        });
+    }
+
+    /*
+        Send a command every interval while the bridge is in one of the given states,
+        used to request reports the firmware cannot send automatically (M105 temperatures, M114 position).
+        No commands are sent while the firmware is busy, as the command would wait in the firmware's queue.
+        Stops when the bridge is canceled.
+    */
+    fn spawn_poller(
+        distributor: Sender<EventType>,
+        bridge_sender: Sender<EventType>,
+        state: Arc<Mutex<StateWrapper>>,
+        canceled: Arc<Mutex<bool>>,
+        busy: Arc<Mutex<bool>>,
+        command: &'static str,
+        states: &'static [BridgeState],
+        interval: Duration,
+    ) {
+        if interval.as_secs() == 0 {
+            return;
+        }
+        println!("[BRIDGE] Sending {} every {}s", command, interval.as_secs());
+        Bridge::spawn_task(distributor.clone(), async move {
+            loop {
+                sleep(interval).await;
+                if *canceled.lock().await {
+                    break;
+                }
+                if !states.contains(&state.lock().await.state) {
+                    continue;
+                }
+                if *busy.lock().await {
+                    continue;
+                }
+                send(
+                    &bridge_sender,
+                    EventType::OutGoingTerminalMessage(Message::new(
+                        command.to_string(),
+                        Uuid::new_v4(),
+                    )),
+                );
+            }
+        });
+    }
+
+    /*
+        Close the file an upload wrote to (M29) and list the SD card again.
+        An upload that didn't complete is removed from the SD card (M30), so no partial file is left behind.
+        A binary transfer already closed (or removed) the file itself.
+    */
+    fn finish_upload(distributor: &Sender<EventType>, print_info: PrintInfo) {
+        let upload = print_info.upload.unwrap();
+        let binary = upload.binary.is_some();
+        let mut commands = vec![];
+        if !binary {
+            commands.push("M29".to_string());
+        }
+        if upload.complete {
+            println!(
+                "[BRIDGE][UPLOAD] Uploaded {} to the SD card as {} in {}s",
+                print_info.filename,
+                upload.target,
+                Utc::now().signed_duration_since(print_info.start).num_seconds()
+            );
+        } else {
+            println!("[BRIDGE][UPLOAD] Upload of {} canceled", print_info.filename);
+            if !binary {
+                commands.push(format!("M30 {}", upload.target));
+            }
+        }
+        commands.push("M20 L".to_string());
+        for command in commands {
+            send(
+                distributor,
+                EventType::OutGoingTerminalMessage(Message::new(command, Uuid::new_v4())),
+            );
+        }
+    }
+
+    /*
+        Request the progress of the SD print (M27) every interval, for firmware that cannot report it automatically.
+        Nothing is sent while the SD print is paused, stops once it ended or the bridge is canceled.
+    */
+    fn spawn_sd_poller(
+        distributor: Sender<EventType>,
+        bridge_sender: Sender<EventType>,
+        sd_print: Arc<Mutex<Option<SdPrint>>>,
+        canceled: Arc<Mutex<bool>>,
+        busy: Arc<Mutex<bool>>,
+        interval: Duration,
+    ) {
+        if interval.as_secs() == 0 {
+            return;
+        }
+        Bridge::spawn_task(distributor.clone(), async move {
+            loop {
+                sleep(interval).await;
+                if *canceled.lock().await {
+                    break;
+                }
+                match &*sd_print.lock().await {
+                    Some(sd) if !sd.paused => (),
+                    Some(_) => continue,
+                    None => break,
+                }
+                if *busy.lock().await {
+                    continue;
+                }
+                send(
+                    &bridge_sender,
+                    EventType::OutGoingTerminalMessage(Message::new("M27".to_string(), Uuid::new_v4())),
+                );
+            }
+        });
+    }
+
+    /// Load an interval or timeout in seconds from the settings table.
+    async fn load_seconds(printer: u32, setting: &str, default: u64) -> Duration {
+        return Duration::from_secs(Bridge::load_number(printer, setting, default).await);
+    }
+
+    /*
+        Load a number setting from the settings table.
+        Falls back to the default when the setting is missing.
+    */
+    async fn load_number(printer: u32, setting: &str, default: u64) -> u64 {
+        return Bridge::load_setting(printer, setting)
+            .await
+            .and_then(|row| row.number)
+            .unwrap_or(default);
+    }
+
+    /*
+        Load a decimal setting from the settings table.
+        Falls back to the default when the setting is missing.
+    */
+    async fn load_float(printer: u32, setting: &str, default: f64) -> f64 {
+        return Bridge::load_setting(printer, setting)
+            .await
+            .and_then(|row| row.float)
+            .unwrap_or(default);
+    }
+
+    /*
+        Load a boolean setting from the settings table.
+        Falls back to the default when the setting is missing.
+    */
+    async fn load_bool(printer: u32, setting: &str, default: bool) -> bool {
+        return Bridge::load_setting(printer, setting)
+            .await
+            .and_then(|row| row.bool)
+            .unwrap_or(default);
+    }
+
+    /*
+        Start the next job of the queue after a finished print.
+        With B_queueConfirmBedClear set, the user has to confirm the bed is clear first (PUT /api/queue).
+    */
+    async fn continue_queue(printer: u32, distributor: &Sender<EventType>) {
+        match print_queue::list(printer).await {
+            Ok(jobs) if jobs.is_empty() => return,
+            Ok(_) => (),
+            Err(err) => {
+                eprintln!("[BRIDGE][ERROR] Cannot load the print queue: {}", err);
+                return;
+            }
+        }
+        if Bridge::load_bool(printer, "B_queueConfirmBedClear", true).await {
+            println!("[BRIDGE][PRINT][INFO] Waiting for the bed to be cleared");
+            return send(distributor, EventType::QueueConfirm);
+        }
+        if let Err(err) = print_queue::start_next(printer, distributor).await {
+            eprintln!("[BRIDGE][ERROR] Cannot start the next job: {}", err);
+        }
+    }
+
+    /*
+        Detect the baudrate of the printer, starting with the last baudrate that worked.
+        The detected baudrate is saved, so the next connection tries it first.
+    */
+    async fn detect_baudrate(printer: u32, address: &str) -> Result<u32, String> {
+        let config = match PrinterConfig::load(printer).await {
+            Ok(config) => config,
+            Err(err) => return Err(err.to_string()),
+        };
+        let preferred = config
+            .as_ref()
+            .map(|config| config.baud_rate)
+            .filter(|baudrate| *baudrate > 0);
+        let probe_address = address.to_string();
+        let result = tokio::task::spawn_blocking(move || {
+            transport::detect_baudrate(&probe_address, preferred)
+        })
+        .await;
+        let baudrate = match result {
+            Ok(Ok(Some(baudrate))) => baudrate,
+            Ok(Ok(None)) => return Err(format!("No printer answered on {} at any baudrate", address)),
+            Ok(Err(err)) => return Err(err.description),
+            Err(err) => return Err(err.to_string()),
+        };
+        println!("[BRIDGE][BAUDRATE] Printer on {} answered at {} baudrate", address, baudrate);
+
+        if let Some(mut config) = config {
+            if Some(baudrate) != preferred {
+                config.baud_rate = baudrate;
+                if let Err(err) = config.save().await {
+                    eprintln!("[BRIDGE][BAUDRATE] Cannot save the baudrate: {}", err);
+                }
+            }
+        }
+        return Ok(baudrate);
+    }
+
+    /*
+        Load the resend policy & threshold (in %) of the settings table.
+        Unknown policies fall back to aborting the print.
+    */
+    async fn load_resend_policy(printer: u32) -> (ResendPolicy, f64) {
+        let threshold = Bridge::load_setting(printer, "F_resendThreshold")
+            .await
+            .and_then(|row| row.float)
+            .unwrap_or(10.0);
+        let policy = match Bridge::load_setting(printer, "S_resendPolicy").await {
+            Some(row) => match ResendPolicy::from_setting(&row.raw_value) {
+                Some(policy) => policy,
+                None => {
+                    eprintln!(
+                        "[BRIDGE][ERROR] Unknown resend policy {}, aborting prints instead",
+                        row.raw_value
+                    );
+                    ResendPolicy::Abort
+                }
+            },
+            None => ResendPolicy::Abort,
+        };
+        return (policy, threshold);
+    }
+
+    /*
+        Load a setting from the settings table.
+        A value stored for the printer in printer_settings overrides it (see printers::PRINTER_SETTINGS).
+    */
+    async fn load_setting(printer: u32, setting: &str) -> Option<SettingRow> {
+        let mut connection = (SqliteConnection::connect("storage.db")).await.unwrap();
+        let query = sqlx::query_as::<_, SettingRow>(
+            "SELECT s.id, s.type, ifnull(p.value, s.value) as value FROM settings s LEFT JOIN printer_settings p ON p.id = s.id AND p.printer = ? where s.id = ?",
+        )
+        .bind(printer)
+        .bind(setting);
+
+        match query.fetch_optional(&mut connection).await {
+            Ok(row) => row,
+            Err(err) => {
+                eprintln!("[BRIDGE][ERROR] Cannot load {}: {}", setting, err);
+                None
+            }
+        }
+    }
+
+    /*
+        Save the progress of the print every interval, so it can be recovered after a restart (see print_recovery).
+        The file is hashed once per print, to check it didn't change before it's recovered.
+    */
+    fn spawn_recovery_saver(
+        printer: u32,
+        distributor: Sender<EventType>,
+        print_info: Arc<Mutex<Option<PrintInfo>>>,
+        canceled: Arc<Mutex<bool>>,
+        interval: Duration,
+    ) {
+        if interval.as_secs() == 0 {
+            return;
+        }
+        Bridge::spawn_task(distributor.clone(), async move {
+            // filename, start & hash of the current print.
+            let mut hashed: Option<(String, DateTime<Utc>, String)> = None;
+            loop {
+                sleep(interval).await;
+                if *canceled.lock().await {
+                    break;
+                }
+                let current = match print_info.lock().await.as_ref() {
+                    Some(info) if info.upload.is_none() && !info.ending => {
+                        (info.filename.clone(), info.start)
+                    }
+                    _ => continue,
+                };
+                if hashed.as_ref().map(|(filename, start, _)| (filename.clone(), *start))
+                    != Some(current.clone())
+                {
+                    let path = Path::new("./files/").join(&current.0);
+                    let result = spawn_blocking(move || print_recovery::file_hash(&path))
+                        .await
+                        .unwrap_or_else(|err| Err(io::Error::new(io::ErrorKind::Other, err)));
+                    match result {
+                        Ok(hash) => hashed = Some((current.0, current.1, hash)),
+                        Err(err) => {
+                            eprintln!("[BRIDGE][ERROR] Cannot hash {}: {}", current.0, err);
+                            continue;
+                        }
+                    }
+                }
+                // saved while holding the print info, so it can't be saved after the print ended.
+                let mut guard = print_info.lock().await;
+                let info = match guard.as_mut() {
+                    Some(info) if info.start == current.1 && !info.ending => info,
+                    _ => continue,
+                };
+                if info.line_number() == 0 {
+                    continue;
+                }
+                let point = RecoveryPoint {
+                    filename: info.filename.clone(),
+                    hash: hashed.as_ref().unwrap().2.clone(),
+                    line: info.line_number(),
+                    heater_targets: info.heater_targets().clone(),
+                    z: info.last_z,
+                    time: Utc::now().timestamp_millis(),
+                };
+                if let Err(err) = print_recovery::save(printer, &point).await {
+                    eprintln!("[BRIDGE][ERROR] Cannot save the recovery point: {}", err);
+                }
+            }
+        });
+    }
+
+    /*
+        Check every second if the communication with the printer stalled.
+        On a stall, a probe is sent that the firmware has to answer (see Watchdog::check).
+        When the probes stay unanswered as well, the bridge goes to the ERRORED state.
+        A timeout of 0 disables the watchdog.
+    */
+    fn spawn_watchdog(
+        distributor: Sender<EventType>,
+        bridge_sender: Sender<EventType>,
+        state: Arc<Mutex<StateWrapper>>,
+        canceled: Arc<Mutex<bool>>,
+        watchdog: Arc<Mutex<Watchdog>>,
+        timeout: Duration,
+    ) {
+        if timeout.as_secs() == 0 {
+            return;
+        }
+        Bridge::spawn_task(distributor.clone(), async move {
+            loop {
+                sleep(Duration::from_secs(1)).await;
+                if *canceled.lock().await {
+                    break;
+                }
+                let current_state = state.lock().await.state;
+                if current_state.ne(&BridgeState::CONNECTED)
+                    && current_state.ne(&BridgeState::PRINTING)
+                    && current_state.ne(&BridgeState::PAUSED)
+                {
+                    continue;
+                }
+                let action = watchdog.lock().await.check(timeout);
+                match action {
+                    WatchdogAction::Wait => (),
+                    WatchdogAction::Probe { command, attempt } => {
+                        eprintln!(
+                            "[BRIDGE][WATCHDOG] No response for {}s, sending {} (attempt {})",
+                            timeout.as_secs(),
+                            command,
+                            attempt
+                        );
+                        send(
+                            &bridge_sender,
+                            EventType::OutGoingTerminalMessage(Message::new(
+                                command,
+                                Uuid::new_v4(),
+                            )),
+                        );
+                    }
+                    WatchdogAction::Unresponsive => {
+                        eprintln!("[BRIDGE][WATCHDOG] Printer stopped responding");
+                        send(
+                            &distributor,
+                            EventType::StateUpdate(StateWrapper {
+                                state: BridgeState::ERRORED,
+                                description: StateDescription::Error {
+                                    message: format!(
+                                        "Printer stopped responding.\n No response for {}s, check the connection and reset the printer.",
+                                        timeout.as_secs() * (PROBE_ATTEMPTS as u64 + 1)
+                                    ),
+                                },
+                            }),
+                        );
+                        break;
+                    }
+                }
+            }
+        });
+    }
+
+    fn spawn_bridge_serial_reader(shared: Shared, mut incoming: Box<dyn Transport>) {
+        Bridge::spawn_task(shared.distributor.clone(), async move {
+            let Shared {
+                printer,
+                distributor,
+                bridge_sender,
+                print_info,
+                state,
+                canceled,
+                message_queue: queue,
+                ready,
+                firmware,
+                busy,
+                watchdog,
+                sd_print,
+                heater_targets,
+                ..
+            } = shared.clone();
+            let mut serial_buf: Vec<u8> = vec![0; 1];
+            let mut collected = String::new();
+            let mut has_collected_capabilities = false;
+            let mut commands_left_to_send: Vec<String> = vec![];
+            let mut profile = FirmwareProfile::generic();
+            let mut prompt: Option<HostPrompt> = None;
+            let mut sd_listing: Option<Vec<SdFile>> = None;
+            let cloned_dist = distributor.clone();
+            let collected_responses: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(vec![]));
+            loop {
+                match incoming.read(serial_buf.as_mut_slice()) {
+                    Ok(t) => {
+                        if *canceled.lock().await {
+                            break;
+                        }
+                        let data = String::from_utf8_lossy(&serial_buf[..t]);
+                        let string = data.into_owned();
+                        if string == "\n" {
+                            let report = !collected.starts_with("ok")
+                                && (Parser::is_temperature_report(&collected)
+                                    || Parser::parse_position(&collected).is_some());
+                            let acknowledged = profile
+                                .ok
+                                .captures(&collected)
+                                .and_then(|captures| captures[1].parse().ok());
+                            watchdog.lock().await.received(&collected, acknowledged, report);
+                            if state.lock().await.state.eq(&BridgeState::CONNECTING) {
+                                if !Parser::is_temperature_report(&collected) {
+                                    send(
+                                        &distributor,
+                                        EventType::IncomingTerminalMessage(collected.clone()),
+                                    );
+                                }
+                                if collected.to_lowercase().starts_with("error") {
+                                    send(
+                                        &distributor,
+                                        EventType::StateUpdate(StateWrapper {
+                                            state: BridgeState::ERRORED,
+                                            description: StateDescription::Error {
+                                                message: collected.clone(),
+                                            },
+                                        }),
+                                    );
+                                }
+                                if has_collected_capabilities
+                                    && state.lock().await.state.eq(&BridgeState::CONNECTING)
+                                {
+                                    collected = String::new();
+                                    if commands_left_to_send.len() == 0 {
+                                        send(&distributor, EventType::StateUpdate(
+                                            StateWrapper {
+                                                state: BridgeState::CONNECTED,
+                                                description: Bridge::connected_description(&*firmware.lock().await),
+                                            },
+                                        ));
+                                        // the listing is only parsed once connected, so it's requested last.
+                                        if let Some(detected) = &*firmware.lock().await {
+                                            if detected.supports("SDCARD") {
+                                                send(
+                                                    &distributor,
+                                                    EventType::OutGoingTerminalMessage(Message::new(
+                                                        "M20 L".to_string(),
+                                                        Uuid::new_v4(),
+                                                    )),
+                                                );
+                                            }
+                                        }
+                                        *collected_responses.lock().await = vec![];
+                                        continue;
+                                    }
+
+                                    let command = commands_left_to_send.pop().unwrap();
+                                    send(
+                                        &distributor,
+                                        EventType::OutGoingTerminalMessage(Message::new(
                                             command.clone(),
                                             Uuid::new_v4(),
                                         )),
@@ -370,9 +1611,8 @@ impl Bridge {
                                     if collected_responses.lock().await.len() == 0 {
                                         continue;
                                     }
-                                    if !collected_responses.lock().await[0]
-                                        .starts_with("FIRMWARE_NAME:Marlin")
-                                    {
+                                    let detected = Firmware::detect(&collected_responses.lock().await);
+                                    if detected.is_none() {
                                         *collected_responses.lock().await = vec![];
                                         send(
                                             &distributor,
@@ -382,27 +1622,54 @@ impl Bridge {
                                             )),
                                         );
                                     } else {
-                                        for cap in &*collected_responses.lock().await {
-                                            // println!("[BRIDGE][CAP] => {}", cap);
-
-                                            if cap.contains("Cap:AUTOREPORT_TEMP:1") {
-                                                commands_left_to_send.push("M155 S2".to_string());
-                                            } else {
-                                                // TODO: ADD TEMP REPORTING.
-                                                // let is_canceled = canceled.clone();
-                                                // spawn(async move {
-                                                //     loop {
-                                                //         if *is_canceled.lock().await {
-                                                //             break;
-                                                //         }
-                                                //         std::thread::sleep(Duration::from_secs(2));
-                                                //     }
-                                                // });
-                                            }
-                                            if cap.contains("Cap:EEPROM:1") {
-                                                commands_left_to_send.push("M501".to_string())
-                                            }
+                                        let detected = detected.unwrap();
+                                        println!(
+                                            "[BRIDGE] Detected firmware: {} {}",
+                                            detected.name,
+                                            detected.version.as_deref().unwrap_or("(unknown version)")
+                                        );
+
+                                        if detected.supports("AUTOREPORT_TEMP") {
+                                            commands_left_to_send.push("M155 S2".to_string());
+                                        } else {
+                                            Bridge::spawn_poller(
+                                                distributor.clone(),
+                                                bridge_sender.clone(),
+                                                state.clone(),
+                                                canceled.clone(),
+                                                busy.clone(),
+                                                "M105",
+                                                &[
+                                                    BridgeState::CONNECTED,
+                                                    BridgeState::PRINTING,
+                                                    BridgeState::PAUSED,
+                                                    BridgeState::WAITING,
+                                                ],
+                                                Bridge::load_seconds(printer, "N_tempPollInterval", 2)
+                                                    .await,
+                                            );
                                         }
+                                        if detected.supports("AUTOREPORT_POSITION") {
+                                            commands_left_to_send.push("M154 S1".to_string());
+                                        } else {
+                                            // the ok of M114 would continue a pause before its own commands are done.
+                                            Bridge::spawn_poller(
+                                                distributor.clone(),
+                                                bridge_sender.clone(),
+                                                state.clone(),
+                                                canceled.clone(),
+                                                busy.clone(),
+                                                "M114",
+                                                &[BridgeState::CONNECTED, BridgeState::PRINTING],
+                                                Bridge::load_seconds(printer, "N_positionPollInterval", 2)
+                                                    .await,
+                                            );
+                                        }
+                                        if detected.supports("EEPROM") {
+                                            commands_left_to_send.push("M501".to_string())
+                                        }
+                                        profile = detected.profile();
+                                        *firmware.lock().await = Some(detected);
                                         send(
                                             &distributor,
                                             EventType::OutGoingTerminalMessage(Message::new(
@@ -418,10 +1685,43 @@ impl Bridge {
                                     collected_responses.lock().await.push(collected);
                                 }
                             } else {
-                                if TOOLTEMPREGEX.is_match(&collected) {
+                                if collected.contains("busy:") {
+                                    *busy.lock().await = true;
+                                    if collected.contains("paused for user") {
+                                        Bridge::handle_user_wait(&distributor, &state, &print_info)
+                                            .await;
+                                    }
+                                } else if collected.starts_with("ok") {
+                                    *busy.lock().await = false;
+                                    if let Some(free) = Parser::parse_buffer(&collected) {
+                                        if let Some(info) = print_info.lock().await.as_mut() {
+                                            info.report_buffer(free);
+                                        }
+                                    }
+                                }
+                                if Parser::is_temperature_report(&collected) {
                                     let temp_info = Parser::parse_temperature(&collected);
+                                    if let EventType::TempUpdate { tools, bed, .. } = &temp_info {
+                                        let targets = models::heater_targets(tools, bed);
+                                        if let Some(info) = print_info.lock().await.as_mut() {
+                                            if !info.is_paused() {
+                                                info.set_heater_targets(targets.clone());
+                                            }
+                                        }
+                                        *heater_targets.lock().await = targets;
+                                    }
 
                                     send(&cloned_dist, temp_info);
+                                } else if let Some(position) = Parser::parse_position(&collected) {
+                                    if let Some(info) = print_info.lock().await.as_mut() {
+                                        info.last_z = Some(position.z);
+                                        if let Some(pause) = info.pause.as_mut() {
+                                            if pause.position.is_none() {
+                                                pause.position = Some(position);
+                                            }
+                                        }
+                                    }
+                                    send(&cloned_dist, EventType::PositionUpdate(position));
                                 } else {
                                     println!("[BRIDGE][RECV] {}", collected);
                                     collected_responses.lock().await.push(collected.clone());
@@ -430,18 +1730,45 @@ impl Bridge {
                                         &distributor,
                                         EventType::IncomingTerminalMessage(collected.clone()),
                                     );
+                                    Bridge::handle_sd_message(
+                                        shared.printer,
+                                        &distributor,
+                                        &bridge_sender,
+                                        &firmware,
+                                        &sd_print,
+                                        &mut sd_listing,
+                                        &collected,
+                                    )
+                                    .await;
+                                    Bridge::handle_upload_message(
+                                        &distributor,
+                                        &bridge_sender,
+                                        &firmware,
+                                        &print_info,
+                                        &collected,
+                                    )
+                                    .await;
+                                    if let Some(action) = Parser::parse_action(&collected) {
+                                        Bridge::handle_host_action(
+                                            &distributor,
+                                            &state,
+                                            &print_info,
+                                            &mut prompt,
+                                            action,
+                                        )
+                                        .await;
+                                    }
                                 }
 
-                                if collected.starts_with("ok") {
+                                // the response to a polled M105 doesn't acknowledge any other command.
+                                if collected.starts_with("ok")
+                                    && !Parser::is_temperature_report(&collected)
+                                {
                                     Bridge::handle_ok_response(
-                                        &cloned_dist,
-                                        &bridge_sender,
+                                        &shared,
                                         &collected_responses,
                                         &mut collected,
-                                        &state,
-                                        &print_info,
-                                        &queue,
-                                        &ready,
+                                        profile,
                                     )
                                     .await;
                                 }
@@ -499,42 +1826,59 @@ impl Bridge {
     }
 
     fn spawn_event_listener(
-        mut outgoing: Box<dyn SerialPort>,
-        receiver: Receiver<EventType>,
-        distributor: Sender<EventType>,
-        print_info: Arc<Mutex<Option<PrintInfo>>>,
-        state_info: Arc<Mutex<StateWrapper>>,
-        canceled: Arc<Mutex<bool>>,
-        queue: Arc<Mutex<VecDeque<Message>>>,
-        ready: Arc<Mutex<bool>>,
+        shared: Shared,
+        mut outgoing: Box<dyn Transport>,
+        receiver: Receiver<EventType>,
     ) {
-        spawn(async move {
-            let panic_sender_clone = distributor.clone();
-            std::panic::set_hook(Box::new(move |e| {
-                println!("[BRIDGE][PANIC] {}", e);
-                let msg = format!("{}", e);
-                sentry::capture_message(&msg, sentry::Level::Error);
-
-                send(
-                    &panic_sender_clone,
-                    EventType::StateUpdate(StateWrapper {
-                        state: BridgeState::ERRORED,
-                        description: StateDescription::Error {
-                            message: "An internal error occurred\nCheck the logs for more info."
-                                .to_string(),
-                        },
-                    }),
-                );
-            }));
-            println!(
-                "[BRIDGE] Connecting to port {} with {} baudrate",
-                outgoing.as_ref().name().unwrap_or("UNNAMED".to_string()),
-                outgoing.as_ref().baud_rate().unwrap()
-            );
+        Bridge::spawn_task(shared.distributor.clone(), async move {
+            let Shared {
+                printer,
+                distributor,
+                bridge_sender,
+                print_info,
+                state: state_info,
+                canceled,
+                message_queue: queue,
+                ready,
+                firmware,
+                busy,
+                watchdog,
+                sd_print,
+                heater_targets,
+            } = shared;
             loop {
                 if let Ok(event) = receiver.try_recv() {
                     match event {
                         EventType::KillBridge => {
+                            // a print that's still running when the bridge stops has errored (or was disconnected).
+                            let info = print_info.lock().await.take();
+                            if let Some(mut info) = info.filter(|info| info.upload.is_none()) {
+                                let reason = match &state_info.lock().await.description {
+                                    StateDescription::Error { message } => message.clone(),
+                                    _ => "Disconnected".to_string(),
+                                };
+                                Bridge::log_print_result(
+                                    printer,
+                                    &mut info,
+                                    PrintOutcome::Errored,
+                                    Some(reason),
+                                )
+                                .await;
+                            }
+                            let sd = sd_print.lock().await.take();
+                            if let Some(sd) = sd {
+                                let reason = match &state_info.lock().await.description {
+                                    StateDescription::Error { message } => message.clone(),
+                                    _ => "Disconnected".to_string(),
+                                };
+                                Bridge::log_sd_print_result(
+                                    printer,
+                                    &sd,
+                                    PrintOutcome::Errored,
+                                    Some(reason),
+                                )
+                                .await;
+                            }
                             drop(outgoing);
                             *canceled.lock().await = true;
                             break;
@@ -552,6 +1896,8 @@ impl Bridge {
                                     *ready.lock().await = true;
                                 }
                             }
+                            // registered before writing, as the response can arrive before the write returns.
+                            watchdog.lock().await.sent(&message.content);
                             let result = outgoing.write(message.content.as_bytes());
                             if result.is_err() {
                                 let err = result.unwrap_err();
@@ -569,30 +1915,176 @@ impl Bridge {
                                 println!("[BRIDGE][SEND] {}", message.content.trim());
                             }
                         }
+                        EventType::OutGoingPacket(packet) => {
+                            if let Err(err) = outgoing.write_all(&packet) {
+                                eprintln!("[BRIDGE][ERROR] {}", err);
+                                send(
+                                    &distributor,
+                                    EventType::StateUpdate(StateWrapper {
+                                        state: BridgeState::ERRORED,
+                                        description: StateDescription::Error {
+                                            message: err.to_string(),
+                                        },
+                                    }),
+                                );
+                            }
+                        }
                         EventType::PrintEnd => {
-                            if state_info.lock().await.state.ne(&BridgeState::PRINTING) {
-                                return ();
+                            let state = state_info.lock().await.state;
+                            if state.ne(&BridgeState::PRINTING)
+                                && state.ne(&BridgeState::PAUSED)
+                                && state.ne(&BridgeState::WAITING)
+                                && state.ne(&BridgeState::UPLOADING)
+                            {
+                                continue;
+                            }
+                            if state.eq(&BridgeState::UPLOADING) {
+                                // a binary transfer has to remove the file & leave the binary protocol first,
+                                // another PrintEnd while it does ends the upload right away.
+                                if let Some(transfer) = print_info
+                                    .lock()
+                                    .await
+                                    .as_mut()
+                                    .and_then(|info| info.upload.as_mut())
+                                    .and_then(|upload| upload.binary.as_mut())
+                                {
+                                    if !transfer.is_finished() && !transfer.is_aborted() {
+                                        transfer.abort();
+                                        continue;
+                                    }
+                                }
+                                if let Some(info) = print_info.lock().await.take() {
+                                    Bridge::finish_upload(&distributor, info);
+                                }
+                                send(
+                                    &distributor,
+                                    EventType::StateUpdate(StateWrapper {
+                                        state: BridgeState::CONNECTED,
+                                        description: Bridge::connected_description(&*firmware.lock().await),
+                                    }),
+                                );
+                                continue;
+                            }
+                            // the end (or cancel) script is sent first, the print ends once it's acknowledged.
+                            // another PrintEnd while the script is sent ends the print right away.
+                            if let Some(info) = print_info.lock().await.as_mut() {
+                                if !info.ending {
+                                    info.ending = true;
+                                    info.finished = info.is_finished();
+                                    let setting = if info.finished {
+                                        "S_endGcode"
+                                    } else {
+                                        "S_cancelGcode"
+                                    };
+                                    info.script = Bridge::load_script_or_skip(
+                                        printer,
+                                        &distributor,
+                                        setting,
+                                        &info.filename,
+                                        info.heater_targets(),
+                                    )
+                                    .await
+                                    .into();
+                                    if let Some(command) = info.script.pop_front() {
+                                        send(
+                                            &distributor,
+                                            EventType::OutGoingTerminalMessage(Message::new(
+                                                command,
+                                                Uuid::new_v4(),
+                                            )),
+                                        );
+                                        continue;
+                                    }
+                                }
+                            }
+                            // the firmware handles aborting SD prints itself (M524).
+                            let sd = sd_print.lock().await.take();
+                            if let Some(sd) = sd.as_ref() {
+                                Bridge::send_sd_commands(&distributor, sd, &["M524"]);
+                                Bridge::log_sd_print_result(printer, sd, PrintOutcome::Cancelled, None)
+                                    .await;
+                            }
+                            let mut info = print_info.lock().await.take();
+                            if let Some(info) = info.as_mut() {
+                                let outcome = if info.finished {
+                                    PrintOutcome::Finished
+                                } else {
+                                    PrintOutcome::Cancelled
+                                };
+                                Bridge::log_print_result(printer, info, outcome, None).await;
+                                send(&distributor, EventType::LinkStats(info.link_stats()));
+                                if let Err(err) = print_recovery::clear(printer).await {
+                                    eprintln!("[BRIDGE][ERROR] Cannot remove the recovery point: {}", err);
+                                }
+                            }
+                            // the print already ended, the state update just didn't arrive yet.
+                            if info.is_none() && sd.is_none() {
+                                continue;
                             }
-                            Bridge::log_print_result(&mut *print_info.lock().await);
-                            *print_info.lock().await = None;
                             send(
                                 &distributor,
                                 EventType::StateUpdate(StateWrapper {
                                     state: BridgeState::CONNECTED,
-                                    description: api_manager::models::StateDescription::None,
+                                    description: Bridge::connected_description(&*firmware.lock().await),
                                 }),
                             );
+                            // finished prints continue with the next job of the queue.
+                            if info.map_or(false, |info| info.finished)
+                                && Bridge::load_bool(printer, "B_queueAutoStart", false).await
+                            {
+                                Bridge::continue_queue(printer, &distributor).await;
+                            }
                         }
 
                         EventType::PrintStart(info) => {
-                            if state_info.lock().await.state.ne(&BridgeState::CONNECTED) {
-                                return ();
+                            // the state is updated by the manager, a print started right before may not show yet.
+                            if state_info.lock().await.state.ne(&BridgeState::CONNECTED)
+                                || print_info.lock().await.is_some()
+                                || sd_print.lock().await.is_some()
+                            {
+                                println!("[BRIDGE][PRINT] Printer is busy, not starting {}", info.filename);
+                                continue;
+                            }
+                            let mut info = info;
+                            // the start script is sent after M110, before the first line of the file.
+                            // a recovered print restores the printer instead, the targets are the saved ones.
+                            if info.start_line() > 0 {
+                                info.script = Bridge::recovery_commands(&mut info);
+                            } else {
+                                let targets = heater_targets.lock().await.clone();
+                                match Bridge::load_script(printer, "S_startGcode", &info.filename, &targets).await {
+                                    Ok(script) => info.script = script.into(),
+                                    Err(message) => {
+                                        eprintln!("[BRIDGE][PRINT][ERROR] Not starting {}: {}", info.filename, message);
+                                        send(
+                                            &distributor,
+                                            EventType::Notification {
+                                                reason: "printNotStarted".to_string(),
+                                                message,
+                                            },
+                                        );
+                                        continue;
+                                    }
+                                }
+                                info.set_heater_targets(targets);
                             }
+                            if let Some(job) = info.queue_job {
+                                if let Err(err) = print_queue::remove(printer, job).await {
+                                    eprintln!("[BRIDGE][ERROR] Cannot remove the started job from the queue: {}", err);
+                                }
+                                send(&distributor, EventType::QueueUpdate);
+                            }
+                            info.set_buffer_size(Bridge::load_number(printer, "N_bufferSize", 0).await as usize);
+                            info.set_rx_buffer(Bridge::load_number(printer, "N_rxBufferSize", 128).await as usize);
+                            let (policy, threshold) = Bridge::load_resend_policy(printer).await;
+                            info.set_resend_policy(policy, threshold);
+                            send(&distributor, EventType::LinkStats(info.link_stats()));
                             let mut guard = print_info.lock().await;
                             let filename = info.filename.clone();
                             let progress = info.progress();
                             let start = info.start.clone();
                             let end = info.end.clone();
+                            let reset = format!("M110 N{}", info.start_line());
 
                             *guard = Some(info);
 
@@ -605,17 +2097,237 @@ impl Bridge {
                                         progress,
                                         start,
                                         end,
+                                        waiting: None,
                                     },
                                 }),
                             );
                             send(
                                 &distributor,
                                 EventType::OutGoingTerminalMessage(Message::new(
-                                    "M110 N0".to_string(),
+                                    reset,
                                     Uuid::new_v4(),
                                 )),
                             );
                         }
+                        EventType::SdUploadStart(info) => {
+                            if state_info.lock().await.state.ne(&BridgeState::CONNECTED) {
+                                continue;
+                            }
+                            let mut info = info;
+                            if info.upload.is_none() {
+                                continue;
+                            }
+                            info.set_buffer_size(Bridge::load_number(printer, "N_bufferSize", 0).await as usize);
+                            info.set_rx_buffer(Bridge::load_number(printer, "N_rxBufferSize", 128).await as usize);
+                            let (policy, threshold) = Bridge::load_resend_policy(printer).await;
+                            info.set_resend_policy(policy, threshold);
+                            let supports_binary = match &*firmware.lock().await {
+                                Some(firmware) => firmware.supports("BINARY_FILE_TRANSFER"),
+                                None => false,
+                            };
+                            let upload = info.upload.as_mut().unwrap();
+                            let mut commands = vec!["M110 N0".to_string(), format!("M28 {}", upload.target)];
+                            if supports_binary {
+                                let path = Path::new("./files/").join(&info.filename);
+                                match File::open(path)
+                                    .and_then(|file| BinaryTransfer::new(file, upload.target.clone()))
+                                {
+                                    Ok(transfer) => {
+                                        upload.binary = Some(transfer);
+                                        commands = vec![binary_transfer::ENTER_COMMAND.to_string()];
+                                        println!(
+                                            "[BRIDGE][UPLOAD] Writing {} to the SD card as {} using the binary protocol",
+                                            info.filename, upload.target
+                                        );
+                                    }
+                                    Err(err) => eprintln!(
+                                        "[BRIDGE][UPLOAD][ERROR] Cannot open {} for a binary transfer, sending it as G-code: {}",
+                                        info.filename, err
+                                    ),
+                                }
+                            }
+                            send(
+                                &distributor,
+                                EventType::StateUpdate(StateWrapper {
+                                    state: BridgeState::UPLOADING,
+                                    description: info.state_description(),
+                                }),
+                            );
+                            *print_info.lock().await = Some(info);
+                            for command in commands.iter() {
+                                send(
+                                    &distributor,
+                                    EventType::OutGoingTerminalMessage(Message::new(
+                                        command.to_string(),
+                                        Uuid::new_v4(),
+                                    )),
+                                );
+                            }
+                        }
+                        EventType::SdPrintStart {
+                            file,
+                            filename,
+                            user,
+                        } => {
+                            if state_info.lock().await.state.ne(&BridgeState::CONNECTED) {
+                                continue;
+                            }
+                            let auto_report = match &*firmware.lock().await {
+                                Some(firmware) => firmware.supports("AUTOREPORT_SD_STATUS"),
+                                None => false,
+                            };
+                            let interval = Bridge::load_seconds(printer, "N_sdPollInterval", 2).await;
+                            let mut sd = SdPrint::new(file, filename, auto_report);
+                            sd.user = user;
+                            println!("[BRIDGE][PRINT][INFO] Starting SD print {} ({})", sd.filename, sd.file);
+                            send(
+                                &distributor,
+                                EventType::StateUpdate(StateWrapper {
+                                    state: BridgeState::PRINTING,
+                                    description: sd.state_description(),
+                                }),
+                            );
+                            let select = format!("M23 {}", sd.file);
+                            Bridge::send_sd_commands(&distributor, &sd, &[&select, "M24"]);
+                            if auto_report {
+                                let report = format!("M27 S{}", interval.as_secs());
+                                Bridge::send_sd_commands(&distributor, &sd, &[&report]);
+                            } else {
+                                Bridge::spawn_sd_poller(
+                                    distributor.clone(),
+                                    bridge_sender.clone(),
+                                    sd_print.clone(),
+                                    canceled.clone(),
+                                    busy.clone(),
+                                    interval,
+                                );
+                            }
+                            *sd_print.lock().await = Some(sd);
+                        }
+                        EventType::PrintPause => {
+                            if let Some(sd) = sd_print.lock().await.as_mut() {
+                                if !sd.paused {
+                                    println!("[BRIDGE][PRINT] Pausing SD print {}", sd.filename);
+                                    sd.paused = true;
+                                    Bridge::send_sd_commands(&distributor, sd, &["M25"]);
+                                    send(
+                                        &distributor,
+                                        EventType::StateUpdate(StateWrapper {
+                                            state: BridgeState::PAUSED,
+                                            description: sd.state_description(),
+                                        }),
+                                    );
+                                }
+                                continue;
+                            }
+                            let mut guard = print_info.lock().await;
+                            if guard.is_none() || guard.as_ref().unwrap().is_paused() {
+                                continue;
+                            }
+                            let info = guard.as_mut().unwrap();
+                            let mut commands = VecDeque::new();
+                            commands.push_back("M114".to_string());
+                            commands.extend(
+                                Bridge::load_script_or_skip(
+                                    printer,
+                                    &distributor,
+                                    "S_pauseGcode",
+                                    &info.filename,
+                                    info.heater_targets(),
+                                )
+                                .await,
+                            );
+                            info.pause = Some(PauseInfo {
+                                commands,
+                                ..Default::default()
+                            });
+                            println!("[BRIDGE][PRINT] Pausing print at line {}", info.line_number());
+
+                            send(
+                                &distributor,
+                                EventType::StateUpdate(StateWrapper {
+                                    state: BridgeState::PAUSED,
+                                    description: info.state_description(),
+                                }),
+                            );
+                        }
+                        EventType::PrintResume => {
+                            if let Some(sd) = sd_print.lock().await.as_mut() {
+                                if sd.paused {
+                                    println!("[BRIDGE][PRINT] Resuming SD print {}", sd.filename);
+                                    sd.paused = false;
+                                    Bridge::send_sd_commands(&distributor, sd, &["M24"]);
+                                    send(
+                                        &distributor,
+                                        EventType::StateUpdate(StateWrapper {
+                                            state: BridgeState::PRINTING,
+                                            description: sd.state_description(),
+                                        }),
+                                    );
+                                }
+                                continue;
+                            }
+                            let mut guard = print_info.lock().await;
+                            if guard.is_none() || !guard.as_ref().unwrap().is_paused() {
+                                continue;
+                            }
+                            let info = guard.as_mut().unwrap();
+                            let pause = info.pause.as_ref().unwrap();
+                            if pause.resuming || pause.commands.len() > 0 {
+                                eprintln!("[BRIDGE][PRINT] Pause not completed yet, ignoring resume");
+                                continue;
+                            }
+                            let commands = if pause.by_printer {
+                                VecDeque::new()
+                            } else {
+                                let script = Bridge::load_script_or_skip(
+                                    printer,
+                                    &distributor,
+                                    "S_resumeGcode",
+                                    &info.filename,
+                                    info.heater_targets(),
+                                )
+                                .await;
+                                Bridge::resume_commands(info, script)
+                            };
+                            let pause = info.pause.as_mut().unwrap();
+                            pause.commands = commands;
+                            pause.resuming = true;
+                            println!("[BRIDGE][PRINT] Resuming print at line {}", info.line_number() + 1);
+
+                            Bridge::advance_pause(&distributor, &bridge_sender, info);
+                        }
+                        EventType::EmergencyStop { username } => {
+                            let reason = format!("Emergency stop triggered by {}", username);
+                            let info = print_info.lock().await.take();
+                            if let Some(mut info) = info.filter(|info| info.upload.is_none()) {
+                                println!(
+                                    "[BRIDGE][PRINT][INFO] Print {} aborted by emergency stop ({})",
+                                    info.filename, username
+                                );
+                                Bridge::log_print_result(
+                                    printer,
+                                    &mut info,
+                                    PrintOutcome::Errored,
+                                    Some(reason.clone()),
+                                )
+                                .await;
+                                // the print was stopped on purpose, it shouldn't be offered for recovery.
+                                if let Err(err) = print_recovery::clear(printer).await {
+                                    eprintln!("[BRIDGE][ERROR] Cannot remove the recovery point: {}", err);
+                                }
+                            }
+                            let sd = sd_print.lock().await.take();
+                            if let Some(sd) = sd {
+                                Bridge::log_sd_print_result(
+                                    printer,
+                                    &sd,
+                                    PrintOutcome::Errored,
+                                    Some(reason),
+                                )
+                                .await;
+                            }
+                        }
                         EventType::StateUpdate(_state_info) => {
                             println!("Received state update event on bridge thread")
                         }
@@ -627,30 +2339,515 @@ impl Bridge {
             }
         });
     }
-    fn log_print_result(print_info: &mut Option<PrintInfo>) {
-        if print_info.is_none() {
-            return;
+    fn connected_description(firmware: &Option<Firmware>) -> StateDescription {
+        match firmware {
+            Some(firmware) => StateDescription::Capability {
+                firmware_name: firmware.name.clone(),
+                firmware_version: firmware.version.clone(),
+                capabilities: firmware.capabilities.clone(),
+            },
+            None => StateDescription::None,
         }
-        let print_info = print_info.as_mut().unwrap();
-        let duration = Utc::now() - print_info.start;
-        let days = duration.num_seconds() / 86400;
-        let hours = duration.num_seconds() % 86400 / 86400 * 24;
-        let minutes = (duration.num_seconds() / 60) % 60;
-        let seconds = duration.num_seconds() % 60;
+    }
+
+    /*
+        Log the result of a print and record it in the print history.
+        reason: Why the print errored, only set for errored prints.
+    */
+    async fn log_print_result(
+        printer: u32,
+        print_info: &mut PrintInfo,
+        outcome: PrintOutcome,
+        reason: Option<String>,
+    ) {
+        let stats = print_info.link_stats();
+        let job = PrintJob {
+            id: 0,
+            printer,
+            filename: print_info.filename.clone(),
+            user: print_info.user.clone(),
+            start: print_info.start.timestamp_millis(),
+            end: Utc::now().timestamp_millis(),
+            outcome,
+            reason,
+            lines: print_info.line_number().saturating_sub(print_info.start_line()),
+            resends: stats.resends,
+            checksum_errors: stats.checksum_errors,
+            line_number_errors: stats.line_number_errors,
+        };
+        Bridge::record_print(&job).await;
+        println!(
+            "[BRIDGE][PRINT][INFO] Resend ratio: {}/{} ({}%)",
+            stats.resends, stats.lines, stats.resend_ratio
+        );
+        println!(
+            "[BRIDGE][PRINT][INFO] Checksum errors: {}, line number errors: {}",
+            stats.checksum_errors, stats.line_number_errors
+        );
+    }
+
+    /*
+        Log the result of an SD print and record it in the print history.
+        The firmware streams the file itself, so there are no lines or link stats.
+    */
+    async fn log_sd_print_result(
+        printer: u32,
+        sd: &SdPrint,
+        outcome: PrintOutcome,
+        reason: Option<String>,
+    ) {
+        let job = PrintJob {
+            id: 0,
+            printer,
+            filename: sd.filename.clone(),
+            user: sd.user.clone(),
+            start: sd.start.timestamp_millis(),
+            end: Utc::now().timestamp_millis(),
+            outcome,
+            reason,
+            lines: 0,
+            resends: 0,
+            checksum_errors: 0,
+            line_number_errors: 0,
+        };
+        Bridge::record_print(&job).await;
+    }
+
+    async fn record_print(job: &PrintJob) {
+        let duration = (job.end - job.start) / 1000;
+        let days = duration / 86400;
+        let hours = duration % 86400 / 3600;
+        let minutes = (duration / 60) % 60;
+        let seconds = duration % 60;
 
         let mut duration = format!("{}h {}m {}s", hours, minutes, seconds);
         if days > 0 {
             duration = format!("{}d {}", days, duration);
         }
+        let result = match job.outcome {
+            PrintOutcome::Finished => "Finished",
+            PrintOutcome::Cancelled => "Cancelled",
+            PrintOutcome::Errored => "Errored",
+        };
         println!(
-            "[BRIDGE][PRINT][INFO] Finished print {} in {}",
-            print_info.filename, duration
+            "[BRIDGE][PRINT][INFO] {} print {} in {}",
+            result, job.filename, duration
         );
-        println!(
-            "[BRIDGE][PRINT][INFO] Resend ratio: {}/{} ({}%)",
-            print_info.get_resend_amount(),
-            print_info.get_line_amount(),
-            print_info.get_resend_ratio()
+        if let Err(err) = print_history::record(job).await {
+            eprintln!("[BRIDGE][ERROR] Cannot record the print in the history: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Mutex as StdMutex,
+        },
+        thread,
+        time::Instant,
+    };
+
+    use crossbeam_channel::unbounded;
+    use futures::executor::block_on;
+    use tokio::sync::MutexGuard;
+
+    use super::*;
+    use crate::{
+        print_file::PrintFile, print_history::HistoryFilter, virtual_printer::ADDRESS_PREFIX,
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(20);
+
+    lazy_static! {
+        // the bridges share storage.db, so the tests run one at a time in a scratch directory.
+        static ref WORKSPACE: Mutex<bool> = Mutex::new(false);
+    }
+
+    async fn workspace() -> MutexGuard<'static, bool> {
+        let mut guard = WORKSPACE.lock().await;
+        if !*guard {
+            let directory =
+                std::env::temp_dir().join(format!("server_v2-test-{}", std::process::id()));
+            std::fs::create_dir_all(directory.join("files")).unwrap();
+            std::env::set_current_dir(&directory).unwrap();
+            crate::setup_db().await;
+            *guard = true;
+        }
+        return guard;
+    }
+
+    #[derive(Default)]
+    struct Received {
+        states: Vec<BridgeState>,
+        messages: Vec<String>,
+    }
+
+    /*
+        A bridge connected to a virtual printer, with a thread that takes the place of its manager:
+        it keeps the state, forwards the events meant for the bridge and stops the bridge once it disconnects.
+    */
+    struct TestPrinter {
+        printer: u32,
+        distributor: Sender<EventType>,
+        state: Arc<Mutex<StateWrapper>>,
+        firmware: Arc<Mutex<Option<Firmware>>>,
+        received: Arc<StdMutex<Received>>,
+        stopped: Arc<AtomicBool>,
+    }
+
+    impl TestPrinter {
+        fn connect(printer: u32, options: &str) -> Self {
+            let (distributor, events) = unbounded::<EventType>();
+            let (bridge_sender, bridge_receiver) = unbounded::<EventType>();
+            let state = Arc::new(Mutex::new(StateWrapper {
+                state: BridgeState::DISCONNECTED,
+                description: StateDescription::None,
+            }));
+            let firmware = Arc::new(Mutex::new(None));
+            let received = Arc::new(StdMutex::new(Received::default()));
+            let stopped = Arc::new(AtomicBool::new(false));
+
+            {
+                let state = state.clone();
+                let received = received.clone();
+                let stopped = stopped.clone();
+                let bridge_sender = bridge_sender.clone();
+                let events_sender = distributor.clone();
+                thread::spawn(move || {
+                    while !stopped.load(Ordering::Relaxed) {
+                        let event = match events.recv_timeout(Duration::from_millis(50)) {
+                            Ok(event) => event,
+                            Err(_) => continue,
+                        };
+                        match event {
+                            EventType::StateUpdate(new_state) => {
+                                let current = new_state.state;
+                                *block_on(state.lock()) = new_state;
+                                received.lock().unwrap().states.push(current);
+                                if current == BridgeState::DISCONNECTED
+                                    || current == BridgeState::ERRORED
+                                {
+                                    send(&bridge_sender, EventType::KillBridge);
+                                }
+                            }
+                            EventType::IncomingTerminalMessage(message) => {
+                                received.lock().unwrap().messages.push(message);
+                            }
+                            EventType::PrintStart(_)
+                            | EventType::PrintEnd
+                            | EventType::PrintPause
+                            | EventType::PrintResume
+                            | EventType::SdUploadStart(_)
+                            | EventType::OutGoingTerminalMessage(_)
+                            | EventType::OutGoingPacket(_) => send(&bridge_sender, event),
+                            EventType::EmergencyStop { username } => {
+                                send(&bridge_sender, EventType::EmergencyStop { username });
+                                send(
+                                    &events_sender,
+                                    EventType::StateUpdate(StateWrapper {
+                                        state: BridgeState::ERRORED,
+                                        description: StateDescription::Error {
+                                            message: "Emergency stop".to_string(),
+                                        },
+                                    }),
+                                );
+                            }
+                            _ => (),
+                        }
+                    }
+                });
+            }
+
+            let mut bridge = Bridge::new(
+                printer,
+                distributor.clone(),
+                bridge_sender,
+                bridge_receiver,
+                format!("{}marlin{}", ADDRESS_PREFIX, options),
+                115200,
+                ConnectionState {
+                    state: state.clone(),
+                    emergency_port: Arc::new(Mutex::new(None)),
+                    firmware: firmware.clone(),
+                },
+            );
+            spawn(async move { bridge.start().await });
+
+            return Self {
+                printer,
+                distributor,
+                state,
+                firmware,
+                received,
+                stopped,
+            };
+        }
+
+        async fn wait_for(&self, description: &str, condition: impl Fn(&Received) -> bool) {
+            let deadline = Instant::now() + TIMEOUT;
+            while !condition(&self.received.lock().unwrap()) {
+                if Instant::now() >= deadline {
+                    panic!("Timed out waiting for {}", description);
+                }
+                sleep(Duration::from_millis(20)).await;
+            }
+        }
+
+        /*
+            Wait until the bridge reached the state, after it was in the given amount of states.
+            Returns the amount of states up to the reached one, to wait for the state that follows it.
+        */
+        async fn wait_for_state(&self, state: BridgeState, after: usize) -> usize {
+            let description = format!("{:?}", state);
+            self.wait_for(&description, |received| {
+                received
+                    .states
+                    .iter()
+                    .skip(after)
+                    .any(|reached| *reached == state)
+            })
+            .await;
+            let received = self.received.lock().unwrap();
+            let index = received
+                .states
+                .iter()
+                .skip(after)
+                .position(|reached| *reached == state);
+            return after + index.unwrap() + 1;
+        }
+
+        fn states(&self) -> usize {
+            return self.received.lock().unwrap().states.len();
+        }
+
+        fn command(&self, command: &str) {
+            send(
+                &self.distributor,
+                EventType::OutGoingTerminalMessage(Message::new(
+                    command.to_string(),
+                    Uuid::new_v4(),
+                )),
+            );
+        }
+
+        fn print(&self, filename: &str, content: &str) {
+            let path = Path::new("./files/").join(filename);
+            std::fs::write(&path, content).unwrap();
+            let info = PrintInfo::new(
+                filename.to_string(),
+                PrintFile::open(&path).unwrap(),
+                Utc::now(),
+            );
+            send(&self.distributor, EventType::PrintStart(info));
+        }
+
+        async fn last_job(&self) -> Option<PrintJob> {
+            let filter = HistoryFilter {
+                printer: Some(self.printer),
+                ..HistoryFilter::new()
+            };
+            let (jobs, _) = print_history::list(&filter).await.unwrap();
+            return jobs.into_iter().next();
+        }
+
+        /// Wait for the print to end, the job is recorded right before the bridge returns to connected.
+        async fn wait_for_job(&self) -> PrintJob {
+            let deadline = Instant::now() + TIMEOUT;
+            loop {
+                if let Some(job) = self.last_job().await {
+                    return job;
+                }
+                if Instant::now() >= deadline {
+                    panic!("Timed out waiting for the print to end");
+                }
+                sleep(Duration::from_millis(50)).await;
+            }
+        }
+
+        async fn disconnect(self) {
+            send(
+                &self.distributor,
+                EventType::StateUpdate(StateWrapper {
+                    state: BridgeState::DISCONNECTED,
+                    description: StateDescription::None,
+                }),
+            );
+            let after = self.states() - 1;
+            self.wait_for_state(BridgeState::DISCONNECTED, after).await;
+            self.stopped.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn gcode(lines: usize) -> String {
+        let mut content = String::from("; generated for the bridge tests\nG28\n");
+        for line in 0..lines {
+            content.push_str(&format!("G1 X{} Y{} F3000\n", line % 100, line % 50));
+        }
+        return content;
+    }
+
+    #[tokio::test]
+    async fn errors_the_printer_of_a_panicked_task() {
+        let (distributor, events) = unbounded::<EventType>();
+        let result = Bridge::spawn_task(distributor, async { panic!("task failed") }).await;
+        assert!(result.is_ok());
+        match events.try_recv() {
+            Ok(EventType::StateUpdate(state)) => assert_eq!(state.state, BridgeState::ERRORED),
+            other => panic!("expected a state update, got {:?}", other),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn connects_to_the_virtual_printer() {
+        let _workspace = workspace().await;
+        let printer = TestPrinter::connect(101, "");
+        printer.wait_for_state(BridgeState::CONNECTED, 0).await;
+        {
+            let firmware = printer.firmware.lock().await;
+            let firmware = firmware
+                .as_ref()
+                .expect("the firmware is detected from M115");
+            assert!(firmware.supports("EMERGENCY_PARSER"));
+            assert!(!firmware.supports("SDCARD"));
+        }
+        assert!(!printer
+            .received
+            .lock()
+            .unwrap()
+            .states
+            .contains(&BridgeState::ERRORED));
+        printer.disconnect().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn prints_a_file() {
+        let _workspace = workspace().await;
+        let printer = TestPrinter::connect(102, "");
+        let after = printer.wait_for_state(BridgeState::CONNECTED, 0).await;
+
+        printer.print("short.gcode", &gcode(50));
+        let after = printer.wait_for_state(BridgeState::PRINTING, after).await;
+        printer.wait_for_state(BridgeState::CONNECTED, after).await;
+
+        let job = printer.wait_for_job().await;
+        assert_eq!(job.filename, "short.gcode");
+        assert_eq!(job.outcome, PrintOutcome::Finished);
+        assert_eq!(job.lines, 51);
+        assert_eq!(job.resends, 0);
+        printer.disconnect().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn resends_rejected_lines() {
+        let _workspace = workspace().await;
+        let printer = TestPrinter::connect(103, "?resend_every=20");
+        let after = printer.wait_for_state(BridgeState::CONNECTED, 0).await;
+
+        // the heater takes a while to reach its target, which leaves time to inject a rejection.
+        printer.print("resend.gcode", &format!("M109 S25\n{}", gcode(100)));
+        let after = printer.wait_for_state(BridgeState::PRINTING, after).await;
+        printer.command("!resend");
+        printer.wait_for_state(BridgeState::CONNECTED, after).await;
+
+        let job = printer.wait_for_job().await;
+        assert_eq!(job.outcome, PrintOutcome::Finished);
+        assert_eq!(job.lines, 102);
+        assert!(job.resends > 5, "{} resends", job.resends);
+        assert!(job.checksum_errors > 0);
+        printer.disconnect().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn reports_firmware_errors() {
+        let _workspace = workspace().await;
+        let printer = TestPrinter::connect(104, "");
+        let after = printer.wait_for_state(BridgeState::CONNECTED, 0).await;
+
+        printer.command("!error Heating failed");
+        printer
+            .wait_for("the error message", |received| {
+                received
+                    .messages
+                    .iter()
+                    .any(|message| message.contains("Error:Heating failed"))
+            })
+            .await;
+        assert_eq!(printer.state.lock().await.state, BridgeState::CONNECTED);
+
+        // the connection keeps working after the error.
+        printer.print("after_error.gcode", &gcode(10));
+        let after = printer.wait_for_state(BridgeState::PRINTING, after).await;
+        printer.wait_for_state(BridgeState::CONNECTED, after).await;
+        assert_eq!(printer.wait_for_job().await.outcome, PrintOutcome::Finished);
+        assert!(!printer
+            .received
+            .lock()
+            .unwrap()
+            .states
+            .contains(&BridgeState::ERRORED));
+        printer.disconnect().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn pauses_and_resumes_a_print() {
+        let _workspace = workspace().await;
+        let printer = TestPrinter::connect(105, "");
+        let after = printer.wait_for_state(BridgeState::CONNECTED, 0).await;
+
+        printer.print("pause.gcode", &format!("M109 S30\n{}", gcode(50)));
+        let after = printer.wait_for_state(BridgeState::PRINTING, after).await;
+        send(&printer.distributor, EventType::PrintPause);
+        let after = printer.wait_for_state(BridgeState::PAUSED, after).await;
+
+        // a resume is ignored until the pause completed, after the heater reached its target.
+        let deadline = Instant::now() + TIMEOUT;
+        while printer.state.lock().await.state == BridgeState::PAUSED {
+            assert!(Instant::now() < deadline, "Timed out resuming the print");
+            send(&printer.distributor, EventType::PrintResume);
+            sleep(Duration::from_millis(250)).await;
+        }
+        let after = printer.wait_for_state(BridgeState::PRINTING, after).await;
+        printer.wait_for_state(BridgeState::CONNECTED, after).await;
+
+        let job = printer.wait_for_job().await;
+        assert_eq!(job.outcome, PrintOutcome::Finished);
+        assert_eq!(job.lines, 52);
+        printer.disconnect().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn records_an_emergency_stopped_print() {
+        let _workspace = workspace().await;
+        let printer = TestPrinter::connect(106, "");
+        let after = printer.wait_for_state(BridgeState::CONNECTED, 0).await;
+
+        printer.print("stopped.gcode", &format!("M109 S30\n{}", gcode(50)));
+        let after = printer.wait_for_state(BridgeState::PRINTING, after).await;
+        // the point the recovery saver would have left behind.
+        let point = RecoveryPoint {
+            filename: "stopped.gcode".to_string(),
+            hash: String::new(),
+            line: 1,
+            heater_targets: vec![("T".to_string(), 30.0)],
+            z: None,
+            time: Utc::now().timestamp_millis(),
+        };
+        print_recovery::save(106, &point).await.unwrap();
+        send(
+            &printer.distributor,
+            EventType::EmergencyStop {
+                username: "tester".to_string(),
+            },
         );
+        printer.wait_for_state(BridgeState::ERRORED, after).await;
+
+        let job = printer.wait_for_job().await;
+        assert_eq!(job.outcome, PrintOutcome::Errored);
+        assert_eq!(job.reason.as_deref(), Some("Emergency stop triggered by tester"));
+        assert!(print_recovery::load(106).await.unwrap().is_none());
+        printer.disconnect().await;
     }
 }
@@ -0,0 +1,185 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref FIRMWARE_NAME: Regex = Regex::new(r"FIRMWARE_NAME: ?([^\s,]+)").unwrap();
+    static ref FIRMWARE_VERSION: Regex = Regex::new(r"FIRMWARE_VERSION: ?([^\s,]+)").unwrap();
+    static ref MARLIN_OK: Regex = Regex::new(r"ok N(\d+)").unwrap();
+    static ref MARLIN_RESEND: Regex = Regex::new(r"Resend: N?:?(\d+)").unwrap();
+    static ref PROFILES: Vec<FirmwareProfile> = vec![
+        FirmwareProfile {
+            kind: FirmwareKind::Prusa,
+            identifier: "Prusa-Firmware",
+            name: "Prusa-Firmware",
+            version: Regex::new(r"FIRMWARE_NAME:Prusa-Firmware ?v?([^\s,]+)").unwrap(),
+            ok: MARLIN_OK.clone(),
+            resend: MARLIN_RESEND.clone(),
+            default_capabilities: &["AUTOREPORT_TEMP"],
+        },
+        FirmwareProfile {
+            kind: FirmwareKind::Marlin,
+            identifier: "Marlin",
+            name: "Marlin",
+            version: Regex::new(r"FIRMWARE_NAME:Marlin[ _]v?([^\s,]+)").unwrap(),
+            ok: MARLIN_OK.clone(),
+            resend: MARLIN_RESEND.clone(),
+            default_capabilities: &[],
+        },
+        FirmwareProfile {
+            kind: FirmwareKind::RepRap,
+            identifier: "RepRapFirmware",
+            name: "RepRapFirmware",
+            version: FIRMWARE_VERSION.clone(),
+            ok: MARLIN_OK.clone(),
+            resend: Regex::new(r"(?:Resend: ?|^rs )N?:?(\d+)").unwrap(),
+            // M501 runs config-override.g, which holds the settings stored with M500.
+            default_capabilities: &["EEPROM"],
+        },
+        FirmwareProfile {
+            kind: FirmwareKind::Smoothie,
+            identifier: "Smoothieware",
+            name: "Smoothieware",
+            version: FIRMWARE_VERSION.clone(),
+            ok: MARLIN_OK.clone(),
+            resend: Regex::new(r"(?:Resend: ?|^rs )N?:?(\d+)").unwrap(),
+            default_capabilities: &["EEPROM"],
+        },
+        FirmwareProfile {
+            kind: FirmwareKind::Repetier,
+            identifier: "Repetier",
+            name: "Repetier-Firmware",
+            version: Regex::new(r"FIRMWARE_NAME:Repetier_([^\s,]+)").unwrap(),
+            ok: Regex::new(r"ok N?(\d+)").unwrap(),
+            resend: Regex::new(r"(?:Resend: ?|^rs )N?:?(\d+)").unwrap(),
+            default_capabilities: &[],
+        },
+    ];
+    // Used for firmware that isn't recognized, most hobby firmware follows the Marlin protocol.
+    static ref GENERIC: FirmwareProfile = FirmwareProfile {
+        kind: FirmwareKind::Unknown,
+        identifier: "",
+        name: "Unknown",
+        version: FIRMWARE_VERSION.clone(),
+        ok: MARLIN_OK.clone(),
+        resend: MARLIN_RESEND.clone(),
+        default_capabilities: &[],
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirmwareKind {
+    Marlin,
+    Prusa,
+    RepRap,
+    Smoothie,
+    Repetier,
+    Unknown,
+}
+
+/*
+    Protocol details of a firmware family.
+
+    identifier: Start of the FIRMWARE_NAME field in the M115 response, used to recognize the family.
+    name: Name of the firmware family as shown to users.
+    version: Finds the firmware version in the M115 response, the first capture group is the version.
+    ok: Matches an ok that confirms a line number, the first capture group is the line number.
+    resend: Matches a resend request, the first capture group is the line number to resend.
+    default_capabilities: Capabilities the firmware supports when it doesn't report them with Cap: lines.
+
+    Temperature reports aren't part of the profile, every family uses the same format (see Parser::parse_temperature).
+*/
+#[derive(Debug)]
+pub struct FirmwareProfile {
+    pub kind: FirmwareKind,
+    identifier: &'static str,
+    pub name: &'static str,
+    version: Regex,
+    pub ok: Regex,
+    pub resend: Regex,
+    default_capabilities: &'static [&'static str],
+}
+
+impl FirmwareProfile {
+    /// Get the profile used before the firmware is detected.
+    pub fn generic() -> &'static FirmwareProfile {
+        &GENERIC
+    }
+}
+
+/*
+    Firmware detected from the M115 response.
+
+    capabilities: Cap: lines without the prefix, for example AUTOREPORT_TEMP:1
+*/
+#[derive(Debug, Clone)]
+pub struct Firmware {
+    pub name: String,
+    pub version: Option<String>,
+    pub capabilities: Vec<String>,
+    profile: &'static FirmwareProfile,
+}
+
+impl Firmware {
+    /*
+        Detect the firmware from the lines received in response to M115.
+        Returns None when none of the lines contain a FIRMWARE_NAME field.
+
+        Examples:
+        FIRMWARE_NAME:Marlin 2.0.9.1 (Aug  2 2021 12:00:00) SOURCE_CODE_URL:github.com/MarlinFirmware/Marlin ...
+        FIRMWARE_NAME:Prusa-Firmware 3.10.0 based on Marlin FIRMWARE_URL:https://github.com/prusa3d/Prusa-Firmware ...
+        FIRMWARE_NAME: RepRapFirmware for Duet 2 WiFi/Ethernet FIRMWARE_VERSION: 3.3 ELECTRONICS: Duet WiFi 1.02 or later ...
+        FIRMWARE_NAME:Smoothieware, FIRMWARE_URL:http%3A//smoothieware.org, FIRMWARE_VERSION:edge-3332442, ...
+        FIRMWARE_NAME:Repetier_1.0.3 COMPILED:Dec 19 2018 FIRMWARE_URL:https://github.com/repetier/Repetier-Firmware/ ...
+    */
+    pub fn detect(responses: &[String]) -> Option<Self> {
+        let info = responses
+            .iter()
+            .find(|response| FIRMWARE_NAME.is_match(response))?;
+        let reported_name = FIRMWARE_NAME.captures(info).unwrap()[1].to_string();
+
+        let profile = PROFILES
+            .iter()
+            .find(|profile| reported_name.starts_with(profile.identifier))
+            .unwrap_or(&GENERIC);
+        let name = if profile.kind == FirmwareKind::Unknown {
+            reported_name
+        } else {
+            profile.name.to_string()
+        };
+        let version = profile
+            .version
+            .captures(info)
+            .map(|captures| captures[1].to_string());
+
+        let capabilities = responses
+            .iter()
+            .filter(|response| response.starts_with("Cap:"))
+            .map(|response| response.trim_start_matches("Cap:").trim().to_string())
+            .collect();
+
+        return Some(Self {
+            name,
+            version,
+            capabilities,
+            profile,
+        });
+    }
+
+    pub fn profile(&self) -> &'static FirmwareProfile {
+        self.profile
+    }
+
+    /*
+        Check if the firmware supports a capability, like AUTOREPORT_TEMP or EEPROM.
+        A capability reported with a Cap: line takes precedence over the defaults of the profile.
+    */
+    pub fn supports(&self, capability: &str) -> bool {
+        let prefix = format!("{}:", capability);
+        for cap in &self.capabilities {
+            if cap.starts_with(&prefix) {
+                return cap[prefix.len()..].trim() == "1";
+            }
+        }
+        return self.profile.default_capabilities.contains(&capability);
+    }
+}
@@ -2,17 +2,23 @@ use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use crate::api_manager::{models::{send, EventType, StateWrapper, BridgeState}, websocket_handler::send_to_all_ws_clients};
 use api_manager::{
-    models::{SettingRow, StateDescription},
+    models::{HostPrompt, LinkStats, SdFile, StateDescription},
     ApiManager,
 };
 
-use bridge::Bridge;
-use chrono::{DateTime, Utc};
+use bridge::{Bridge, ConnectionState};
+use firmware::Firmware;
+use transport::Transport;
+use parser::{Position, TempInfo};
+use printers::{PrinterConfig, PrinterHandle, Printers};
+use chrono::Utc;
 use crossbeam_channel::{Receiver, Sender, TryRecvError, unbounded};
 use hyper::upgrade::Upgraded;
 use hyper_tungstenite::WebSocketStream;
 use serde_json::json;
-use sqlx::{Connection, Executor, SqliteConnection};
+use sqlx::{Connection, Executor, Row, SqliteConnection};
+use temperature_history::TemperatureHistory;
+use terminal_log::TerminalLog;
 use tokio::{
     fs::OpenOptions,
     spawn,
@@ -21,9 +27,21 @@ use tokio::{
     time::{sleep, Instant},
 };
 mod api_manager;
+mod binary_transfer;
 mod bridge;
 mod client_update_check;
+mod firmware;
 mod parser;
+mod print_file;
+mod print_history;
+mod print_queue;
+mod print_recovery;
+mod printers;
+mod temperature_history;
+mod terminal_log;
+mod transport;
+mod virtual_printer;
+mod watchdog;
 
 #[tokio::main(worker_threads = 2)]
 async fn main() {
@@ -38,22 +56,87 @@ async fn main() {
 
     client_update_check::check_updates().await;
 
-    let mut manager = Manager::new();
-    manager.start().await;
+    std::panic::set_hook(Box::new(move |e| {
+        println!("[MAIN][PANIC] {}", e);
+        let msg = format!("{}", e);
+        sentry::capture_message(&msg, sentry::Level::Error);
+    }));
+
+    let websockets: Arc<Mutex<HashMap<u128, WebSocketStream<Upgraded>>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+    let printers: Printers = Arc::new(Mutex::new(HashMap::new()));
+
+    let sockets = websockets.clone();
+    spawn(async move {
+        loop {
+            api_manager::websocket_handler::check_incoming_messages(sockets.clone()).await;
+            sleep(Duration::from_secs(1)).await;
+        }
+    });
+
+    match PrinterConfig::load_all().await {
+        Ok(configs) => {
+            for config in configs {
+                start_printer(config, &printers, websockets.clone()).await;
+            }
+        }
+        Err(err) => eprintln!("[MAIN][ERROR] Cannot load the printers: {}", err),
+    }
+
+    ApiManager::start(printers, websockets).await;
 }
 
+/*
+    Create a manager for the printer, add it to the registry & start it.
+    The manager runs until the printer is removed (RemovePrinter event).
+*/
+async fn start_printer(
+    config: PrinterConfig,
+    printers: &Printers,
+    websockets: Arc<Mutex<HashMap<u128, WebSocketStream<Upgraded>>>>,
+) {
+    println!("[MAIN] Starting printer {} ({})", config.id, config.name);
+    let mut manager = Manager::new(config.id, websockets);
+    printers
+        .lock()
+        .await
+        .insert(config.id, manager.handle(config.name.clone()));
+    spawn(async move {
+        manager.start(config).await;
+    });
+}
+
+/*
+    Handles the events of a single printer: owns its bridge and keeps its state up to date.
+    Every websocket message of the manager is tagged with the id of the printer.
+*/
 struct Manager {
+    printer_id: u32,
     bridge_thread: Option<JoinHandle<()>>,
     state: Arc<Mutex<StateWrapper>>,
     sender: Sender<EventType>,
     receiver: Receiver<EventType>,
     websockets: Arc<tokio::sync::Mutex<HashMap<u128, WebSocketStream<Upgraded>>>>,
+    temperature_history: Arc<Mutex<TemperatureHistory>>,
+    terminal_log: Arc<Mutex<TerminalLog>>,
+    position: Arc<Mutex<Option<Position>>>,
+    emergency_port: Arc<Mutex<Option<Box<dyn Transport>>>>,
+    firmware: Arc<Mutex<Option<Firmware>>>,
+    host_prompt: Arc<Mutex<Option<HostPrompt>>>,
+    link_stats: Arc<Mutex<Option<LinkStats>>>,
+    sd_files: Arc<Mutex<Option<Vec<SdFile>>>>,
+    // the next job of the queue waits for the user to confirm the bed is clear.
+    queue_confirm: Arc<Mutex<bool>>,
 }
 
 impl Manager {
-    fn new() -> Self {
+    fn new(
+        printer_id: u32,
+        websockets: Arc<tokio::sync::Mutex<HashMap<u128, WebSocketStream<Upgraded>>>>,
+    ) -> Self {
         let (sender, receiver) = unbounded();
         Self {
+            printer_id,
             bridge_thread: None,
             state: Arc::new(Mutex::new(StateWrapper {
                 state: BridgeState::DISCONNECTED,
@@ -61,62 +144,51 @@ impl Manager {
             })),
             sender,
             receiver,
-            websockets: Arc::new(tokio::sync::Mutex::new(HashMap::new())),
+            websockets,
+            temperature_history: Arc::new(Mutex::new(TemperatureHistory::new())),
+            terminal_log: Arc::new(Mutex::new(TerminalLog::new())),
+            position: Arc::new(Mutex::new(None)),
+            emergency_port: Arc::new(Mutex::new(None)),
+            firmware: Arc::new(Mutex::new(None)),
+            host_prompt: Arc::new(Mutex::new(None)),
+            link_stats: Arc::new(Mutex::new(None)),
+            sd_files: Arc::new(Mutex::new(None)),
+            queue_confirm: Arc::new(Mutex::new(false)),
         }
     }
 
-    async fn start<'a>(&'a mut self) {
-        let dist_sender_clone = self.sender.clone();
-        let (bridge_sender, bridge_receiver) = unbounded();
-        let websockets = self.websockets.clone();
-        let stateinfo = self.state.clone();
-        let panic_sender_clone = self.sender.clone();
-        spawn(async move {
-            std::panic::set_hook(Box::new(move |e| {
-                println!("[API][PANIC] {}", e);
-                let msg = format!("{}", e);
-                sentry::capture_message(&msg, sentry::Level::Error);
+    /// Get the handle the api uses to address this printer.
+    fn handle(&self, name: String) -> PrinterHandle {
+        PrinterHandle {
+            id: self.printer_id,
+            name,
+            distributor: self.sender.clone(),
+            state: self.state.clone(),
+            temperature_history: self.temperature_history.clone(),
+            terminal_log: self.terminal_log.clone(),
+            position: self.position.clone(),
+            host_prompt: self.host_prompt.clone(),
+            link_stats: self.link_stats.clone(),
+            sd_files: self.sd_files.clone(),
+            queue_confirm: self.queue_confirm.clone(),
+            emergency_port: self.emergency_port.clone(),
+            firmware: self.firmware.clone(),
+        }
+    }
 
-                send(
-                    &panic_sender_clone,
-                    EventType::StateUpdate(StateWrapper {
-                        state: BridgeState::ERRORED,
-                        description: StateDescription::Error {
-                            message: "An internal error occurred\nCheck the logs for more info."
-                                .to_string(),
-                        },
-                    }),
-                );
-            }));
-            let _ = spawn(ApiManager::start(dist_sender_clone, websockets, stateinfo));
-        });
-        self.connect_boot(self.sender.clone(), self.state.clone())
+    async fn start<'a>(&'a mut self, config: PrinterConfig) {
+        *self.temperature_history.lock().await =
+            TemperatureHistory::load(self.printer_id, Utc::now().timestamp_millis()).await;
+        *self.terminal_log.lock().await = TerminalLog::load(self.printer_id).await;
+        if let Ok(Some(point)) = print_recovery::load(self.printer_id).await {
+            println!(
+                "[MAIN] The print of {} on printer {} was interrupted at line {}, it can be recovered",
+                point.filename, self.printer_id, point.line
+            );
+        }
+        let (bridge_sender, bridge_receiver) = unbounded();
+        self.connect_boot(config, self.sender.clone(), self.state.clone())
             .await;
-        let websockets = self.websockets.clone();
-        let panic_sender_clone = self.sender.clone();
-        spawn(async move {
-            std::panic::set_hook(Box::new(move |e| {
-                println!("[WS][PANIC] {}", e);
-                let msg = format!("{}", e);
-                sentry::capture_message(&msg, sentry::Level::Error);
-
-                send(
-                    &panic_sender_clone,
-                    EventType::StateUpdate(StateWrapper {
-                        state: BridgeState::ERRORED,
-                        description: StateDescription::Error {
-                            message: "An internal error occurred\nCheck the logs for more info."
-                                .to_string(),
-                        },
-                    }),
-                );
-            }));
-            loop {
-                api_manager::websocket_handler::check_incoming_messages(websockets.clone())
-                    .await;
-                sleep(Duration::from_secs(1)).await;
-            }
-        });
         loop {
             match self.receiver.try_recv() {
                 Ok(event) => {
@@ -137,29 +209,23 @@ impl Manager {
                             let dist_sender_clone = self.sender.clone();
                             let bridge_receiver_clone = bridge_receiver.clone();
                             let bridge_sender_clone = bridge_sender.clone();
-                            let state = self.state.clone();
-                            self.bridge_thread = Some(spawn(async move {
-                                let panic_sender_clone = dist_sender_clone.clone();
-                                std::panic::set_hook(Box::new(move |e| {
-                                    println!("[BRIDGE][PANIC] {}", e);
-                                    let msg = format!("{}", e);
-                                    sentry::capture_message(&msg, sentry::Level::Error);
-                                    send(&panic_sender_clone, EventType::StateUpdate ( 
-                                        StateWrapper {
-                                        state: BridgeState::ERRORED,
-                                        description: StateDescription::Error {
-                                            message: "An internal error occurred\nCheck the logs for more info.".to_string()
-                                        }
-                                    })
-                                );
-                            }));
+                            let connection = ConnectionState {
+                                state: self.state.clone(),
+                                emergency_port: self.emergency_port.clone(),
+                                firmware: self.firmware.clone(),
+                            };
+                            let printer_id = self.printer_id;
+                            // a panic errors this printer only, the hook installed in main reports it.
+                            let panic_sender_clone = dist_sender_clone.clone();
+                            self.bridge_thread = Some(Bridge::spawn_task(panic_sender_clone, async move {
                                 let mut bridge = Bridge::new(
+                                    printer_id,
                                     dist_sender_clone,
                                     bridge_sender_clone,
                                     bridge_receiver_clone,
                                     address,
                                     port,
-                                    state,
+                                    connection,
                                 );
                                 bridge.start().await;
                             }));
@@ -186,15 +252,24 @@ impl Manager {
                             }
                         }
                         EventType::StateUpdate(new_state) => {
-                            {
-                                let old = self.state.lock().await;
-                                println!("[STATEUPDATE] {:?} => {:?}", old.state, new_state.state);
-                            }
+                            let old_state = self.state.lock().await.state;
+                            println!(
+                                "[STATEUPDATE][{}] {:?} => {:?}",
+                                self.printer_id, old_state, new_state.state
+                            );
                             *self.state.lock().await = new_state.clone();
                             self.send_websockets_updated_state(new_state.clone()).await;
+                            if new_state.state == BridgeState::WAITING && old_state != BridgeState::WAITING {
+                                self.send_websockets_wait_notification(&new_state).await;
+                            }
                             if new_state.state == BridgeState::DISCONNECTED
                                 || new_state.state == BridgeState::ERRORED
                             {
+                                *self.position.lock().await = None;
+                                *self.emergency_port.lock().await = None;
+                                *self.host_prompt.lock().await = None;
+                                *self.sd_files.lock().await = None;
+                                *self.queue_confirm.lock().await = false;
                                 send(&bridge_sender, EventType::KillBridge);
                                 self.bridge_thread.take();
                             }
@@ -208,83 +283,248 @@ impl Manager {
                             if self.bridge_thread.is_none() {
                                 continue;
                             }
+                            if *self.queue_confirm.lock().await {
+                                *self.queue_confirm.lock().await = false;
+                                send(&self.sender, EventType::QueueUpdate);
+                            }
                             send(
                                 &bridge_sender,
                                 EventType::PrintStart (info),
                             );
                         }
+                        EventType::SdPrintStart { file, filename, user } => {
+                            if self.bridge_thread.is_none() {
+                                continue;
+                            }
+                            send(&bridge_sender, EventType::SdPrintStart { file, filename, user });
+                        }
+                        EventType::SdUploadStart(info) => {
+                            if self.bridge_thread.is_none() {
+                                continue;
+                            }
+                            send(&bridge_sender, EventType::SdUploadStart(info));
+                        }
+                        EventType::EmergencyStop { username } => {
+                            // M112 is already written to the printer by the api, the print is aborted here.
+                            println!("[MAIN] Emergency stop triggered by {}", username);
+                            send(&bridge_sender, EventType::EmergencyStop { username: username.clone() });
+                            send(
+                                &self.sender,
+                                EventType::StateUpdate(StateWrapper {
+                                    state: BridgeState::ERRORED,
+                                    description: StateDescription::Error {
+                                        message: format!(
+                                            "Emergency stop triggered by {}.\n Reset the printer before reconnecting.",
+                                            username
+                                        ),
+                                    },
+                                }),
+                            );
+                        }
+                        EventType::PrintPause => send(&bridge_sender, EventType::PrintPause),
+                        EventType::PrintResume => send(&bridge_sender, EventType::PrintResume),
                         EventType::TempUpdate {
                             tools,
+                            active_tool,
                             bed,
                             chamber,
+                            probe,
+                            redundant,
+                            ambient,
                         } => {
+                            let time = Utc::now().timestamp_millis();
+                            let mut heaters: Vec<(String, &TempInfo)> = tools
+                                .iter()
+                                .map(|tool| (tool.name().to_string(), tool))
+                                .collect();
+                            let sensors = [
+                                ("B", &bed),
+                                ("C", &chamber),
+                                ("P", &probe),
+                                ("R", &redundant),
+                                ("A", &ambient),
+                            ];
+                            for (name, sensor) in sensors.iter() {
+                                if let Some(sensor) = sensor {
+                                    heaters.push((name.to_string(), sensor));
+                                }
+                            }
+                            let mut completed = vec![];
+                            {
+                                let mut history = self.temperature_history.lock().await;
+                                for (name, info) in heaters {
+                                    let sample = history.record(
+                                        &name,
+                                        time,
+                                        info.current_temp(),
+                                        info.target_temp(),
+                                    );
+                                    if let Some(sample) = sample {
+                                        completed.push((name, sample));
+                                    }
+                                }
+                            }
+                            if completed.len() > 0 {
+                                spawn(TemperatureHistory::persist(self.printer_id, completed, time));
+                            }
+
                             let json = json!({
                                     "type": "temperature_change",
                                     "content": {
                                             "tools": tools,
+                                            "activeTool": active_tool,
                                             "bed": bed,
                                             "chamber": chamber,
-                                            "time": Utc::now().timestamp_millis()
+                                            "probe": probe,
+                                            "redundant": redundant,
+                                            "ambient": ambient,
+                                            "time": time
                                     },
                             });
     
-                            send_to_all_ws_clients(json.to_string(), &self.websockets).await;
+                            self.broadcast(json).await;
                            
                         }
     
+                        EventType::PositionUpdate(position) => {
+                            // reports can still arrive while the bridge is shutting down.
+                            let state = self.state.lock().await.state;
+                            if state == BridgeState::DISCONNECTED || state == BridgeState::ERRORED {
+                                continue;
+                            }
+                            *self.position.lock().await = Some(position);
+                            let json = json!({
+                                    "type": "position_change",
+                                    "content": {
+                                            "position": position,
+                                            "time": Utc::now().timestamp_millis()
+                                    },
+                            });
+
+                            self.broadcast(json).await;
+                        }
+
+                        EventType::HostPrompt(prompt) => {
+                            match &prompt {
+                                Some(prompt) => println!("[MAIN] Printer asks: {} {:?}", prompt.message, prompt.choices),
+                                None => println!("[MAIN] Printer prompt closed"),
+                            }
+                            *self.host_prompt.lock().await = prompt.clone();
+                            let json = json!({
+                                    "type": "host_prompt",
+                                    "content": prompt,
+                            });
+
+                            self.broadcast(json).await;
+                        }
+
+                        EventType::LinkStats(stats) => {
+                            *self.link_stats.lock().await = Some(stats.clone());
+                            let json = json!({
+                                    "type": "link_stats",
+                                    "content": stats,
+                            });
+
+                            self.broadcast(json).await;
+                        }
+
+                        EventType::SdFiles(files) => {
+                            println!("[MAIN] Found {} file(s) on the SD card", files.len());
+                            *self.sd_files.lock().await = Some(files.clone());
+                            let json = json!({
+                                    "type": "sd_files",
+                                    "content": files,
+                            });
+
+                            self.broadcast(json).await;
+                        }
+
+                        EventType::Notification { reason, message } => {
+                            self.send_websockets_notification(json!(reason), &message).await;
+                        }
+
                         EventType::IncomingTerminalMessage(message) => {
-                            let time: DateTime<Utc> = Utc::now();
+                            let line = self.terminal_log.lock().await.push("OUTPUT", &message, None);
                             let json = json!({
                                     "type": "terminal_message",
-                                    "content": [
-                                            {
-                                                    "message": message,
-                                                    "type": "OUTPUT",
-                                                    "id": null,
-                                                    "time": time.to_rfc3339()
-                                            }
-                                    ]
+                                    "content": [line]
                             });
-                            send_to_all_ws_clients(json.to_string(), &self.websockets).await;
+                            self.broadcast(json).await;
                         }
     
                         EventType::OutGoingTerminalMessage(message) => {
-                            let time: DateTime<Utc> = Utc::now();
+                            let line = self.terminal_log.lock().await.push(
+                                "INPUT",
+                                message.content.trim(),
+                                Some(message.id.to_hyphenated().to_string()),
+                            );
                             let json = json!({
                                     "type": "terminal_message",
-                                    "content": [
-                                            {
-                                                    "message": message.content.trim(),
-                                                    "type": "INPUT",
-                                                    "id": message.id.to_hyphenated().to_string(),
-                                                    "time": time.to_rfc3339()
-                                            }
-                                    ]
+                                    "content": [line]
                             });
                             send(&bridge_sender, EventType::OutGoingTerminalMessage(message.clone()));
     
-                            send_to_all_ws_clients(json.to_string(), &self.websockets).await;
+                            self.broadcast(json).await;
                             
                         }
     
+                        // packets of the binary file transfer aren't shown in the terminal.
+                        EventType::OutGoingPacket(packet) => {
+                            send(&bridge_sender, EventType::OutGoingPacket(packet));
+                        }
+
                         EventType::KillBridge => {
                             eprintln!("[WARNING] Received KillBridge event on main receiver");
                         }
+
+                        EventType::QueueUpdate => self.send_websockets_queue().await,
+
+                        EventType::QueueConfirm => {
+                            *self.queue_confirm.lock().await = true;
+                            self.send_websockets_queue().await;
+                            self.send_websockets_notification(
+                                json!("queueConfirm"),
+                                "Clear the bed to start the next job of the queue.",
+                            )
+                            .await;
+                        }
+
+                        EventType::RemovePrinter => {
+                            println!("[MAIN] Removing printer {}", self.printer_id);
+                            send(&bridge_sender, EventType::KillBridge);
+                            if let Some(handle) = self.bridge_thread.take() {
+                                handle.abort();
+                            }
+                            break;
+                        }
                     }
                 },
                 Err(error) => {
                     if error != TryRecvError::Empty {
                         eprintln!("[ERROR][EVENT] {}", error);
                         }
+                        // terminal lines are stored in batches, as there's a line for every command while printing.
+                        let unsaved = self
+                            .terminal_log
+                            .lock()
+                            .await
+                            .take_unsaved(Utc::now().timestamp_millis());
+                        if let Some(lines) = unsaved {
+                            spawn(TerminalLog::persist(self.printer_id, lines));
+                        }
                         let time = Instant::now();
                         yield_now().await;
                         let state = self.state.lock().await.state;
-                        if state.ne(&BridgeState::PRINTING) && time.elapsed().as_millis() < 300 {
+                        let is_printing = state.eq(&BridgeState::PRINTING)
+                            || state.eq(&BridgeState::PAUSED)
+                            || state.eq(&BridgeState::WAITING)
+                            || state.eq(&BridgeState::UPLOADING);
+                        if !is_printing && time.elapsed().as_millis() < 300 {
                             sleep(tokio::time::Duration::from_millis(
                                 300 - time.elapsed().as_millis() as u64,
                             ))
                             .await;
-                        } else if state.eq(&BridgeState::PRINTING) && time.elapsed().as_millis() < 3 {
+                        } else if is_printing && time.elapsed().as_millis() < 3 {
                             sleep(tokio::time::Duration::from_millis(
                                 3 - time.elapsed().as_millis() as u64,
                             ))
@@ -296,142 +536,98 @@ impl Manager {
         }
     }
     /*
-        Fetch settings for automatically connecting on starting application (boot setting, device path & baudrate).
-        Check if those settings are correctly set and autoboot is enabled.
-        Try to send a connectionCreate event to the global dist sender.
+        Check if the printer should connect on starting the application and if its connection is set up.
+        Try to send a connectionCreate event to the dist sender of the printer.
     */
-    async fn connect_boot(&self, sender: Sender<EventType>, state: Arc<Mutex<StateWrapper>>) {
+    async fn connect_boot(
+        &self,
+        config: PrinterConfig,
+        sender: Sender<EventType>,
+        state: Arc<Mutex<StateWrapper>>,
+    ) {
         spawn(async move {
-            if state.lock().await.state != BridgeState::DISCONNECTED {
+            if state.lock().await.state != BridgeState::DISCONNECTED || !config.start_on_boot {
                 return;
             }
-
-            let mut connection = (SqliteConnection::connect("storage.db")).await.unwrap();
-            let query = sqlx::query_as::<_, SettingRow>(
-                "SELECT * FROM settings where id = 'B_startOnBoot' or id = 'S_devicePath' or id = 'N_deviceBaud'",
-            );
-
-            let result = query.fetch_all(&mut connection).await;
-            if result.is_ok() {
-                let rows = result.unwrap();
-                if rows.len() == 3 {
-                    let mut address: Option<String> = None;
-                    let mut baud_rate = 0;
-                    let mut connect = false;
-
-                    for row in rows {
-                        if row.id == "B_startOnBoot" {
-                            connect = row.bool.unwrap();
-                        } else if row.id == "S_devicePath" {
-                            address = Some(row.raw_value);
-                        } else if row.id == "N_deviceBaud" {
-                            baud_rate = row.number.unwrap() as u32;
-                        }
-                    }
-                    if connect && baud_rate != 0 && address.is_some() {
-                        println!("[BRIDGE] Connect on boot is set, trying to connect.");
-                        send(
-                            &sender,
-                            EventType::CreateBridge {
-                                address: address.unwrap(),
-                                port: baud_rate,
-                            },
-                        );
-                    }
-                }
+            // the bridge detects the baudrate when it's 0.
+            if let Ok((address, baud_rate)) = config.connection() {
+                println!(
+                    "[BRIDGE] Connect on boot is set for {}, trying to connect.",
+                    config.name
+                );
+                send(
+                    &sender,
+                    EventType::CreateBridge {
+                        address,
+                        port: baud_rate,
+                    },
+                );
             }
         });
     }
 
+    /// Send the new state to the clients, described the same way as in the ready event.
     async fn send_websockets_updated_state(&self, state_info: StateWrapper) {
-        let json = match state_info.state {
-            BridgeState::DISCONNECTED => json!({
-                    "type": "state_update",
-                    "content": {
-                            "state": "Disconnected",
-                            "description": serde_json::Value::Null
-                    }
-            })
-            .to_string(),
-            BridgeState::CONNECTING => json!({
-                    "type": "state_update",
-                    "content": {
-                            "state": "Connecting",
-                            "description": serde_json::Value::Null
-                    }
-            })
-            .to_string(),
-            BridgeState::CONNECTED => json!({
-                    "type": "state_update",
-                    "content": {
-                            "state": "Connected",
-                            "description": serde_json::Value::Null
-                    }
-            })
-            .to_string(),
-            BridgeState::ERRORED => match state_info.description {
-                StateDescription::Error { message } => json!({
-                        "type": "state_update",
-                        "content": {
-                                "state": "Errored",
-                                "description": {
-                                        "errorDescription": message
-                                }
-                        }
-                })
-                .to_string(),
-                _ => json!({
-                        "type": "state_update",
-                        "content": {
-                                "state": "Errored",
-                                "description": serde_json::Value::Null
-                        }
-                })
-                .to_string(),
-            },
-            BridgeState::PREPARING => todo!(),
-            BridgeState::PRINTING => match state_info.description {
-                StateDescription::Print {
-                    filename,
-                    progress,
-                    start,
-                    end,
-                } => {
-                    let mut end_string: Option<String> = None;
-                    if end.is_some() {
-                        end_string = Some(end.unwrap().to_rfc3339());
-                    }
-                    json!({
-                            "type": "state_update",
-                            "content": {
-                                "state": "Printing",
-                                "description": {
-                                    "printInfo": {
-                                        "file": {
-                                            "name": filename,
-                                        },
-                                        "progress": format!("{:.2}", progress),
-                                        "startTime": start.to_rfc3339(),
-                                        "estEndTime": end_string
-                                    }
-                                }
-                            }
-                    })
-                    .to_string()
+        let (state, description) = api_manager::websocket_handler::describe_state(&state_info);
+        let json = json!({
+                "type": "state_update",
+                "content": {
+                        "state": state,
+                        "description": description
                 }
-                _ => json!({
-                        "type": "state_update",
-                        "content": {
-                                "state": "Printing",
-                                "description": serde_json::Value::Null
-                        }
-                })
-                .to_string(),
-            },
-            BridgeState::FINISHING => todo!(),
-        };
+        });
+        self.broadcast(json).await;
+    }
+
+    /*
+        Notify the clients that the printer started waiting for the user (filament change, runout, M0),
+        the state update only says which state the print is in.
+    */
+    async fn send_websockets_wait_notification(&self, state_info: &StateWrapper) {
+        if let StateDescription::Print {
+            waiting: Some(reason),
+            ..
+        } = state_info.description
+        {
+            self.send_websockets_notification(json!(reason), reason.message()).await;
+        }
+    }
+
+    /// Send a message to every websocket client, tagged with the id of this printer.
+    async fn broadcast(&self, mut json: serde_json::Value) {
+        json["printer"] = json!(self.printer_id);
         send_to_all_ws_clients(json.to_string(), &self.websockets).await;
     }
+
+    async fn send_websockets_queue(&self) {
+        let jobs = match print_queue::list(self.printer_id).await {
+            Ok(jobs) => jobs,
+            Err(err) => {
+                eprintln!("[MAIN][ERROR] Cannot load the print queue: {}", err);
+                return;
+            }
+        };
+        let json = json!({
+                "type": "queue_update",
+                "content": {
+                        "jobs": jobs,
+                        "awaitingConfirmation": *self.queue_confirm.lock().await
+                }
+        });
+        self.broadcast(json).await;
+    }
+
+    async fn send_websockets_notification(&self, reason: serde_json::Value, message: &str) {
+        println!("[MAIN] {}", message);
+        let json = json!({
+                "type": "notification",
+                "content": {
+                        "reason": reason,
+                        "message": message
+                }
+        });
+        self.broadcast(json).await;
+    }
 }
 
 async fn setup_db() {
@@ -464,9 +660,80 @@ async fn setup_db() {
             type integer(3) not null
         );
 
+        CREATE TABLE IF NOT EXISTS temperature_history (
+            printer integer not null default 1,
+            heater varchar(8) not null,
+            time integer not null,
+            current real not null,
+            target real not null,
+            primary key (printer, heater, time)
+        );
+
+        CREATE TABLE IF NOT EXISTS terminal_log (
+            printer integer not null,
+            line integer not null,
+            message TEXT not null,
+            type varchar(8) not null,
+            message_id varchar(36),
+            time varchar(64) not null,
+            primary key (printer, line)
+        );
+
+        CREATE TABLE IF NOT EXISTS printers (
+            id integer primary key,
+            name varchar(255) not null,
+            device_path TEXT,
+            baud_rate integer,
+            auto_baud boolean not null default false,
+            start_on_boot boolean not null default false
+        );
+
+        CREATE TABLE IF NOT EXISTS print_queue (
+            id integer primary key autoincrement,
+            printer integer not null,
+            filename TEXT not null,
+            position integer not null,
+            added_by varchar(255) not null,
+            added integer not null
+        );
+
+        CREATE TABLE IF NOT EXISTS print_recovery (
+            printer integer primary key,
+            filename TEXT not null,
+            hash varchar(64) not null,
+            line integer not null,
+            heater_targets TEXT not null,
+            z real,
+            time integer not null
+        );
+
+        CREATE TABLE IF NOT EXISTS printer_settings (
+            printer integer not null,
+            id TEXT not null,
+            value TEXT not null,
+            primary key (printer, id)
+        );
+
+        CREATE TABLE IF NOT EXISTS print_jobs (
+            id integer primary key autoincrement,
+            printer integer not null,
+            filename TEXT not null,
+            user varchar(255),
+            start integer not null,
+            end integer not null,
+            outcome varchar(16) not null,
+            reason TEXT,
+            lines integer not null,
+            resends integer not null,
+            checksum_errors integer not null,
+            line_number_errors integer not null
+        );
+
+        INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('S_deviceName', 0, 'Printer');
         INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('S_devicePath', 0, null);
         INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('N_deviceBaud', 2, null);
         INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('B_startOnBoot', 1, false);
+        INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('B_autoBaud', 1, false);
         INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('F_adjustCorrectionF', 3, null);
         INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('B_savePrinterNotifications', 1, true);
         INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('B_savePrinterNotifications', 1, true);
@@ -476,6 +743,25 @@ async fn setup_db() {
         INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('B_deviceHB', 1, false);
         INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('B_deviceHC', 1, false);
         INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('N_clientTerminalAmount', 2, 500);
+        INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('S_pauseGcode', 0, '');
+        INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('S_resumeGcode', 0, '');
+        INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('S_startGcode', 0, '');
+        INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('S_endGcode', 0, '');
+        INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('S_cancelGcode', 0, '');
+        INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('F_defaultToolTemp', 3, '');
+        INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('F_defaultBedTemp', 3, '');
+        INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('N_tempPollInterval', 2, 2);
+        INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('N_positionPollInterval', 2, 2);
+        INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('B_persistTemperatureHistory', 1, false);
+        INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('N_communicationTimeout', 2, 30);
+        INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('N_bufferSize', 2, 0);
+        INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('N_rxBufferSize', 2, 128);
+        INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('S_resendPolicy', 0, 'abort');
+        INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('F_resendThreshold', 3, 10);
+        INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('N_sdPollInterval', 2, 2);
+        INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('B_queueAutoStart', 1, false);
+        INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('B_queueConfirmBedClear', 1, true);
+        INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('N_recoveryInterval', 2, 5);
         INSERT OR IGNORE INTO SETTINGS (id, type, value) VALUES ('S_sentryDsn', 0, 'https://cd35379ff0fc45daa30a67bfe9aa8b36@0229745.ingest.sentry.io/5778789');
 
         DELETE FROM tokens where expire < DATE('now');
@@ -483,4 +769,34 @@ async fn setup_db() {
         )
         .await
         .expect("Error while creating tables.");
+
+    // the temperature history of databases from before there were multiple printers belongs to the default printer.
+    let columns = sqlx::query("PRAGMA table_info(temperature_history)")
+        .fetch_all(&mut connection)
+        .await
+        .expect("Error while checking the temperature history table.");
+    if !columns.iter().any(|column| column.get::<String, _>("name") == "printer") {
+        connection
+            .execute(
+                "
+            ALTER TABLE temperature_history RENAME TO temperature_history_old;
+
+            CREATE TABLE temperature_history (
+                printer integer not null default 1,
+                heater varchar(8) not null,
+                time integer not null,
+                current real not null,
+                target real not null,
+                primary key (printer, heater, time)
+            );
+
+            INSERT INTO temperature_history (printer, heater, time, current, target)
+                SELECT 1, heater, time, current, target FROM temperature_history_old;
+
+            DROP TABLE temperature_history_old;
+        ",
+            )
+            .await
+            .expect("Error while migrating the temperature history table.");
+    }
 }
@@ -1,30 +1,45 @@
 use lazy_static::lazy_static;
 use regex::Regex;
-use serde::ser::SerializeStruct;
+use serde::{ser::SerializeStruct, Serialize};
 
-use crate::api_manager::models::{BridgeAction, EventType};
+use crate::{
+    api_manager::models::{BridgeAction, EventType, HostAction, LinkError, SdFile},
+    firmware::FirmwareProfile,
+};
 
 lazy_static! {
-    static ref TOOLTEMPREGEX: Regex = Regex::new(r"((T\d?):([\d\.]+) ?/([\d\.]+))+").unwrap();
-    static ref BEDTEMPREGEX: Regex = Regex::new(r"B:([\d\.]+) ?/([\d\.]+)").unwrap();
-    static ref CHAMBERREMPREGEX: Regex = Regex::new(r"((T\d?):([\d\.]+) ?/([\d\.]+))+").unwrap();
-    static ref LINENR: Regex = Regex::new(r"ok N(\d+)").unwrap();
-    static ref RESEND: Regex = Regex::new(r"Resend: N?:?(\d+)").unwrap();
+    static ref TEMPERATURE: Regex = Regex::new(concat!(
+        r"(?:^|\s)(?:",
+        r"(?P<sensor>T\d*|B|C|P|R|A):\s*(?P<current>-?[\d\.]+)(?:\s*/\s*(?P<target>-?[\d\.]+))?",
+        r"|(?P<heater>B|C)?@(?P<index>\d*):\s*(?P<power>[\d\.]+)",
+        r"|@(?P<trailing>[\d\.]+)",
+        r")"
+    ))
+    .unwrap();
+    // a sensor with a target temperature, present in the reports of every firmware family.
+    static ref TEMPERATURE_REPORT: Regex =
+        Regex::new(r"(?:^|\s)(?:T\d*|B|C):\s*-?[\d\.]+\s*/\s*-?[\d\.]+").unwrap();
+    static ref ADVANCED_OK: Regex = Regex::new(r"^ok(?: N\d+)? P(\d+) B(\d+)").unwrap();
+    static ref SD_FILE: Regex = Regex::new(r"^(\S+)(?:\s+(\d+))?(?:\s+(.+?))?\s*$").unwrap();
+    static ref SD_OPENED: Regex = Regex::new(r"File opened:\s*\S+\s+Size:\s*(\d+)").unwrap();
+    static ref SD_PROGRESS: Regex = Regex::new(r"SD printing byte (\d+)/(\d+)").unwrap();
+    static ref POSITION: Regex =
+        Regex::new(r"X:(-?[\d\.]+) ?Y:(-?[\d\.]+) ?Z:(-?[\d\.]+) ?E:(-?[\d\.]+)").unwrap();
 }
 pub struct Parser {}
 impl Parser {
-    pub fn parse_responses(responses: Vec<String>) -> BridgeAction {
+    pub fn parse_responses(responses: Vec<String>, firmware: &FirmwareProfile) -> BridgeAction {
         for response in responses {
-            if RESEND.is_match(&response) {
+            if firmware.resend.is_match(&response) {
                 return BridgeAction::Resend(
-                    RESEND.captures(&response).unwrap()[1]
+                    firmware.resend.captures(&response).unwrap()[1]
                         .parse::<usize>()
                         .unwrap(),
                 );
             }
-            if LINENR.is_match(&response) {
+            if firmware.ok.is_match(&response) {
                 return BridgeAction::Continue(Some(
-                    LINENR.captures(&response).unwrap()[1]
+                    firmware.ok.captures(&response).unwrap()[1]
                         .parse::<usize>()
                         .unwrap(),
                 ));
@@ -39,6 +54,124 @@ impl Parser {
         return BridgeAction::Continue(None);
     }
 
+    /*
+        Parse the free space in the serial buffer of the firmware from an ok with ADVANCED_OK enabled.
+        The free space in the planner (P) isn't used, lines waiting for the planner stay in the serial buffer.
+
+        Example: ok N1234 P15 B3
+    */
+    pub fn parse_buffer(input: &str) -> Option<usize> {
+        let captures = ADVANCED_OK.captures(input)?;
+        return captures[2].parse().ok();
+    }
+
+    /*
+        Parse a host action command, returns None for lines that aren't one and for unsupported actions.
+
+        Examples:
+        //action:pause
+        //action:paused filament_runout 0
+        //action:prompt_begin Filament runout
+        //action:prompt_choice Continue
+    */
+    pub fn parse_action(input: &str) -> Option<HostAction> {
+        let action = input.strip_prefix("//action:")?.trim();
+        let mut parts = action.splitn(2, ' ');
+        let name = parts.next().unwrap_or("");
+        let argument = parts.next().unwrap_or("").trim().to_string();
+
+        return match name {
+            "pause" => Some(HostAction::Pause),
+            // the runout sensor pauses the printer with a reason: //action:paused filament_runout <tool>
+            "paused" if argument.starts_with("filament_runout") => Some(HostAction::Runout),
+            "paused" => Some(HostAction::Paused),
+            "resume" => Some(HostAction::Resume),
+            "resumed" => Some(HostAction::Resumed),
+            "cancel" => Some(HostAction::Cancel),
+            "prompt_begin" => Some(HostAction::PromptBegin(argument)),
+            // prompt_button is used by older Marlin versions.
+            "prompt_choice" | "prompt_button" => Some(HostAction::PromptChoice(argument)),
+            "prompt_show" => Some(HostAction::PromptShow),
+            "prompt_end" => Some(HostAction::PromptEnd),
+            _ => None,
+        };
+    }
+
+    /*
+        Parse a transmission error of the firmware, returns None for other lines.
+
+        Examples:
+        Marlin:   Error:checksum mismatch, Last Line: 1234
+                  Error:Line Number is not Last Line Number+1, Last Line: 1234
+        Repetier: Error:Wrong checksum
+                  Error:expected line 1235 got 1236
+    */
+    pub fn parse_link_error(input: &str) -> Option<LinkError> {
+        let input = input.to_lowercase();
+        if !input.starts_with("error") {
+            return None;
+        }
+        if input.contains("checksum") {
+            return Some(LinkError::Checksum);
+        }
+        if input.contains("line number") || input.contains("expected line") {
+            return Some(LinkError::LineNumber);
+        }
+        return None;
+    }
+
+    /*
+        Parse a file of the SD card listing (M20), sent between Begin file list & End file list.
+        The size and long name are only sent by firmware that supports those.
+        Other messages can arrive in between (temperature reports, echo:, busy:, ok), those return None,
+        as does anything that doesn't look like a file name (without an extension or directory).
+
+        Examples:
+        CUBE.GCO
+        CUBE.GCO 12345
+        PARTS/BRACKE~1.GCO 12345 bracket with holes.gcode
+    */
+    pub fn parse_sd_file(input: &str) -> Option<SdFile> {
+        let input = input.trim();
+        let lowercase = input.to_lowercase();
+        if ["echo:", "busy:", "ok", "error", "//"]
+            .iter()
+            .any(|prefix| lowercase.starts_with(prefix))
+            || Parser::is_temperature_report(input)
+        {
+            return None;
+        }
+        let captures = SD_FILE.captures(input)?;
+        if !captures[1].contains('.') && !captures[1].contains('/') {
+            return None;
+        }
+        return Some(SdFile {
+            name: captures[1].to_string(),
+            size: captures.get(2).and_then(|size| size.as_str().parse().ok()),
+            long_name: captures.get(3).map(|name| name.as_str().to_string()),
+        });
+    }
+
+    /*
+        Parse the size of a file opened on the SD card (M23).
+
+        Example: File opened: CUBE.GCO Size: 12345
+    */
+    pub fn parse_sd_opened(input: &str) -> Option<u64> {
+        let captures = SD_OPENED.captures(input)?;
+        return captures[1].parse().ok();
+    }
+
+    /*
+        Parse the progress of an SD print (M27), as (position, size) in bytes.
+
+        Example: SD printing byte 1234/12345
+    */
+    pub fn parse_sd_progress(input: &str) -> Option<(u64, u64)> {
+        let captures = SD_PROGRESS.captures(input)?;
+        return Some((captures[1].parse().ok()?, captures[2].parse().ok()?));
+    }
+
     pub fn add_checksum(linenr: &usize, line: &str) -> String {
         let line = line.replace(" ", "");
         let line = format!("N{}{}", linenr, line);
@@ -50,51 +183,125 @@ impl Parser {
         return format!("{}*{}", line, cs);
     }
 
-    pub fn parse_temperature(input: &String) -> EventType {
-        let mut tools: Vec<TempInfo> = Vec::new();
+    /// Check if a line is a temperature report (M105 response or auto report), see parse_temperature.
+    pub fn is_temperature_report(input: &str) -> bool {
+        return TEMPERATURE_REPORT.is_match(input);
+    }
 
-        let mut bed: Option<TempInfo> = None;
-        let mut chamber: Option<TempInfo> = None;
+    /*
+        Parse a temperature report (M105 response or auto report).
 
-        for capture in TOOLTEMPREGEX.captures_iter(input) {
-            let current_temp = capture[3].parse().unwrap_or(0.0);
-            let target_temp = capture[4].parse().unwrap_or(0.0);
+        Sensors: T (active tool), T0..Tn (every tool), B (bed), C (chamber), P (probe), R (redundant), A (ambient).
+        Heater power: @: (active tool), @0..@n: (every tool), B@: (bed) & C@: (chamber),
+        or @<power> right after the sensor it belongs to (Smoothieware).
 
-            let info = TempInfo::new(capture[2].to_string(), current_temp, target_temp);
+        Examples:
+        Marlin:          ok T:210.00 /210.00 B:60.00 /60.00 T0:210.00 /210.00 T1:25.00 /0.00 @:64 B@:0 @0:64 @1:0
+        Prusa-Firmware:  T:210.0 /210.0 B:60.0 /60.0 T0:210.0 /210.0 @:64 B@:0 P:35.2 A:31.4
+        RepRapFirmware:  ok T0:210.0 /210.0 T1:25.1 /0.0 B:60.0 /60.0 C:30.2 /0.0
+        Smoothieware:    ok T:210.0 /210.0 @64 B:60.0 /60.0 @0
+        Repetier:        T:210.00 /210 B:60.00 /60 B@:0 @:64
+    */
+    pub fn parse_temperature(input: &str) -> EventType {
+        let mut sensors: Vec<TempInfo> = Vec::new();
+        let mut powers: Vec<(String, f64)> = Vec::new();
 
-            tools.push(info);
+        for capture in TEMPERATURE.captures_iter(input) {
+            if let Some(sensor) = capture.name("sensor") {
+                let current_temp = capture["current"].parse().unwrap_or(0.0);
+                let target_temp = capture
+                    .name("target")
+                    .and_then(|target| target.as_str().parse().ok());
+                sensors.push(TempInfo::new(
+                    sensor.as_str().to_string(),
+                    current_temp,
+                    target_temp,
+                ));
+            } else if let Some(power) = capture.name("power") {
+                let heater = match (capture.name("heater"), &capture["index"]) {
+                    (Some(heater), _) => heater.as_str().to_string(),
+                    (None, "") => "T".to_string(),
+                    (None, index) => format!("T{}", index),
+                };
+                powers.push((heater, power.as_str().parse().unwrap_or(0.0)));
+            } else if let Some(sensor) = sensors.last_mut() {
+                sensor.power = capture["trailing"].parse().ok();
+            }
         }
-        let bed_result = BEDTEMPREGEX.captures(input);
-        if bed_result.is_some() {
-            let bed_capture = bed_result.unwrap();
-            let current_temp = bed_capture[1].parse().unwrap_or(0.0);
-            let target_temp = bed_capture[2].parse().unwrap_or(0.0);
-
-            let info = TempInfo::new_no_name(current_temp, target_temp);
-            bed = Some(info);
+        for (heater, power) in powers {
+            if let Some(sensor) = sensors.iter_mut().find(|sensor| sensor.tool_name == heater) {
+                sensor.power = Some(power);
+            }
         }
 
-        let chamber_result = CHAMBERREMPREGEX.captures(input);
-        if chamber_result.is_some() {
-            let chamber_capture = chamber_result.unwrap();
-            let current_temp = chamber_capture[1].parse().unwrap_or(0.0);
-            let target_temp = chamber_capture[2].parse().unwrap_or(0.0);
-            let info = TempInfo::new_no_name(current_temp, target_temp);
-            chamber = Some(info);
+        let find = |name: &str| {
+            sensors
+                .iter()
+                .find(|sensor| sensor.tool_name == name)
+                .map(|sensor| sensor.without_name())
+        };
+        let mut numbered_tools: Vec<TempInfo> = sensors
+            .iter()
+            .filter(|sensor| sensor.tool_name.starts_with('T') && sensor.tool_name.len() > 1)
+            .cloned()
+            .collect();
+
+        // T is the active tool, which is also listed as Tn when there are multiple tools.
+        let active_tool = sensors
+            .iter()
+            .find(|sensor| sensor.tool_name == "T")
+            .cloned();
+        if numbered_tools.len() == 1 && numbered_tools[0].power.is_none() {
+            // a single tool only reports the power of the active tool (Prusa-Firmware).
+            numbered_tools[0].power = active_tool.as_ref().and_then(|tool| tool.power);
         }
+        let (tools, active_tool) = if numbered_tools.len() > 0 {
+            (numbered_tools, active_tool)
+        } else {
+            (active_tool.into_iter().collect(), None)
+        };
+
         return EventType::TempUpdate {
             tools,
-            bed,
-            chamber,
+            active_tool,
+            bed: find("B"),
+            chamber: find("C"),
+            probe: find("P"),
+            redundant: find("R"),
+            ambient: find("A"),
         };
     }
+
+    /*
+        Parse a M114 position report.
+
+        Example: X:10.00 Y:20.00 Z:0.30 E:1.20 Count X:800 Y:1600 Z:120
+    */
+    pub fn parse_position(input: &str) -> Option<Position> {
+        let captures = POSITION.captures(input)?;
+        return Some(Position {
+            x: captures[1].parse().ok()?,
+            y: captures[2].parse().ok()?,
+            z: captures[3].parse().ok()?,
+            e: captures[4].parse().ok()?,
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Position {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub e: f64,
 }
 
 #[derive(Debug, Clone)]
 pub struct TempInfo {
     tool_name: String,
     current_temp: f64,
-    target_temp: f64,
+    target_temp: Option<f64>,
+    power: Option<f64>,
 }
 
 impl serde::Serialize for TempInfo {
@@ -102,37 +309,253 @@ impl serde::Serialize for TempInfo {
     where
         S: serde::Serializer,
     {
-        if self.current_temp == 0.0 {
-            return serializer.serialize_none();
-        }
         if self.tool_name.len() == 0 {
-            let mut state = serializer.serialize_struct("TempInfo", 2)?;
+            let mut state = serializer.serialize_struct("TempInfo", 3)?;
             state.serialize_field("currentTemp", &self.current_temp)?;
             state.serialize_field("targetTemp", &self.target_temp)?;
+            state.serialize_field("power", &self.power)?;
             state.end()
         } else {
-            let mut state = serializer.serialize_struct("TempInfo", 2)?;
+            let mut state = serializer.serialize_struct("TempInfo", 4)?;
             state.serialize_field("name", &self.tool_name)?;
             state.serialize_field("currentTemp", &self.current_temp)?;
             state.serialize_field("targetTemp", &self.target_temp)?;
+            state.serialize_field("power", &self.power)?;
             state.end()
         }
     }
 }
 
 impl TempInfo {
-    pub fn new(tool_name: String, current_temp: f64, target_temp: f64) -> Self {
+    pub fn new(tool_name: String, current_temp: f64, target_temp: Option<f64>) -> Self {
         Self {
             tool_name,
             current_temp,
             target_temp,
+            power: None,
         }
     }
-    pub fn new_no_name(current_temp: f64, target_temp: f64) -> Self {
+
+    /// Copy of the temp info without a name, used for sensors that only exist once (like the bed).
+    fn without_name(&self) -> Self {
         Self {
             tool_name: String::new(),
-            current_temp,
-            target_temp,
+            ..self.clone()
         }
     }
+
+    /// Get a reference to the temp info's tool name.
+    pub fn name(&self) -> &str {
+        self.tool_name.as_str()
+    }
+
+    /// Get the temp info's current temperature.
+    pub fn current_temp(&self) -> f64 {
+        self.current_temp
+    }
+
+    /// Get the temp info's target temperature, 0 when the sensor has no target.
+    pub fn target_temp(&self) -> f64 {
+        self.target_temp.unwrap_or(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temperatures(
+        input: &str,
+    ) -> (
+        Vec<TempInfo>,
+        Option<TempInfo>,
+        Option<TempInfo>,
+        Option<TempInfo>,
+    ) {
+        return match Parser::parse_temperature(input) {
+            EventType::TempUpdate {
+                tools,
+                active_tool,
+                bed,
+                chamber,
+                ..
+            } => (tools, active_tool, bed, chamber),
+            other => panic!("expected a temperature update, got {:?}", other),
+        };
+    }
+
+    #[test]
+    fn parses_marlin_temperatures() {
+        let (tools, active_tool, bed, chamber) = temperatures(
+            "ok T:210.00 /210.00 B:60.00 /60.00 T0:210.00 /210.00 T1:25.00 /0.00 @:64 B@:0 @0:64 @1:0",
+        );
+        assert_eq!(tools.len(), 2);
+        assert_eq!(tools[0].name(), "T0");
+        assert_eq!(tools[0].target_temp(), 210.0);
+        assert_eq!(tools[0].power, Some(64.0));
+        assert_eq!(tools[1].name(), "T1");
+        assert_eq!(tools[1].current_temp(), 25.0);
+        assert_eq!(tools[1].power, Some(0.0));
+        assert_eq!(active_tool.unwrap().power, Some(64.0));
+        let bed = bed.unwrap();
+        assert_eq!(bed.name(), "");
+        assert_eq!(bed.current_temp(), 60.0);
+        assert_eq!(bed.power, Some(0.0));
+        assert!(chamber.is_none());
+    }
+
+    #[test]
+    fn parses_single_tool_temperatures() {
+        // Prusa-Firmware only reports the power of the active tool.
+        let (tools, _, bed, _) =
+            temperatures("T:210.0 /210.0 B:60.0 /60.0 T0:210.0 /210.0 @:64 B@:0 P:35.2 A:31.4");
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0].name(), "T0");
+        assert_eq!(tools[0].power, Some(64.0));
+        assert_eq!(bed.unwrap().target_temp(), 60.0);
+
+        // Smoothieware reports the power right after the sensor.
+        let (tools, active_tool, bed, _) = temperatures("ok T:210.0 /210.0 @64 B:60.0 /60.0 @0");
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0].name(), "T");
+        assert_eq!(tools[0].power, Some(64.0));
+        assert!(active_tool.is_none());
+        assert_eq!(bed.unwrap().power, Some(0.0));
+
+        let (tools, _, bed, _) = temperatures("T:210.00 /210 B:60.00 /60 B@:0 @:64");
+        assert_eq!(tools[0].power, Some(64.0));
+        assert_eq!(bed.unwrap().power, Some(0.0));
+    }
+
+    #[test]
+    fn parses_reprap_temperatures() {
+        let (tools, active_tool, bed, chamber) =
+            temperatures("ok T0:210.0 /210.0 T1:25.1 /0.0 B:60.0 /60.0 C:30.2 /0.0");
+        assert_eq!(tools.len(), 2);
+        assert_eq!(tools[1].current_temp(), 25.1);
+        assert_eq!(tools[1].target_temp(), 0.0);
+        assert!(active_tool.is_none());
+        assert_eq!(bed.unwrap().current_temp(), 60.0);
+        assert_eq!(chamber.unwrap().current_temp(), 30.2);
+    }
+
+    #[test]
+    fn recognizes_temperature_reports() {
+        assert!(Parser::is_temperature_report(
+            "ok T:210.00 /210.00 B:60.00 /60.00 @:64 B@:0"
+        ));
+        assert!(Parser::is_temperature_report(
+            "T0:210.0 /210.0 B:60.0 /60.0"
+        ));
+        assert!(!Parser::is_temperature_report("ok"));
+        assert!(!Parser::is_temperature_report("echo:busy: processing"));
+        assert!(!Parser::is_temperature_report(
+            "X:10.00 Y:20.00 Z:0.30 E:1.20 Count X:800 Y:1600 Z:120"
+        ));
+    }
+
+    #[test]
+    fn parses_actions() {
+        assert_eq!(
+            Parser::parse_action("//action:pause"),
+            Some(HostAction::Pause)
+        );
+        assert_eq!(
+            Parser::parse_action("//action:paused"),
+            Some(HostAction::Paused)
+        );
+        assert_eq!(
+            Parser::parse_action("//action:paused filament_runout 0"),
+            Some(HostAction::Runout)
+        );
+        assert_eq!(
+            Parser::parse_action("//action:resume"),
+            Some(HostAction::Resume)
+        );
+        assert_eq!(
+            Parser::parse_action("//action:cancel"),
+            Some(HostAction::Cancel)
+        );
+        assert_eq!(
+            Parser::parse_action("//action:prompt_begin Filament runout"),
+            Some(HostAction::PromptBegin("Filament runout".to_string()))
+        );
+        assert_eq!(
+            Parser::parse_action("//action:prompt_choice Continue"),
+            Some(HostAction::PromptChoice("Continue".to_string()))
+        );
+        assert_eq!(
+            Parser::parse_action("//action:prompt_button Continue"),
+            Some(HostAction::PromptChoice("Continue".to_string()))
+        );
+        assert_eq!(
+            Parser::parse_action("//action:prompt_end"),
+            Some(HostAction::PromptEnd)
+        );
+        assert_eq!(Parser::parse_action("//action:unknown"), None);
+        assert_eq!(Parser::parse_action("echo://action:pause"), None);
+    }
+
+    #[test]
+    fn parses_link_errors() {
+        assert_eq!(
+            Parser::parse_link_error("Error:checksum mismatch, Last Line: 1234"),
+            Some(LinkError::Checksum)
+        );
+        assert_eq!(
+            Parser::parse_link_error(
+                "Error:Line Number is not Last Line Number+1, Last Line: 1234"
+            ),
+            Some(LinkError::LineNumber)
+        );
+        assert_eq!(
+            Parser::parse_link_error("Error:Wrong checksum"),
+            Some(LinkError::Checksum)
+        );
+        assert_eq!(
+            Parser::parse_link_error("Error:expected line 1235 got 1236"),
+            Some(LinkError::LineNumber)
+        );
+        assert_eq!(
+            Parser::parse_link_error("Error:Printer halted. kill() called!"),
+            None
+        );
+        assert_eq!(Parser::parse_link_error("echo:checksum mismatch"), None);
+    }
+
+    #[test]
+    fn parses_sd_files() {
+        let file = Parser::parse_sd_file("CUBE.GCO").unwrap();
+        assert_eq!(file.name, "CUBE.GCO");
+        assert_eq!(file.size, None);
+        assert_eq!(file.long_name, None);
+
+        let file = Parser::parse_sd_file("CUBE.GCO 12345").unwrap();
+        assert_eq!(file.size, Some(12345));
+
+        let file =
+            Parser::parse_sd_file("PARTS/BRACKE~1.GCO 12345 bracket with holes.gcode").unwrap();
+        assert_eq!(file.name, "PARTS/BRACKE~1.GCO");
+        assert_eq!(file.size, Some(12345));
+        assert_eq!(file.long_name.as_deref(), Some("bracket with holes.gcode"));
+
+        assert!(Parser::parse_sd_file("echo:busy: processing").is_none());
+        assert!(Parser::parse_sd_file("ok").is_none());
+        assert!(Parser::parse_sd_file("T:210.00 /210.00 B:60.00 /60.00 @:64 B@:0").is_none());
+        assert!(Parser::parse_sd_file("wait").is_none());
+    }
+
+    #[test]
+    fn parses_sd_progress() {
+        assert_eq!(
+            Parser::parse_sd_opened("File opened: CUBE.GCO Size: 12345"),
+            Some(12345)
+        );
+        assert_eq!(Parser::parse_sd_opened("File selected"), None);
+        assert_eq!(
+            Parser::parse_sd_progress("SD printing byte 1234/12345"),
+            Some((1234, 12345))
+        );
+        assert_eq!(Parser::parse_sd_progress("Not SD printing"), None);
+    }
 }
@@ -0,0 +1,276 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Seek, SeekFrom},
+    collections::VecDeque,
+    path::Path,
+};
+
+// Amount of lines between two stored offsets in the line index.
+const INDEX_INTERVAL: usize = 256;
+
+/*
+    A gcode file that is streamed from disk while printing.
+
+    Lines are numbered the same way they are sent to the printer:
+    line 0 is the M110 N0 line number reset, followed by every line of the file
+    that still has content after removing comments and whitespace.
+
+    Instead of loading the file into memory, the offset of every INDEX_INTERVAL-th line is stored
+    while reading. A line that was read before (for example when a resend is requested)
+    is found by seeking to the closest stored offset and reading forward from there.
+
+    Print time hints of the slicer are collected while reading, see take_time_hint.
+*/
+#[derive(Debug)]
+pub struct PrintFile {
+    reader: BufReader<File>,
+    size: u64,
+    index: Vec<u64>,
+    // Line number of the next line the reader returns & its byte offset.
+    next_line: usize,
+    offset: u64,
+    line_count: usize,
+    // the line that was read last, it's requested again when it doesn't fit in the printer's buffer yet.
+    last_line: Option<(usize, String)>,
+    // print time hints of the slicer, in seconds.
+    total_time: Option<f64>,
+    elapsed_time: Option<f64>,
+    remaining_time: Option<f64>,
+    // remaining print time at a line, for the hints that weren't taken yet.
+    hints: VecDeque<(usize, f64)>,
+}
+
+impl PrintFile {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            reader: BufReader::new(file),
+            size,
+            index: vec![],
+            next_line: 1,
+            offset: 0,
+            line_count: 0,
+            last_line: None,
+            total_time: None,
+            elapsed_time: None,
+            remaining_time: None,
+            hints: VecDeque::new(),
+        })
+    }
+
+    /// Get the size of the file in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Get the byte offset right after the last line that was read.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Get the highest line number that has been read so far.
+    pub fn line_count(&self) -> usize {
+        self.line_count
+    }
+
+    /*
+        Take the remaining print time (in seconds) according to the slicer, once the given line is executed.
+        Returns the last hint at or before the line, None when there's no new hint for it.
+        Lines are read ahead of the printer, so the hint is only used once its line is acknowledged.
+
+        Hints:
+        M73 P<percent> R<minutes remaining> (PrusaSlicer)
+        ;TIME:<total seconds> and ;TIME_ELAPSED:<seconds> at every layer (Cura)
+    */
+    pub fn take_time_hint(&mut self, line_number: usize) -> Option<f64> {
+        let mut remaining = None;
+        while self.hints.front().map_or(false, |(line, _)| *line <= line_number) {
+            remaining = self.hints.pop_front().map(|(_, time)| time);
+        }
+        return remaining;
+    }
+
+    fn remaining_time(&self) -> Option<f64> {
+        if self.remaining_time.is_some() {
+            return self.remaining_time;
+        }
+        let total_time = self.total_time?;
+        return Some((total_time - self.elapsed_time.unwrap_or(0.0)).max(0.0));
+    }
+
+    /*
+        Get the content of a line.
+        Returns Ok(None) once the line is past the end of the file,
+        a file that can't be read (or isn't valid UTF-8) returns the error instead.
+    */
+    pub fn get_line(&mut self, line_number: usize) -> io::Result<Option<String>> {
+        if line_number == 0 {
+            return Ok(Some("M110 N0".to_string()));
+        }
+        if let Some((number, line)) = self.last_line.as_ref() {
+            if *number == line_number {
+                return Ok(Some(line.clone()));
+            }
+        }
+        if line_number < self.next_line {
+            let position = (line_number - 1) / INDEX_INTERVAL;
+            self.reader.seek(SeekFrom::Start(self.index[position]))?;
+            self.offset = self.index[position];
+            self.next_line = position * INDEX_INTERVAL + 1;
+        }
+        loop {
+            let line = match self.read_next_line()? {
+                Some(line) => line,
+                None => return Ok(None),
+            };
+            if self.next_line - 1 == line_number {
+                self.last_line = Some((line_number, line.clone()));
+                return Ok(Some(line));
+            }
+        }
+    }
+
+    /*
+        Read the print time hint in a line, the hint belongs to the next line with content (or the M73 line itself).
+        Lines that are read again (after seeking back) don't add hints twice.
+    */
+    fn read_time_hint(&mut self, line: &str, content: &str) {
+        if self.next_line <= self.line_count {
+            return;
+        }
+        if let Some(time) = line.strip_prefix(";TIME:") {
+            self.total_time = time.trim().parse().ok();
+        } else if let Some(time) = line.strip_prefix(";TIME_ELAPSED:") {
+            self.elapsed_time = time.trim().parse().ok();
+        } else if content.starts_with("M73 ") {
+            let minutes = content
+                .split_whitespace()
+                .find_map(|parameter| parameter.strip_prefix('R'))
+                .and_then(|minutes| minutes.parse::<f64>().ok());
+            match minutes {
+                Some(minutes) => self.remaining_time = Some(minutes * 60.0),
+                None => return,
+            }
+        } else {
+            return;
+        }
+        if let Some(remaining) = self.remaining_time() {
+            self.hints.push_back((self.next_line, remaining));
+        }
+    }
+
+    /*
+        Read lines until one with content is found.
+        Updates the line index when the line is the first of a new interval.
+    */
+    fn read_next_line(&mut self) -> io::Result<Option<String>> {
+        let mut buffer = String::new();
+        loop {
+            let start = self.offset;
+            buffer.clear();
+            let bytes = self.reader.read_line(&mut buffer)?;
+            if bytes == 0 {
+                return Ok(None);
+            }
+            self.offset += bytes as u64;
+
+            let content = buffer.split(';').next().unwrap_or("").trim();
+            self.read_time_hint(buffer.trim(), content);
+            if content.len() == 0 {
+                continue;
+            }
+            if (self.next_line - 1) % INDEX_INTERVAL == 0
+                && self.index.len() == (self.next_line - 1) / INDEX_INTERVAL
+            {
+                self.index.push(start);
+            }
+            if self.next_line > self.line_count {
+                self.line_count = self.next_line;
+            }
+            self.next_line += 1;
+            return Ok(Some(content.to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn print_file(name: &str, content: &str) -> PrintFile {
+        let filename = format!("server_v2-{}-{}.gcode", std::process::id(), name);
+        let path = std::env::temp_dir().join(filename);
+        std::fs::write(&path, content).unwrap();
+        return PrintFile::open(&path).unwrap();
+    }
+
+    fn line(file: &mut PrintFile, line_number: usize) -> Option<String> {
+        return file.get_line(line_number).unwrap();
+    }
+
+    #[test]
+    fn rereads_lines_through_the_index() {
+        let content: String = (1..=600)
+            .map(|line| format!("G1 X{} ; move {}\n", line, line))
+            .collect();
+        let mut file = print_file("index", &content);
+        assert_eq!(line(&mut file, 0).as_deref(), Some("M110 N0"));
+
+        // read ahead past a few index intervals, then request earlier lines like a resend would.
+        assert_eq!(line(&mut file, 600).as_deref(), Some("G1 X600"));
+        assert_eq!(line(&mut file, 601), None);
+        for line_number in [1, 255, 256, 257, 258, 513, 599].iter() {
+            let expected = format!("G1 X{}", line_number);
+            assert_eq!(line(&mut file, *line_number), Some(expected));
+        }
+        assert_eq!(line(&mut file, 600).as_deref(), Some("G1 X600"));
+        assert_eq!(file.line_count(), 600);
+        assert_eq!(file.offset(), file.size());
+    }
+
+    #[test]
+    fn skips_lines_without_content() {
+        let mut file = print_file(
+            "comments",
+            "; generated by a slicer\nG28 ; home\n\n   ;indented comment\n;\nG1 X1\n",
+        );
+        assert_eq!(line(&mut file, 1).as_deref(), Some("G28"));
+        assert_eq!(line(&mut file, 2).as_deref(), Some("G1 X1"));
+        assert_eq!(line(&mut file, 3), None);
+        assert_eq!(file.line_count(), 2);
+    }
+
+    #[test]
+    fn reads_cura_time_hints() {
+        let mut file = print_file(
+            "cura",
+            ";TIME:600\nG28\n;LAYER:0\n;TIME_ELAPSED:120\nG1 X1\nG1 X2\n;TIME_ELAPSED:300\nG1 X3\n",
+        );
+        assert_eq!(line(&mut file, 4).as_deref(), Some("G1 X3"));
+        assert_eq!(file.take_time_hint(0), None);
+        assert_eq!(file.take_time_hint(1), Some(600.0));
+        assert_eq!(file.take_time_hint(1), None);
+        // the hint belongs to the line after it.
+        assert_eq!(file.take_time_hint(2), Some(480.0));
+        assert_eq!(file.take_time_hint(3), None);
+        assert_eq!(file.take_time_hint(4), Some(300.0));
+    }
+
+    #[test]
+    fn reads_prusaslicer_time_hints() {
+        let mut file = print_file(
+            "prusaslicer",
+            "M73 P0 R10\nG28\nM73 P50 R5\nG1 X1\nM73 P100\n",
+        );
+        assert_eq!(line(&mut file, 5).as_deref(), Some("M73 P100"));
+        // a hint at the M73 line itself, M73 without remaining time isn't one.
+        assert_eq!(file.take_time_hint(1), Some(600.0));
+        assert_eq!(file.take_time_hint(5), Some(300.0));
+
+        // lines read again don't add their hints twice.
+        assert_eq!(line(&mut file, 1).as_deref(), Some("M73 P0 R10"));
+        assert_eq!(line(&mut file, 4).as_deref(), Some("G1 X1"));
+        assert_eq!(file.take_time_hint(5), None);
+    }
+}
@@ -0,0 +1,188 @@
+use serde::Serialize;
+use sqlx::{
+    query::Query,
+    sqlite::{Sqlite, SqliteArguments, SqliteRow},
+    Connection, Row, SqliteConnection,
+};
+
+// Amount of jobs returned at once when no limit is given & the highest limit allowed.
+pub const DEFAULT_LIMIT: u32 = 50;
+pub const MAX_LIMIT: u32 = 500;
+
+const FILTER: &str = "(? IS NULL OR printer = ?) AND (? IS NULL OR outcome = ?) AND (? IS NULL OR instr(filename, ?) > 0) AND (? IS NULL OR user = ?) AND (? IS NULL OR start >= ?) AND (? IS NULL OR start < ?)";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PrintOutcome {
+    Finished,
+    Cancelled,
+    Errored,
+}
+
+impl PrintOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PrintOutcome::Finished => "finished",
+            PrintOutcome::Cancelled => "cancelled",
+            PrintOutcome::Errored => "errored",
+        }
+    }
+
+    pub fn from_str(value: &str) -> Option<Self> {
+        match value {
+            "finished" => Some(PrintOutcome::Finished),
+            "cancelled" => Some(PrintOutcome::Cancelled),
+            "errored" => Some(PrintOutcome::Errored),
+            _ => None,
+        }
+    }
+}
+
+/*
+    A print that ran on a printer, recorded once it ended.
+
+    user: User that started the print, None when it's unknown.
+    start & end: Timestamps in milliseconds.
+    reason: Why the print errored.
+    lines: Lines sent to the printer (and acknowledged), the stats are the link stats of the print.
+*/
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PrintJob {
+    pub id: u32,
+    pub printer: u32,
+    pub filename: String,
+    pub user: Option<String>,
+    pub start: i64,
+    pub end: i64,
+    pub outcome: PrintOutcome,
+    pub reason: Option<String>,
+    pub lines: usize,
+    pub resends: usize,
+    pub checksum_errors: usize,
+    pub line_number_errors: usize,
+}
+
+impl PrintJob {
+    fn from_row(row: &SqliteRow) -> Self {
+        Self {
+            id: row.get::<i64, _>("id") as u32,
+            printer: row.get::<i64, _>("printer") as u32,
+            filename: row.get("filename"),
+            user: row.get("user"),
+            start: row.get("start"),
+            end: row.get("end"),
+            outcome: PrintOutcome::from_str(row.get("outcome")).unwrap_or(PrintOutcome::Errored),
+            reason: row.get("reason"),
+            lines: row.get::<i64, _>("lines") as usize,
+            resends: row.get::<i64, _>("resends") as usize,
+            checksum_errors: row.get::<i64, _>("checksum_errors") as usize,
+            line_number_errors: row.get::<i64, _>("line_number_errors") as usize,
+        }
+    }
+}
+
+/*
+    Filter & page of the history, every filter is optional.
+    filename matches every file containing it, since & until are compared to the start of the job.
+*/
+#[derive(Debug, Clone)]
+pub struct HistoryFilter {
+    pub printer: Option<u32>,
+    pub outcome: Option<PrintOutcome>,
+    pub filename: Option<String>,
+    pub user: Option<String>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub limit: u32,
+    pub offset: u32,
+}
+
+impl HistoryFilter {
+    pub fn new() -> Self {
+        Self {
+            printer: None,
+            outcome: None,
+            filename: None,
+            user: None,
+            since: None,
+            until: None,
+            limit: DEFAULT_LIMIT,
+            offset: 0,
+        }
+    }
+
+    // every filter is bound twice, see FILTER.
+    fn bind<'q>(
+        &'q self,
+        query: Query<'q, Sqlite, SqliteArguments<'q>>,
+    ) -> Query<'q, Sqlite, SqliteArguments<'q>> {
+        let outcome = self.outcome.map(|outcome| outcome.as_str());
+        return query
+            .bind(self.printer)
+            .bind(self.printer)
+            .bind(outcome)
+            .bind(outcome)
+            .bind(&self.filename)
+            .bind(&self.filename)
+            .bind(&self.user)
+            .bind(&self.user)
+            .bind(self.since)
+            .bind(self.since)
+            .bind(self.until)
+            .bind(self.until);
+    }
+}
+
+pub async fn record(job: &PrintJob) -> Result<(), sqlx::Error> {
+    let mut connection = SqliteConnection::connect("storage.db").await?;
+    sqlx::query(
+        "INSERT INTO print_jobs (printer, filename, user, start, end, outcome, reason, lines, resends, checksum_errors, line_number_errors) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(job.printer)
+    .bind(&job.filename)
+    .bind(&job.user)
+    .bind(job.start)
+    .bind(job.end)
+    .bind(job.outcome.as_str())
+    .bind(&job.reason)
+    .bind(job.lines as i64)
+    .bind(job.resends as i64)
+    .bind(job.checksum_errors as i64)
+    .bind(job.line_number_errors as i64)
+    .execute(&mut connection)
+    .await?;
+    return Ok(());
+}
+
+/// Get a page of the jobs matching the filter (latest first) & the total amount of matching jobs.
+pub async fn list(filter: &HistoryFilter) -> Result<(Vec<PrintJob>, u32), sqlx::Error> {
+    let mut connection = SqliteConnection::connect("storage.db").await?;
+    let select = format!(
+        "SELECT * FROM print_jobs WHERE {} ORDER BY start DESC, id DESC LIMIT ? OFFSET ?",
+        FILTER
+    );
+    let rows = filter
+        .bind(sqlx::query(&select))
+        .bind(filter.limit)
+        .bind(filter.offset)
+        .fetch_all(&mut connection)
+        .await?;
+    let count = format!("SELECT count(*) as total FROM print_jobs WHERE {}", FILTER);
+    let total = filter
+        .bind(sqlx::query(&count))
+        .fetch_one(&mut connection)
+        .await?
+        .get::<i64, _>("total");
+    return Ok((rows.iter().map(PrintJob::from_row).collect(), total as u32));
+}
+
+/// Remove a job from the history, returns false when it doesn't exist.
+pub async fn delete(id: u32) -> Result<bool, sqlx::Error> {
+    let mut connection = SqliteConnection::connect("storage.db").await?;
+    let result = sqlx::query("DELETE FROM print_jobs WHERE id = ?")
+        .bind(id)
+        .execute(&mut connection)
+        .await?;
+    return Ok(result.rows_affected() > 0);
+}
@@ -0,0 +1,153 @@
+use std::path::Path;
+
+use chrono::Utc;
+use crossbeam_channel::Sender;
+use serde::Serialize;
+use sqlx::{sqlite::SqliteRow, Connection, Row, SqliteConnection};
+
+use crate::{
+    api_manager::models::{send, EventType, PrintInfo},
+    print_file::PrintFile,
+};
+
+/*
+    A stored file waiting to be printed.
+    The jobs of a printer are printed in order of their position, the first job is printed next.
+*/
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueuedJob {
+    pub id: u32,
+    pub filename: String,
+    pub added_by: String,
+    // timestamp in milliseconds.
+    pub added: i64,
+}
+
+impl QueuedJob {
+    fn from_row(row: &SqliteRow) -> Self {
+        Self {
+            id: row.get::<i64, _>("id") as u32,
+            filename: row.get("filename"),
+            added_by: row.get("added_by"),
+            added: row.get("added"),
+        }
+    }
+}
+
+/// Get the queue of a printer, the first job is printed next.
+pub async fn list(printer: u32) -> Result<Vec<QueuedJob>, sqlx::Error> {
+    let mut connection = SqliteConnection::connect("storage.db").await?;
+    let rows = sqlx::query(
+        "SELECT id, filename, added_by, added FROM print_queue WHERE printer = ? ORDER BY position, id",
+    )
+    .bind(printer)
+    .fetch_all(&mut connection)
+    .await?;
+    return Ok(rows.iter().map(QueuedJob::from_row).collect());
+}
+
+/// Add a job to the end of the queue of a printer.
+pub async fn add(printer: u32, filename: &str, added_by: &str) -> Result<QueuedJob, sqlx::Error> {
+    let mut connection = SqliteConnection::connect("storage.db").await?;
+    let added = Utc::now().timestamp_millis();
+    let result = sqlx::query(
+        "INSERT INTO print_queue (printer, filename, position, added_by, added) VALUES (?, ?, (SELECT ifnull(max(position), -1) + 1 FROM print_queue WHERE printer = ?), ?, ?)",
+    )
+    .bind(printer)
+    .bind(filename)
+    .bind(printer)
+    .bind(added_by)
+    .bind(added)
+    .execute(&mut connection)
+    .await?;
+    return Ok(QueuedJob {
+        id: result.last_insert_rowid() as u32,
+        filename: filename.to_string(),
+        added_by: added_by.to_string(),
+        added,
+    });
+}
+
+/// Remove a job from the queue of a printer, returns false when the job isn't queued.
+pub async fn remove(printer: u32, id: u32) -> Result<bool, sqlx::Error> {
+    let mut connection = SqliteConnection::connect("storage.db").await?;
+    let result = sqlx::query("DELETE FROM print_queue WHERE printer = ? AND id = ?")
+        .bind(printer)
+        .bind(id)
+        .execute(&mut connection)
+        .await?;
+    return Ok(result.rows_affected() > 0);
+}
+
+/*
+    Move a job to another position in the queue of a printer, 0 is printed next.
+    Positions past the end move the job to the end. Returns false when the job isn't queued.
+*/
+pub async fn move_job(printer: u32, id: u32, position: usize) -> Result<bool, sqlx::Error> {
+    let mut ids: Vec<u32> = list(printer).await?.iter().map(|job| job.id).collect();
+    let index = match ids.iter().position(|job| *job == id) {
+        Some(index) => index,
+        None => return Ok(false),
+    };
+    ids.remove(index);
+    ids.insert(position.min(ids.len()), id);
+
+    let mut connection = SqliteConnection::connect("storage.db").await?;
+    for (position, id) in ids.iter().enumerate() {
+        sqlx::query("UPDATE print_queue SET position = ? WHERE id = ?")
+            .bind(position as u32)
+            .bind(id)
+            .execute(&mut connection)
+            .await?;
+    }
+    return Ok(true);
+}
+
+/// Keep queued jobs pointing to a stored file after it's renamed.
+pub async fn rename_file(old_name: &str, new_name: &str) -> Result<(), sqlx::Error> {
+    let mut connection = SqliteConnection::connect("storage.db").await?;
+    sqlx::query("UPDATE print_queue SET filename = ? WHERE filename = ?")
+        .bind(new_name)
+        .bind(old_name)
+        .execute(&mut connection)
+        .await?;
+    return Ok(());
+}
+
+/*
+    Start printing the first job of the queue of a printer, as the user that queued it.
+    The bridge removes the job from the queue once the print started,
+    so it stays queued when the printer is busy by then (see PrintInfo::queue_job).
+    Jobs of which the file can't be opened anymore are dropped.
+    Returns the name of the file, None when the queue is empty.
+
+    ! The printer has to be connected (and not printing), otherwise the bridge ignores the print.
+*/
+pub async fn start_next(
+    printer: u32,
+    distributor: &Sender<EventType>,
+) -> Result<Option<String>, sqlx::Error> {
+    loop {
+        let job = match list(printer).await?.into_iter().next() {
+            Some(job) => job,
+            None => return Ok(None),
+        };
+        let path = Path::new("./files/").join(&job.filename);
+        match PrintFile::open(&path) {
+            Ok(file) => {
+                println!("[QUEUE] Starting {} on printer {}", job.filename, printer);
+                let mut info = PrintInfo::new(job.filename.clone(), file, Utc::now());
+                info.user = Some(job.added_by);
+                info.queue_job = Some(job.id);
+                send(distributor, EventType::PrintStart(info));
+                return Ok(Some(job.filename));
+            }
+            Err(err) => {
+                eprintln!("[QUEUE][ERROR] Cannot open {}, skipping it: {}", job.filename, err);
+                remove(printer, job.id).await?;
+                send(distributor, EventType::QueueUpdate);
+            }
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use sqlx::{Connection, Row, SqliteConnection};
+use tokio::task::spawn_blocking;
+
+lazy_static! {
+    static ref WORD: Regex = Regex::new(r"([A-Z])\s*(-?\d*\.?\d+)?").unwrap();
+}
+
+/*
+    Progress of a print that's saved regularly, so the print can be recovered
+    after the server (or the host) restarted or the printer lost power mid-print.
+
+    line: Last line acknowledged by the printer, the print continues after it.
+          An ok only means the firmware queued the line, the moves still in its planner buffer
+          (16 for a default Marlin configuration) weren't executed when the printer lost power.
+          Those moves are missing from the recovered print, a small gap at the layer it stopped at.
+    heater_targets: Last known target temperatures.
+    z: Height of the nozzle in the last position report.
+*/
+#[derive(Debug, Clone)]
+pub struct RecoveryPoint {
+    pub filename: String,
+    pub hash: String,
+    pub line: usize,
+    pub heater_targets: Vec<(String, f64)>,
+    pub z: Option<f64>,
+    // timestamp in milliseconds.
+    pub time: i64,
+}
+
+/// Get the SHA-256 hash (hex) of a file, used to check the file didn't change before recovering a print of it.
+pub fn file_hash(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    return Ok(format!("{:x}", hasher.finalize()));
+}
+
+/// Check if the file of a recovery point was changed or removed since the point was saved.
+pub async fn file_changed(point: &RecoveryPoint) -> bool {
+    let path = Path::new("./files/").join(&point.filename);
+    return match spawn_blocking(move || file_hash(&path)).await {
+        Ok(Ok(hash)) => hash != point.hash,
+        _ => true,
+    };
+}
+
+pub async fn save(printer: u32, point: &RecoveryPoint) -> Result<(), sqlx::Error> {
+    let mut connection = SqliteConnection::connect("storage.db").await?;
+    sqlx::query(
+        "INSERT OR REPLACE INTO print_recovery (printer, filename, hash, line, heater_targets, z, time) VALUES (?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(printer)
+    .bind(&point.filename)
+    .bind(&point.hash)
+    .bind(point.line as i64)
+    .bind(serde_json::to_string(&point.heater_targets).unwrap())
+    .bind(point.z)
+    .bind(point.time)
+    .execute(&mut connection)
+    .await?;
+    return Ok(());
+}
+
+/// Load the saved progress of the interrupted print of a printer, if any.
+pub async fn load(printer: u32) -> Result<Option<RecoveryPoint>, sqlx::Error> {
+    let mut connection = SqliteConnection::connect("storage.db").await?;
+    let row = sqlx::query(
+        "SELECT filename, hash, line, heater_targets, z, time FROM print_recovery WHERE printer = ?",
+    )
+    .bind(printer)
+    .fetch_optional(&mut connection)
+    .await?;
+    return Ok(row.map(|row| RecoveryPoint {
+        filename: row.get("filename"),
+        hash: row.get("hash"),
+        line: row.get::<i64, _>("line") as usize,
+        heater_targets: serde_json::from_str(row.get("heater_targets")).unwrap_or_default(),
+        z: row.get("z"),
+        time: row.get("time"),
+    }));
+}
+
+/// Remove the saved progress of a printer, once its print ended or is discarded.
+pub async fn clear(printer: u32) -> Result<(), sqlx::Error> {
+    let mut connection = SqliteConnection::connect("storage.db").await?;
+    sqlx::query("DELETE FROM print_recovery WHERE printer = ?")
+        .bind(printer)
+        .execute(&mut connection)
+        .await?;
+    return Ok(());
+}
+
+/*
+    State of the printer according to the lines of a file, used to continue a recovered print.
+    Only what the printer forgets when it's reset is tracked.
+*/
+#[derive(Debug, Clone)]
+pub struct FileState {
+    pub z: f64,
+    pub e: f64,
+    pub relative_positioning: bool,
+    pub relative_extrusion: bool,
+    // fan speed (0 - 255).
+    pub fan: u32,
+    pub feedrate: Option<f64>,
+}
+
+impl FileState {
+    pub fn new() -> Self {
+        Self {
+            z: 0.0,
+            e: 0.0,
+            relative_positioning: false,
+            relative_extrusion: false,
+            fan: 0,
+            feedrate: None,
+        }
+    }
+
+    /// Apply a line of the file, comments have to be removed already.
+    pub fn track(&mut self, line: &str) {
+        let line = line.to_uppercase();
+        let mut words = WORD.captures_iter(&line).map(|captures| {
+            let value = captures.get(2).and_then(|value| value.as_str().parse::<f64>().ok());
+            (captures[1].chars().next().unwrap(), value)
+        });
+        let command = match words.next() {
+            Some((letter, Some(number))) if letter == 'G' || letter == 'M' => {
+                format!("{}{}", letter, number)
+            }
+            _ => return,
+        };
+        let words: Vec<(char, Option<f64>)> = words.collect();
+        let value = |letter: char| {
+            words
+                .iter()
+                .find(|(word, _)| *word == letter)
+                .map(|(_, value)| value.unwrap_or(0.0))
+        };
+        match command.as_str() {
+            "G0" | "G1" => {
+                if let Some(z) = value('Z') {
+                    self.z = if self.relative_positioning { self.z + z } else { z };
+                }
+                if let Some(e) = value('E') {
+                    self.e = if self.relative_extrusion { self.e + e } else { e };
+                }
+                if let Some(feedrate) = value('F') {
+                    self.feedrate = Some(feedrate);
+                }
+            }
+            "G28" => {
+                if value('Z').is_some() || !words.iter().any(|(word, _)| "XYZ".contains(*word)) {
+                    self.z = 0.0;
+                }
+            }
+            "G90" => {
+                self.relative_positioning = false;
+                self.relative_extrusion = false;
+            }
+            "G91" => {
+                self.relative_positioning = true;
+                self.relative_extrusion = true;
+            }
+            "G92" => {
+                if let Some(z) = value('Z') {
+                    self.z = z;
+                }
+                if let Some(e) = value('E') {
+                    self.e = e;
+                }
+            }
+            "M82" => self.relative_extrusion = false,
+            "M83" => self.relative_extrusion = true,
+            "M106" => self.fan = value('S').unwrap_or(255.0).max(0.0).min(255.0) as u32,
+            "M107" => self.fan = 0,
+            _ => (),
+        }
+    }
+}
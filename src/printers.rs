@@ -0,0 +1,381 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use crossbeam_channel::Sender;
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{Connection, Row, SqliteConnection};
+use tokio::sync::Mutex;
+
+use crate::{
+    api_manager::models::{
+        EventType, HostPrompt, LinkStats, SdFile, SettingRow, StateDescription, StateWrapper,
+    },
+    firmware::Firmware,
+    parser::Position,
+    temperature_history::TemperatureHistory,
+    terminal_log::TerminalLog,
+    transport::Transport,
+};
+
+// The printer that always exists, routes without a printer id are meant for it.
+pub const DEFAULT_PRINTER: u32 = 1;
+
+/*
+    The settings a printer can override, they depend on the printer and are read by its bridge & terminal log.
+    Printers without an override use the value of the settings table.
+*/
+pub const PRINTER_SETTINGS: [&str; 19] = [
+    "S_startGcode",
+    "S_endGcode",
+    "S_cancelGcode",
+    "S_pauseGcode",
+    "S_resumeGcode",
+    "N_bufferSize",
+    "N_rxBufferSize",
+    "S_resendPolicy",
+    "F_resendThreshold",
+    "N_tempPollInterval",
+    "N_positionPollInterval",
+    "N_sdPollInterval",
+    "N_communicationTimeout",
+    "N_recoveryInterval",
+    "B_queueAutoStart",
+    "B_queueConfirmBedClear",
+    "F_defaultToolTemp",
+    "F_defaultBedTemp",
+    "B_savePrinterNotifications",
+];
+
+/*
+    A printer managed by this server and how to connect to it.
+
+    The default printer is configured through the settings (S_deviceName, S_devicePath, N_deviceBaud,
+    B_autoBaud & B_startOnBoot), so setups from before there were multiple printers keep working.
+    Every other printer is stored in the printers table.
+*/
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PrinterConfig {
+    pub id: u32,
+    pub name: String,
+    pub device_path: String,
+    // 0 when it isn't set up.
+    pub baud_rate: u32,
+    pub auto_baud: bool,
+    pub start_on_boot: bool,
+    // overrides of the settings in PRINTER_SETTINGS, stored in the printer_settings table.
+    pub settings: BTreeMap<String, String>,
+}
+
+impl PrinterConfig {
+    pub fn new(name: String) -> Self {
+        Self {
+            id: 0,
+            name,
+            device_path: String::new(),
+            baud_rate: 0,
+            auto_baud: false,
+            start_on_boot: false,
+            settings: BTreeMap::new(),
+        }
+    }
+
+    /*
+        Apply the fields of a request body (name, devicePath, baudRate, autoBaud, startOnBoot & settings), missing fields are kept.
+        Settings are overridden one by one, null removes the override.
+        Returns false when a field has the wrong type, the name is empty or a setting can't be overridden.
+    */
+    pub fn update(&mut self, json: &Value) -> bool {
+        if let Some(name) = json.get("name") {
+            match name.as_str().map(str::trim) {
+                Some(name) if name.len() > 0 && name.len() <= 255 => self.name = name.to_string(),
+                _ => return false,
+            }
+        }
+        if let Some(device_path) = json.get("devicePath") {
+            match device_path.as_str() {
+                Some(device_path) => self.device_path = device_path.trim().to_string(),
+                None => return false,
+            }
+        }
+        if let Some(baud_rate) = json.get("baudRate") {
+            match baud_rate.as_u64() {
+                Some(baud_rate) if baud_rate <= u32::MAX as u64 => self.baud_rate = baud_rate as u32,
+                _ => return false,
+            }
+        }
+        if let Some(auto_baud) = json.get("autoBaud") {
+            match auto_baud.as_bool() {
+                Some(auto_baud) => self.auto_baud = auto_baud,
+                None => return false,
+            }
+        }
+        if let Some(start_on_boot) = json.get("startOnBoot") {
+            match start_on_boot.as_bool() {
+                Some(start_on_boot) => self.start_on_boot = start_on_boot,
+                None => return false,
+            }
+        }
+        if let Some(settings) = json.get("settings") {
+            let settings = match settings.as_object() {
+                Some(settings) => settings,
+                None => return false,
+            };
+            for (setting, value) in settings {
+                if !PRINTER_SETTINGS.contains(&setting.as_str()) {
+                    return false;
+                }
+                if value.is_null() {
+                    self.settings.remove(setting);
+                    continue;
+                }
+                match PrinterConfig::setting_value(setting, value) {
+                    Some(value) => self.settings.insert(setting.clone(), value),
+                    None => return false,
+                };
+            }
+        }
+        return true;
+    }
+
+    /// Convert the value of a setting to how it's stored, the type is given by the prefix of the setting.
+    fn setting_value(setting: &str, value: &Value) -> Option<String> {
+        return match &setting[..2] {
+            "S_" => value.as_str().map(str::to_string),
+            "N_" => value.as_u64().map(|value| value.to_string()),
+            "F_" => value.as_f64().map(|value| value.to_string()),
+            "B_" => value.as_bool().map(|value| value.to_string()),
+            _ => None,
+        };
+    }
+
+    /// Load every printer, starting with the default printer.
+    pub async fn load_all() -> Result<Vec<PrinterConfig>, sqlx::Error> {
+        let mut connection = SqliteConnection::connect("storage.db").await?;
+        let mut printers = vec![PrinterConfig::load_default(&mut connection).await?];
+        let rows = sqlx::query(
+            "SELECT id, name, ifnull(device_path, '') as device_path, ifnull(baud_rate, 0) as baud_rate, auto_baud, start_on_boot FROM printers ORDER BY id",
+        )
+        .fetch_all(&mut connection)
+        .await?;
+        for row in rows {
+            printers.push(PrinterConfig {
+                id: row.get::<i64, _>("id") as u32,
+                name: row.get("name"),
+                device_path: row.get("device_path"),
+                baud_rate: row.get::<i64, _>("baud_rate") as u32,
+                auto_baud: row.get("auto_baud"),
+                start_on_boot: row.get("start_on_boot"),
+                settings: BTreeMap::new(),
+            });
+        }
+        let rows = sqlx::query("SELECT printer, id, value FROM printer_settings")
+            .fetch_all(&mut connection)
+            .await?;
+        for row in rows {
+            let id = row.get::<i64, _>("printer") as u32;
+            if let Some(printer) = printers.iter_mut().find(|printer| printer.id == id) {
+                printer.settings.insert(row.get("id"), row.get("value"));
+            }
+        }
+        return Ok(printers);
+    }
+
+    pub async fn load(id: u32) -> Result<Option<PrinterConfig>, sqlx::Error> {
+        let printers = PrinterConfig::load_all().await?;
+        return Ok(printers.into_iter().find(|printer| printer.id == id));
+    }
+
+    async fn load_default(connection: &mut SqliteConnection) -> Result<PrinterConfig, sqlx::Error> {
+        let rows = sqlx::query_as::<_, SettingRow>(
+            "SELECT id, type, ifnull(value, '') as value FROM settings where id in ('S_deviceName', 'S_devicePath', 'N_deviceBaud', 'B_autoBaud', 'B_startOnBoot')",
+        )
+        .fetch_all(connection)
+        .await?;
+        let mut printer = PrinterConfig::new("Printer".to_string());
+        printer.id = DEFAULT_PRINTER;
+        for row in rows {
+            match row.id.as_str() {
+                "S_deviceName" if row.raw_value.len() > 0 => printer.name = row.raw_value,
+                "S_devicePath" => printer.device_path = row.raw_value.trim().to_string(),
+                "N_deviceBaud" => printer.baud_rate = row.number.unwrap_or(0) as u32,
+                "B_autoBaud" => printer.auto_baud = row.bool.unwrap_or(false),
+                "B_startOnBoot" => printer.start_on_boot = row.bool.unwrap_or(false),
+                _ => (),
+            }
+        }
+        return Ok(printer);
+    }
+
+    /// Store a new printer, the id is assigned here.
+    pub async fn insert(&mut self) -> Result<(), sqlx::Error> {
+        let mut connection = SqliteConnection::connect("storage.db").await?;
+        let row = sqlx::query("SELECT max(ifnull(max(id), 0), ?) + 1 as id FROM printers")
+            .bind(DEFAULT_PRINTER)
+            .fetch_one(&mut connection)
+            .await?;
+        self.id = row.get::<i64, _>("id") as u32;
+        sqlx::query(
+            "INSERT INTO printers (id, name, device_path, baud_rate, auto_baud, start_on_boot) VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(self.id)
+        .bind(&self.name)
+        .bind(&self.device_path)
+        .bind(self.baud_rate)
+        .bind(self.auto_baud)
+        .bind(self.start_on_boot)
+        .execute(&mut connection)
+        .await?;
+        self.save_settings(&mut connection).await?;
+        return Ok(());
+    }
+
+    pub async fn save(&self) -> Result<(), sqlx::Error> {
+        let mut connection = SqliteConnection::connect("storage.db").await?;
+        if self.id == DEFAULT_PRINTER {
+            let settings = [
+                ("S_deviceName", self.name.clone()),
+                ("S_devicePath", self.device_path.clone()),
+                ("N_deviceBaud", self.baud_rate.to_string()),
+                ("B_autoBaud", self.auto_baud.to_string()),
+                ("B_startOnBoot", self.start_on_boot.to_string()),
+            ];
+            for (id, value) in settings.iter() {
+                sqlx::query("UPDATE settings SET value = ? WHERE id = ?")
+                    .bind(value)
+                    .bind(id)
+                    .execute(&mut connection)
+                    .await?;
+            }
+            self.save_settings(&mut connection).await?;
+            return Ok(());
+        }
+        sqlx::query(
+            "UPDATE printers SET name = ?, device_path = ?, baud_rate = ?, auto_baud = ?, start_on_boot = ? WHERE id = ?",
+        )
+        .bind(&self.name)
+        .bind(&self.device_path)
+        .bind(self.baud_rate)
+        .bind(self.auto_baud)
+        .bind(self.start_on_boot)
+        .bind(self.id)
+        .execute(&mut connection)
+        .await?;
+        self.save_settings(&mut connection).await?;
+        return Ok(());
+    }
+
+    /// Replace the stored setting overrides of the printer.
+    async fn save_settings(&self, connection: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM printer_settings WHERE printer = ?")
+            .bind(self.id)
+            .execute(&mut *connection)
+            .await?;
+        for (setting, value) in self.settings.iter() {
+            sqlx::query("INSERT INTO printer_settings (printer, id, value) VALUES (?, ?, ?)")
+                .bind(self.id)
+                .bind(setting)
+                .bind(value)
+                .execute(&mut *connection)
+                .await?;
+        }
+        return Ok(());
+    }
+
+    /// Remove a printer, the default printer cannot be removed.
+    pub async fn delete(id: u32) -> Result<(), sqlx::Error> {
+        if id == DEFAULT_PRINTER {
+            return Ok(());
+        }
+        let mut connection = SqliteConnection::connect("storage.db").await?;
+        sqlx::query("DELETE FROM printers WHERE id = ?")
+            .bind(id)
+            .execute(&mut connection)
+            .await?;
+        sqlx::query("DELETE FROM temperature_history WHERE printer = ?")
+            .bind(id)
+            .execute(&mut connection)
+            .await?;
+        sqlx::query("DELETE FROM terminal_log WHERE printer = ?")
+            .bind(id)
+            .execute(&mut connection)
+            .await?;
+        sqlx::query("DELETE FROM print_queue WHERE printer = ?")
+            .bind(id)
+            .execute(&mut connection)
+            .await?;
+        sqlx::query("DELETE FROM print_recovery WHERE printer = ?")
+            .bind(id)
+            .execute(&mut connection)
+            .await?;
+        sqlx::query("DELETE FROM printer_settings WHERE printer = ?")
+            .bind(id)
+            .execute(&mut connection)
+            .await?;
+        // ids are reused, a printer added later mustn't inherit the history.
+        sqlx::query("DELETE FROM print_jobs WHERE printer = ?")
+            .bind(id)
+            .execute(&mut connection)
+            .await?;
+        return Ok(());
+    }
+
+    /*
+        Get the device path & baudrate to connect with, the baudrate is 0 when it has to be detected.
+        Returns a message for the user when the connection isn't set up.
+    */
+    pub fn connection(&self) -> Result<(String, u32), String> {
+        if self.device_path.len() == 0 {
+            return Err("No device path set up".to_string());
+        }
+        if self.auto_baud {
+            return Ok((self.device_path.clone(), 0));
+        }
+        if self.baud_rate == 0 {
+            return Err("No baudrate set up, set one or enable detecting it".to_string());
+        }
+        return Ok((self.device_path.clone(), self.baud_rate));
+    }
+}
+
+/*
+    Everything the api needs to address a running printer.
+    The arcs are shared with the manager of the printer, the distributor is its event channel.
+*/
+#[derive(Clone)]
+pub struct PrinterHandle {
+    pub id: u32,
+    pub name: String,
+    pub distributor: Sender<EventType>,
+    pub state: Arc<Mutex<StateWrapper>>,
+    pub temperature_history: Arc<Mutex<TemperatureHistory>>,
+    pub terminal_log: Arc<Mutex<TerminalLog>>,
+    pub position: Arc<Mutex<Option<Position>>>,
+    pub host_prompt: Arc<Mutex<Option<HostPrompt>>>,
+    pub link_stats: Arc<Mutex<Option<LinkStats>>>,
+    pub sd_files: Arc<Mutex<Option<Vec<SdFile>>>>,
+    pub queue_confirm: Arc<Mutex<bool>>,
+    // clone of the bridge's connection, written to directly for an emergency stop.
+    pub emergency_port: Arc<Mutex<Option<Box<dyn Transport>>>>,
+    pub firmware: Arc<Mutex<Option<Firmware>>>,
+}
+
+// Running printers, mapped by id.
+pub type Printers = Arc<Mutex<HashMap<u32, PrinterHandle>>>;
+
+/// Check if any printer is printing (or uploading) the stored file with the given name.
+pub async fn file_in_use(printers: &Printers, name: &str) -> bool {
+    for printer in printers.lock().await.values() {
+        if let StateDescription::Print { filename, .. } | StateDescription::Upload { filename, .. } =
+            &printer.state.lock().await.description
+        {
+            if filename == name {
+                return true;
+            }
+        }
+    }
+    return false;
+}
@@ -0,0 +1,180 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::Serialize;
+use sqlx::{Connection, Row, SqliteConnection};
+
+use crate::api_manager::models::SettingRow;
+
+// Readings within the same interval (in milliseconds) are averaged into a single sample.
+pub const SAMPLE_INTERVAL: i64 = 5_000;
+// How long samples are kept (in milliseconds).
+const RETENTION: i64 = 60 * 60 * 1000;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sample {
+    pub time: i64,
+    pub current_temp: f64,
+    pub target_temp: f64,
+    #[serde(skip)]
+    readings: u32,
+}
+
+/*
+    Rolling history of the temperatures of every heater (and sensor) of a printer.
+
+    Readings are downsampled to one sample per SAMPLE_INTERVAL, samples older than RETENTION are removed.
+    When the B_persistTemperatureHistory setting is enabled, completed samples are also stored in the database,
+    so the history survives a restart.
+*/
+pub struct TemperatureHistory {
+    heaters: HashMap<String, VecDeque<Sample>>,
+}
+
+impl TemperatureHistory {
+    pub fn new() -> Self {
+        Self {
+            heaters: HashMap::new(),
+        }
+    }
+
+    /*
+        Add a reading of a heater, time is a timestamp in milliseconds.
+        Returns the previous sample of the heater when this reading starts a new sample,
+        the previous sample won't change anymore after that.
+    */
+    pub fn record(
+        &mut self,
+        heater: &str,
+        time: i64,
+        current_temp: f64,
+        target_temp: f64,
+    ) -> Option<Sample> {
+        let sample_time = time - time % SAMPLE_INTERVAL;
+        let samples = self
+            .heaters
+            .entry(heater.to_string())
+            .or_insert_with(VecDeque::new);
+
+        if let Some(last) = samples.back_mut() {
+            if last.time == sample_time {
+                last.current_temp = (last.current_temp * last.readings as f64 + current_temp)
+                    / (last.readings + 1) as f64;
+                last.target_temp = target_temp;
+                last.readings += 1;
+                return None;
+            }
+        }
+        let completed = samples.back().cloned();
+        samples.push_back(Sample {
+            time: sample_time,
+            current_temp,
+            target_temp,
+            readings: 1,
+        });
+        while samples.front().map_or(false, |sample| sample.time < time - RETENTION) {
+            samples.pop_front();
+        }
+        return completed;
+    }
+
+    /// Get the samples of every heater starting at the given timestamp (in milliseconds).
+    pub fn since(&self, time: i64) -> HashMap<String, Vec<Sample>> {
+        let mut result = HashMap::new();
+        for (heater, samples) in &self.heaters {
+            let samples: Vec<Sample> = samples
+                .iter()
+                .filter(|sample| sample.time >= time)
+                .cloned()
+                .collect();
+            if samples.len() > 0 {
+                result.insert(heater.to_string(), samples);
+            }
+        }
+        return result;
+    }
+
+    /*
+        Load the stored history of the printer of the last RETENTION, when persisting is enabled.
+        Returns an empty history otherwise.
+    */
+    pub async fn load(printer: u32, now: i64) -> Self {
+        let mut history = Self::new();
+        let mut connection = (SqliteConnection::connect("storage.db")).await.unwrap();
+        if !TemperatureHistory::is_persisted(&mut connection).await {
+            return history;
+        }
+        let query = sqlx::query(
+            "SELECT heater, time, current, target FROM temperature_history WHERE printer = ? AND time >= ? ORDER BY time",
+        )
+        .bind(printer)
+        .bind(now - RETENTION);
+
+        match query.fetch_all(&mut connection).await {
+            Ok(rows) => {
+                for row in rows {
+                    let heater: String = row.get("heater");
+                    history
+                        .heaters
+                        .entry(heater)
+                        .or_insert_with(VecDeque::new)
+                        .push_back(Sample {
+                            time: row.get("time"),
+                            current_temp: row.get("current"),
+                            target_temp: row.get("target"),
+                            readings: 1,
+                        });
+                }
+            }
+            Err(err) => eprintln!("[TEMPERATURE][ERROR] Cannot load history: {}", err),
+        }
+        return history;
+    }
+
+    /*
+        Store completed samples of the printer and remove the ones older than RETENTION.
+        Does nothing when persisting is disabled.
+    */
+    pub async fn persist(printer: u32, samples: Vec<(String, Sample)>, now: i64) {
+        let mut connection = (SqliteConnection::connect("storage.db")).await.unwrap();
+        if !TemperatureHistory::is_persisted(&mut connection).await {
+            return;
+        }
+        for (heater, sample) in samples {
+            let result = sqlx::query(
+                "INSERT OR REPLACE INTO temperature_history (printer, heater, time, current, target) VALUES (?, ?, ?, ?, ?)",
+            )
+            .bind(printer)
+            .bind(heater)
+            .bind(sample.time)
+            .bind(sample.current_temp)
+            .bind(sample.target_temp)
+            .execute(&mut connection)
+            .await;
+            if let Err(err) = result {
+                eprintln!("[TEMPERATURE][ERROR] Cannot store sample: {}", err);
+                return;
+            }
+        }
+        let result = sqlx::query("DELETE FROM temperature_history WHERE time < ?")
+            .bind(now - RETENTION)
+            .execute(&mut connection)
+            .await;
+        if let Err(err) = result {
+            eprintln!("[TEMPERATURE][ERROR] Cannot remove old samples: {}", err);
+        }
+    }
+
+    async fn is_persisted(connection: &mut SqliteConnection) -> bool {
+        let query = sqlx::query_as::<_, SettingRow>(
+            "SELECT * FROM settings where id = 'B_persistTemperatureHistory'",
+        );
+        match query.fetch_optional(connection).await {
+            Ok(row) => row.and_then(|row| row.bool).unwrap_or(false),
+            Err(err) => {
+                eprintln!("[TEMPERATURE][ERROR] {}", err);
+                false
+            }
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::VecDeque;
+
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::{sqlite::SqliteRow, Connection, Row, SqliteConnection};
+use tokio::sync::Mutex;
+
+use crate::api_manager::models::SettingRow;
+
+// Amount of lines kept in memory.
+const CAPACITY: usize = 2_000;
+// Amount of lines kept in the database for each printer.
+const STORED_LINES: i64 = 50_000;
+// New lines are written to the database in batches, at most once per interval (in milliseconds).
+const FLUSH_INTERVAL: i64 = 2_000;
+
+/*
+    A line of terminal traffic, serialized like the content of the terminal_message websocket event.
+
+    line: Number of the line in the log of the printer, increasing.
+    kind: INPUT for commands sent to the printer, OUTPUT for responses of the printer.
+    id: Id of the sent command, only set for INPUT lines.
+*/
+#[derive(Debug, Clone, Serialize)]
+pub struct TerminalLine {
+    pub line: i64,
+    pub message: String,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub id: Option<String>,
+    pub time: String,
+}
+
+impl TerminalLine {
+    fn from_row(row: &SqliteRow) -> Self {
+        Self {
+            line: row.get("line"),
+            message: row.get("message"),
+            kind: if row.get::<String, _>("type") == "INPUT" {
+                "INPUT"
+            } else {
+                "OUTPUT"
+            },
+            id: row.get("message_id"),
+            time: row.get("time"),
+        }
+    }
+
+    fn matches(&self, search: Option<&str>) -> bool {
+        match search {
+            Some(search) => self.message.to_lowercase().contains(search),
+            None => true,
+        }
+    }
+}
+
+/*
+    Terminal traffic of a printer, the last CAPACITY lines are kept in memory.
+
+    When the B_savePrinterNotifications setting is enabled for the printer, lines are also stored in the database
+    (the last STORED_LINES of every printer), so earlier output survives a restart and can be searched.
+*/
+pub struct TerminalLog {
+    lines: VecDeque<TerminalLine>,
+    next_line: i64,
+    // lines that aren't written to the database yet.
+    unsaved: Vec<TerminalLine>,
+    last_flush: i64,
+}
+
+impl TerminalLog {
+    pub fn new() -> Self {
+        Self {
+            lines: VecDeque::new(),
+            next_line: 1,
+            unsaved: vec![],
+            last_flush: 0,
+        }
+    }
+
+    /// Add a line to the log, id is the id of the sent command for INPUT lines.
+    pub fn push(&mut self, kind: &'static str, message: &str, id: Option<String>) -> TerminalLine {
+        let line = TerminalLine {
+            line: self.next_line,
+            message: message.to_string(),
+            kind,
+            id,
+            time: Utc::now().to_rfc3339(),
+        };
+        self.next_line += 1;
+        self.lines.push_back(line.clone());
+        if self.lines.len() > CAPACITY {
+            self.lines.pop_front();
+        }
+        self.unsaved.push(line.clone());
+        return line;
+    }
+
+    /// Get the last lines of the log (oldest first), at most the given amount.
+    pub fn last(&self, amount: usize) -> Vec<TerminalLine> {
+        let skip = self.lines.len().saturating_sub(amount);
+        return self.lines.iter().skip(skip).cloned().collect();
+    }
+
+    /*
+        Take the lines that have to be written to the database,
+        None when the last batch was taken less than FLUSH_INTERVAL ago or there are no new lines.
+    */
+    pub fn take_unsaved(&mut self, now: i64) -> Option<Vec<TerminalLine>> {
+        if self.unsaved.len() == 0 || now - self.last_flush < FLUSH_INTERVAL {
+            return None;
+        }
+        self.last_flush = now;
+        return Some(self.unsaved.drain(..).collect());
+    }
+
+    /*
+        Get at most limit lines before the given line (oldest first), containing search (case insensitive).
+        Lines that aren't in memory anymore are loaded from the database when storing is enabled.
+
+        The log is only locked to copy the matching lines in memory, as the manager locks it for every line
+        sent or received and searching the database can take a while.
+    */
+    pub async fn before(
+        log: &Mutex<TerminalLog>,
+        printer: u32,
+        before: Option<i64>,
+        limit: usize,
+        search: Option<&str>,
+    ) -> Result<Vec<TerminalLine>, sqlx::Error> {
+        let before = before.unwrap_or(i64::MAX);
+        let search = search.map(str::to_lowercase);
+        let (mut lines, first) = {
+            let log = log.lock().await;
+            let lines: Vec<TerminalLine> = log
+                .lines
+                .iter()
+                .rev()
+                .filter(|line| line.line < before && line.matches(search.as_deref()))
+                .take(limit)
+                .cloned()
+                .collect();
+            // the lines in memory are the last lines of the log, older lines can only be stored.
+            let first = log.lines.front().map_or(log.next_line, |line| line.line);
+            (lines, first)
+        };
+
+        if lines.len() < limit && first > 1 {
+            let mut connection = (SqliteConnection::connect("storage.db")).await?;
+            if TerminalLog::is_persisted(&mut connection, printer).await {
+                let rows = sqlx::query(
+                    "SELECT line, message, type, message_id, time FROM terminal_log WHERE printer = ? AND line < ? AND (? IS NULL OR instr(lower(message), ?) > 0) ORDER BY line DESC LIMIT ?",
+                )
+                .bind(printer)
+                .bind(before.min(first))
+                .bind(&search)
+                .bind(&search)
+                .bind((limit - lines.len()) as i64)
+                .fetch_all(&mut connection)
+                .await?;
+                lines.extend(rows.iter().map(TerminalLine::from_row));
+            }
+        }
+        lines.reverse();
+        return Ok(lines);
+    }
+
+    /*
+        Load the last stored lines of the printer, when storing is enabled.
+        Returns an empty log otherwise, numbered after the stored lines.
+    */
+    pub async fn load(printer: u32) -> Self {
+        let mut log = Self::new();
+        let mut connection = (SqliteConnection::connect("storage.db")).await.unwrap();
+        let last = sqlx::query("SELECT ifnull(max(line), 0) as line FROM terminal_log WHERE printer = ?")
+            .bind(printer)
+            .fetch_one(&mut connection)
+            .await;
+        match last {
+            Ok(row) => log.next_line = row.get::<i64, _>("line") + 1,
+            Err(err) => eprintln!("[TERMINAL][ERROR] Cannot load log: {}", err),
+        }
+        if !TerminalLog::is_persisted(&mut connection, printer).await {
+            return log;
+        }
+        let query = sqlx::query(
+            "SELECT line, message, type, message_id, time FROM terminal_log WHERE printer = ? ORDER BY line DESC LIMIT ?",
+        )
+        .bind(printer)
+        .bind(CAPACITY as i64);
+
+        match query.fetch_all(&mut connection).await {
+            Ok(rows) => {
+                log.lines.extend(rows.iter().rev().map(TerminalLine::from_row));
+            }
+            Err(err) => eprintln!("[TERMINAL][ERROR] Cannot load log: {}", err),
+        }
+        return log;
+    }
+
+    /*
+        Store lines of the printer and remove the ones older than the last STORED_LINES.
+        Does nothing when storing is disabled.
+    */
+    pub async fn persist(printer: u32, lines: Vec<TerminalLine>) {
+        let mut connection = (SqliteConnection::connect("storage.db")).await.unwrap();
+        if !TerminalLog::is_persisted(&mut connection, printer).await {
+            return;
+        }
+        let last = match lines.last() {
+            Some(line) => line.line,
+            None => return,
+        };
+        let mut transaction = match connection.begin().await {
+            Ok(transaction) => transaction,
+            Err(err) => {
+                eprintln!("[TERMINAL][ERROR] Cannot store lines: {}", err);
+                return;
+            }
+        };
+        for line in lines {
+            let result = sqlx::query(
+                "INSERT OR REPLACE INTO terminal_log (printer, line, message, type, message_id, time) VALUES (?, ?, ?, ?, ?, ?)",
+            )
+            .bind(printer)
+            .bind(line.line)
+            .bind(line.message)
+            .bind(line.kind)
+            .bind(line.id)
+            .bind(line.time)
+            .execute(&mut transaction)
+            .await;
+            if let Err(err) = result {
+                eprintln!("[TERMINAL][ERROR] Cannot store line: {}", err);
+                return;
+            }
+        }
+        let result = sqlx::query("DELETE FROM terminal_log WHERE printer = ? AND line <= ?")
+            .bind(printer)
+            .bind(last - STORED_LINES)
+            .execute(&mut transaction)
+            .await;
+        if let Err(err) = result {
+            eprintln!("[TERMINAL][ERROR] Cannot remove old lines: {}", err);
+            return;
+        }
+        if let Err(err) = transaction.commit().await {
+            eprintln!("[TERMINAL][ERROR] Cannot store lines: {}", err);
+        }
+    }
+
+    /// Check if storing is enabled for the printer, which can override the setting (see printers::PRINTER_SETTINGS).
+    async fn is_persisted(connection: &mut SqliteConnection, printer: u32) -> bool {
+        let query = sqlx::query_as::<_, SettingRow>(
+            "SELECT s.id, s.type, ifnull(p.value, s.value) as value FROM settings s LEFT JOIN printer_settings p ON p.id = s.id AND p.printer = ? where s.id = 'B_savePrinterNotifications'",
+        )
+        .bind(printer);
+        match query.fetch_optional(connection).await {
+            Ok(row) => row.and_then(|row| row.bool).unwrap_or(false),
+            Err(err) => {
+                eprintln!("[TERMINAL][ERROR] {}", err);
+                false
+            }
+        }
+    }
+}
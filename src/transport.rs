@@ -0,0 +1,594 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    path::Path,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use serialport::{Error, ErrorKind, SerialPort, SerialPortType};
+
+use crate::virtual_printer::{self, VirtualPrinter};
+
+pub const TCP_PREFIX: &str = "tcp://";
+pub const RFC2217_PREFIX: &str = "rfc2217://";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const RECONNECT_ATTEMPTS: u32 = 5;
+
+// Baudrates tried when detecting the baudrate, most printers use one of the first two.
+pub const BAUDRATES: [u32; 8] = [250000, 115200, 57600, 38400, 19200, 9600, 230400, 500000];
+// Time a printer gets to answer M115 at a baudrate, boards that reset when the port opens need about 2 seconds.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(4);
+
+// Telnet commands & options used by RFC2217.
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+const BINARY: u8 = 0;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+const COM_PORT_OPTION: u8 = 44;
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+
+/*
+    The connection the bridge uses to talk to the printer.
+
+    Reads have to return an io::ErrorKind::TimedOut error when no data arrived within the timeout,
+    the bridge uses those moments to send queued messages.
+*/
+pub trait Transport: Read + Write + Send {
+    fn name(&self) -> String;
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()>;
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>>;
+}
+
+/*
+    Open a transport based on the device path.
+
+    - virtual://marlin: The built-in virtual printer.
+    - tcp://host:port: A raw TCP serial bridge, like ser2net or ESP3D.
+    - rfc2217://host:port: A telnet serial bridge, the baudrate is set using RFC2217.
+    - Anything else is opened as a local serial port.
+*/
+pub fn open(address: &str, baudrate: u32) -> serialport::Result<Box<dyn Transport>> {
+    if address.starts_with(virtual_printer::ADDRESS_PREFIX) {
+        let port = VirtualPrinter::open(address, baudrate)?;
+        return Ok(Box::new(SerialTransport { port }));
+    }
+    if address.starts_with(TCP_PREFIX) {
+        let host = address.trim_start_matches(TCP_PREFIX);
+        return Ok(Box::new(NetworkTransport::connect(host, false, baudrate)?));
+    }
+    if address.starts_with(RFC2217_PREFIX) {
+        let host = address.trim_start_matches(RFC2217_PREFIX);
+        return Ok(Box::new(NetworkTransport::connect(host, true, baudrate)?));
+    }
+    let port = serialport::new(address, baudrate).open()?;
+    return Ok(Box::new(SerialTransport { port }));
+}
+
+/*
+    Serial port found on the system.
+
+    kind: "usb", "pci", "bluetooth" or "unknown".
+    vendor_id / product_id: USB ids as hex strings, for example "2341" / "0042" for an Arduino Mega.
+*/
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PortInfo {
+    pub path: String,
+    pub kind: String,
+    pub vendor_id: Option<String>,
+    pub product_id: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+}
+
+/*
+    List the serial ports of the system.
+    Without libudev the ports are found in /sys/class/tty on linux, the USB info is read from sysfs as well.
+    Built-in serial ports (serial8250) that cannot be opened aren't connected to anything, so they're skipped.
+*/
+pub fn available_ports() -> serialport::Result<Vec<PortInfo>> {
+    let mut ports = vec![];
+    for port in serialport::available_ports()? {
+        let mut info = PortInfo {
+            path: port.port_name.clone(),
+            kind: "unknown".to_string(),
+            vendor_id: None,
+            product_id: None,
+            manufacturer: None,
+            product: None,
+            serial_number: None,
+        };
+        match port.port_type {
+            SerialPortType::UsbPort(usb) => {
+                info.kind = "usb".to_string();
+                info.vendor_id = Some(format!("{:04x}", usb.vid));
+                info.product_id = Some(format!("{:04x}", usb.pid));
+                info.manufacturer = usb.manufacturer;
+                info.product = usb.product;
+                info.serial_number = usb.serial_number;
+            }
+            SerialPortType::PciPort => info.kind = "pci".to_string(),
+            SerialPortType::BluetoothPort => info.kind = "bluetooth".to_string(),
+            SerialPortType::Unknown => {
+                if let Some(name) = port.port_name.strip_prefix("/sys/class/tty/") {
+                    info.path = format!("/dev/{}", name);
+                    let device = Path::new(&port.port_name).join("device");
+                    let driver = fs::read_link(device.join("driver")).ok();
+                    let driver = driver.as_ref().and_then(|driver| driver.file_name());
+                    if driver.map_or(false, |driver| driver == "serial8250")
+                        && serialport::new(&info.path, 9600).open().is_err()
+                    {
+                        continue;
+                    }
+                    read_usb_info(&device, &mut info);
+                }
+            }
+        }
+        ports.push(info);
+    }
+    ports.sort_by(|a, b| a.path.cmp(&b.path));
+    return Ok(ports);
+}
+
+// The USB device is a parent of the tty device (the interface), with the ids & strings as attributes.
+fn read_usb_info(device: &Path, info: &mut PortInfo) {
+    let mut path = match fs::canonicalize(device) {
+        Ok(path) => path,
+        Err(_) => return,
+    };
+    let read = |path: &Path, name: &str| {
+        fs::read_to_string(path.join(name))
+            .ok()
+            .map(|value| value.trim().to_string())
+    };
+    loop {
+        if path.join("idVendor").is_file() {
+            info.kind = "usb".to_string();
+            info.vendor_id = read(&path, "idVendor");
+            info.product_id = read(&path, "idProduct");
+            info.manufacturer = read(&path, "manufacturer");
+            info.product = read(&path, "product");
+            info.serial_number = read(&path, "serial");
+            return;
+        }
+        if !path.pop() || path == Path::new("/sys/devices") {
+            return;
+        }
+    }
+}
+
+/*
+    Find the baudrate a printer answers on, by sending M115 at every rate until a valid response arrives.
+    The preferred rate (the last one that worked) is tried first, blocks for a few seconds per rate.
+    A response is valid when it has the firmware name or an ok, at a wrong rate only garbage arrives.
+    Returns None when the printer didn't answer at any rate, and an error when the port cannot be opened.
+*/
+pub fn detect_baudrate(address: &str, preferred: Option<u32>) -> serialport::Result<Option<u32>> {
+    let mut rates: Vec<u32> = BAUDRATES.to_vec();
+    if let Some(preferred) = preferred {
+        rates.retain(|rate| *rate != preferred);
+        rates.insert(0, preferred);
+    }
+    for rate in rates {
+        println!("[BRIDGE][BAUDRATE] Trying {} at {} baudrate", address, rate);
+        let mut port = open(address, rate)?;
+        if port.set_timeout(Duration::from_millis(100)).is_err() {
+            continue;
+        }
+        let start = Instant::now();
+        let mut last_probe: Option<Instant> = None;
+        let mut received = String::new();
+        while start.elapsed() < PROBE_TIMEOUT {
+            if last_probe.map_or(true, |time| time.elapsed() >= Duration::from_secs(1)) {
+                // the newline ends any garbage the firmware received while the rate was wrong.
+                if port.write_all(b"\nM115\n").is_err() {
+                    break;
+                }
+                last_probe = Some(Instant::now());
+            }
+            let mut buf = [0; 256];
+            match port.read(&mut buf) {
+                Ok(amount) => received.push_str(&String::from_utf8_lossy(&buf[..amount])),
+                Err(err) if err.kind() == io::ErrorKind::TimedOut => (),
+                Err(_) => break,
+            }
+            let valid = received
+                .lines()
+                .any(|line| line.contains("FIRMWARE_NAME") || line.trim() == "ok" || line.starts_with("ok "));
+            if valid {
+                return Ok(Some(rate));
+            }
+        }
+    }
+    return Ok(None);
+}
+
+pub struct SerialTransport {
+    port: Box<dyn SerialPort>,
+}
+
+impl Read for SerialTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.port.read(buf)
+    }
+}
+
+impl Write for SerialTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.port.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl Transport for SerialTransport {
+    fn name(&self) -> String {
+        self.port.name().unwrap_or("UNNAMED".to_string())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        Ok(self.port.set_timeout(timeout)?)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(SerialTransport {
+            port: self.port.try_clone()?,
+        }))
+    }
+}
+
+/*
+    A serial connection over TCP.
+
+    The stream is shared between clones, so the stream can be replaced for all clones
+    when the connection drops and is reconnected.
+    Reconnecting happens on a separate thread, meanwhile reads time out and writes are kept
+    until the connection is back.
+*/
+pub struct NetworkTransport {
+    host: String,
+    rfc2217: bool,
+    baudrate: u32,
+    timeout: Duration,
+    connection: Arc<RwLock<Connection>>,
+    received: VecDeque<u8>,
+    telnet: TelnetState,
+}
+
+// The generation is increased on every reconnect, so clones don't reconnect twice.
+struct Connection {
+    // None while reconnecting.
+    stream: Option<TcpStream>,
+    generation: u64,
+    read_timeout: Duration,
+    // data written while reconnecting, sent once the connection is back.
+    pending: Vec<u8>,
+    // set when reconnecting failed, reads & writes return it from then on.
+    error: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum TelnetState {
+    Data,
+    Command,
+    Option(u8),
+    Subnegotiation,
+    SubnegotiationCommand,
+}
+
+impl NetworkTransport {
+    fn connect(host: &str, rfc2217: bool, baudrate: u32) -> serialport::Result<Self> {
+        if !host.contains(':') {
+            return Err(Error::new(
+                ErrorKind::NoDevice,
+                format!("No port specified for {}", host),
+            ));
+        }
+        let stream = NetworkTransport::open_stream(host, rfc2217, baudrate)?;
+        Ok(Self {
+            host: host.to_string(),
+            rfc2217,
+            baudrate,
+            timeout: Duration::from_millis(10),
+            connection: Arc::new(RwLock::new(Connection {
+                stream: Some(stream),
+                generation: 0,
+                read_timeout: Duration::from_millis(10),
+                pending: vec![],
+                error: None,
+            })),
+            received: VecDeque::new(),
+            telnet: TelnetState::Data,
+        })
+    }
+
+    fn open_stream(host: &str, rfc2217: bool, baudrate: u32) -> io::Result<TcpStream> {
+        let address = host.to_socket_addrs()?.next();
+        if address.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Cannot resolve {}", host),
+            ));
+        }
+        let mut stream = TcpStream::connect_timeout(&address.unwrap(), CONNECT_TIMEOUT)?;
+        stream.set_nodelay(true)?;
+
+        if rfc2217 {
+            let baudrate = baudrate.to_be_bytes();
+            let mut negotiation = vec![
+                IAC, WILL, BINARY, IAC, DO, BINARY, IAC, WILL, SUPPRESS_GO_AHEAD, IAC, DO,
+                SUPPRESS_GO_AHEAD, IAC, WILL, COM_PORT_OPTION,
+            ];
+            negotiation.extend(&[IAC, SB, COM_PORT_OPTION, SET_BAUDRATE]);
+            negotiation.extend(&baudrate);
+            negotiation.extend(&[IAC, SE]);
+            // 8 data bits, no parity, 1 stop bit
+            negotiation.extend(&[IAC, SB, COM_PORT_OPTION, SET_DATASIZE, 8, IAC, SE]);
+            negotiation.extend(&[IAC, SB, COM_PORT_OPTION, SET_PARITY, 1, IAC, SE]);
+            negotiation.extend(&[IAC, SB, COM_PORT_OPTION, SET_STOPSIZE, 1, IAC, SE]);
+            stream.write_all(&negotiation)?;
+        }
+        return Ok(stream);
+    }
+
+    /*
+        Replace the stream of all clones with a new connection, unless another clone already did.
+        Serial bridges keep the printer connected, so the print can continue afterwards.
+        The connection is opened on a separate thread, so the caller (& the lock) isn't blocked
+        while connecting & waiting between attempts.
+    */
+    fn start_reconnect(&mut self, generation: u64) {
+        let mut connection = self.connection.write().unwrap();
+        self.telnet = TelnetState::Data;
+        if connection.generation != generation || connection.stream.is_none() {
+            return;
+        }
+        connection.stream = None;
+        let shared = self.connection.clone();
+        let host = self.host.clone();
+        let (rfc2217, baudrate) = (self.rfc2217, self.baudrate);
+        std::thread::spawn(move || NetworkTransport::reconnect(shared, host, rfc2217, baudrate));
+    }
+
+    fn reconnect(connection: Arc<RwLock<Connection>>, host: String, rfc2217: bool, baudrate: u32) {
+        let mut last_error = None;
+        for attempt in 1..=RECONNECT_ATTEMPTS {
+            println!(
+                "[BRIDGE][TRANSPORT] Connection to {} lost, reconnecting ({}/{})",
+                host, attempt, RECONNECT_ATTEMPTS
+            );
+            match NetworkTransport::open_stream(&host, rfc2217, baudrate) {
+                Ok(mut stream) => {
+                    let mut connection = connection.write().unwrap();
+                    let result = stream
+                        .set_read_timeout(Some(connection.read_timeout))
+                        .and_then(|_| stream.write_all(&connection.pending));
+                    match result {
+                        Ok(()) => {
+                            connection.pending.clear();
+                            connection.stream = Some(stream);
+                            connection.generation += 1;
+                            println!("[BRIDGE][TRANSPORT] Reconnected to {}", host);
+                            return;
+                        }
+                        Err(err) => last_error = Some(err),
+                    }
+                }
+                Err(err) => last_error = Some(err),
+            }
+            std::thread::sleep(Duration::from_secs(1));
+        }
+        let error = last_error.unwrap();
+        eprintln!("[BRIDGE][TRANSPORT][ERROR] Cannot reconnect to {}: {}", host, error);
+        connection.write().unwrap().error = Some(error.to_string());
+    }
+
+    /// Get the error to return when reconnecting failed.
+    fn reconnect_error(&self, error: &str) -> io::Error {
+        return io::Error::new(
+            io::ErrorKind::NotConnected,
+            format!("Cannot reconnect to {}: {}", self.host, error),
+        );
+    }
+
+    /*
+        Write data while the connection is lost.
+        The data is sent once the connection is back, or right away if it already is.
+    */
+    fn write_pending(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut connection = self.connection.write().unwrap();
+        if let Some(error) = connection.error.as_ref() {
+            return Err(self.reconnect_error(error));
+        }
+        if let Some(mut stream) = connection.stream.as_ref() {
+            return stream.write_all(data);
+        }
+        connection.pending.extend(data);
+        return Ok(());
+    }
+
+    /*
+        Remove telnet commands from the received data.
+        Options other than the ones used by RFC2217 are refused.
+    */
+    fn handle_received(&mut self, data: &[u8]) -> io::Result<()> {
+        if !self.rfc2217 {
+            self.received.extend(data);
+            return Ok(());
+        }
+        let mut responses = vec![];
+        for byte in data {
+            self.telnet = match (self.telnet, *byte) {
+                (TelnetState::Data, IAC) => TelnetState::Command,
+                (TelnetState::Data, byte) => {
+                    self.received.push_back(byte);
+                    TelnetState::Data
+                }
+                (TelnetState::Command, IAC) => {
+                    self.received.push_back(IAC);
+                    TelnetState::Data
+                }
+                (TelnetState::Command, SB) => TelnetState::Subnegotiation,
+                (TelnetState::Command, command) if command >= WILL => TelnetState::Option(command),
+                (TelnetState::Command, _) => TelnetState::Data,
+                (TelnetState::Option(command), option) => {
+                    let supported = [BINARY, SUPPRESS_GO_AHEAD, COM_PORT_OPTION].contains(&option);
+                    if command == DO && !supported {
+                        responses.extend(&[IAC, WONT, option]);
+                    } else if command == WILL && !supported {
+                        responses.extend(&[IAC, DONT, option]);
+                    }
+                    TelnetState::Data
+                }
+                (TelnetState::Subnegotiation, IAC) => TelnetState::SubnegotiationCommand,
+                (TelnetState::Subnegotiation, _) => TelnetState::Subnegotiation,
+                (TelnetState::SubnegotiationCommand, SE) => TelnetState::Data,
+                (TelnetState::SubnegotiationCommand, _) => TelnetState::Subnegotiation,
+            };
+        }
+        if responses.len() > 0 {
+            if let Some(mut stream) = self.connection.read().unwrap().stream.as_ref() {
+                stream.write_all(&responses)?;
+            }
+        }
+        return Ok(());
+    }
+}
+
+impl Read for NetworkTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.received.len() == 0 {
+            let mut chunk = [0; 512];
+            let (result, generation) = {
+                let connection = self.connection.read().unwrap();
+                let result = connection.stream.as_ref().map(|mut stream| stream.read(&mut chunk));
+                (result, connection.generation)
+            };
+            let result = match result {
+                Some(result) => result,
+                None => {
+                    // reconnecting, the read times out like it would without data.
+                    if let Some(error) = self.connection.read().unwrap().error.as_ref() {
+                        return Err(self.reconnect_error(error));
+                    }
+                    std::thread::sleep(self.timeout);
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "Operation timed out",
+                    ));
+                }
+            };
+            match result {
+                Ok(0) => self.start_reconnect(generation),
+                Ok(amount) => self.handle_received(&chunk[..amount])?,
+                Err(err) => match err.kind() {
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "Operation timed out",
+                        ))
+                    }
+                    io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted => {
+                        self.start_reconnect(generation)
+                    }
+                    _ => return Err(err),
+                },
+            }
+        }
+        let mut amount = 0;
+        while amount < buf.len() && self.received.len() > 0 {
+            buf[amount] = self.received.pop_front().unwrap();
+            amount += 1;
+        }
+        return Ok(amount);
+    }
+}
+
+impl Write for NetworkTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut data = buf.to_vec();
+        if self.rfc2217 && data.contains(&IAC) {
+            data = vec![];
+            for byte in buf {
+                if *byte == IAC {
+                    data.push(IAC);
+                }
+                data.push(*byte);
+            }
+        }
+        let (result, generation) = {
+            let connection = self.connection.read().unwrap();
+            let result = connection.stream.as_ref().map(|mut stream| stream.write_all(&data));
+            (result, connection.generation)
+        };
+        match result {
+            Some(Ok(())) => (),
+            Some(Err(err)) => match err.kind() {
+                io::ErrorKind::BrokenPipe
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::NotConnected => {
+                    self.start_reconnect(generation);
+                    self.write_pending(&data)?;
+                }
+                _ => return Err(err),
+            },
+            None => self.write_pending(&data)?,
+        }
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.connection.read().unwrap().stream.as_ref() {
+            Some(mut stream) => stream.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Transport for NetworkTransport {
+    fn name(&self) -> String {
+        if self.rfc2217 {
+            return format!("{}{}", RFC2217_PREFIX, self.host);
+        }
+        return format!("{}{}", TCP_PREFIX, self.host);
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.timeout = timeout;
+        let mut connection = self.connection.write().unwrap();
+        connection.read_timeout = timeout;
+        match connection.stream.as_ref() {
+            Some(stream) => stream.set_read_timeout(Some(timeout)),
+            None => Ok(()),
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(NetworkTransport {
+            host: self.host.clone(),
+            rfc2217: self.rfc2217,
+            baudrate: self.baudrate,
+            timeout: self.timeout,
+            connection: self.connection.clone(),
+            received: VecDeque::new(),
+            telnet: TelnetState::Data,
+        }))
+    }
+}
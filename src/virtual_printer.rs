@@ -0,0 +1,869 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    io::{self, Read, Write},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serialport::{
+    ClearBuffer, DataBits, Error, ErrorKind, FlowControl, Parity, SerialPort, StopBits,
+};
+
+use crate::binary_transfer::{
+    self, CONTROL, CONTROL_CLOSE, CONTROL_SYNC, FILE_ABORT, FILE_CLOSE, FILE_OPEN, FILE_QUERY,
+    FILE_TRANSFER, FILE_WRITE, HEADER_SIZE, HEADER_TOKEN,
+};
+
+pub const ADDRESS_PREFIX: &str = "virtual://";
+
+const AMBIENT_TEMP: f64 = 21.0;
+// Payload size of the binary file transfer, like the default of Marlin.
+const BINARY_BLOCK_SIZE: usize = 512;
+const DEFAULT_CAPABILITIES: [&str; 16] = [
+    "SERIAL_XON_XOFF:0",
+    "BINARY_FILE_TRANSFER:0",
+    "EEPROM:0",
+    "VOLUMETRIC:1",
+    "AUTOREPORT_TEMP:1",
+    "AUTOREPORT_POSITION:1",
+    "PROGRESS:0",
+    "PRINT_JOB:1",
+    "AUTOLEVEL:0",
+    "Z_PROBE:0",
+    "SOFTWARE_POWER:0",
+    "EMERGENCY_PARSER:1",
+    "PROMPT_SUPPORT:0",
+    "SDCARD:0",
+    "THERMAL_PROTECTION:1",
+    "ARCS:1",
+];
+
+/*
+    An in-process Marlin printer, used for development and testing without real hardware.
+
+    Selected by using virtual://marlin as device path.
+    Options can be added as query parameters, for example: virtual://marlin?AUTOREPORT_TEMP=0&resend_every=100
+
+    - Uppercase keys override (or add) the Cap: lines returned by M115.
+    - advanced_ok: Respond with ok N.. P.. B.. (default 1).
+    - resend_every: Reject every n-th numbered line with a checksum error (default 0, disabled).
+
+    With SDCARD=1 the printer has an SD card that's kept in memory, it starts out empty.
+    Files can be written to it with M28 / M29, or with the binary file transfer when BINARY_FILE_TRANSFER=1.
+
+    The printer also accepts the following commands, to inject failures on demand:
+    - !resend: Reject the next numbered line with a checksum error.
+    - !drop: Don't send the ok of the next command, like it got lost on the way.
+    - !error <message>: Respond with Error:<message>.
+    - !action <action>: Respond with //action:<action>, like a host action command.
+*/
+pub struct VirtualPrinter {
+    name: String,
+    baud_rate: u32,
+    timeout: Duration,
+    simulation: Arc<Mutex<Simulation>>,
+}
+
+impl VirtualPrinter {
+    pub fn open(address: &str, baud_rate: u32) -> serialport::Result<Box<dyn SerialPort>> {
+        let address = address.trim_start_matches(ADDRESS_PREFIX);
+        let mut parts = address.splitn(2, '?');
+        let firmware = parts.next().unwrap_or("");
+        if firmware != "marlin" {
+            return Err(Error::new(
+                ErrorKind::NoDevice,
+                format!("Unknown virtual printer: {}", firmware),
+            ));
+        }
+
+        let mut options = HashMap::new();
+        for option in parts.next().unwrap_or("").split('&') {
+            let mut option = option.splitn(2, '=');
+            let key = option.next().unwrap_or("").trim();
+            if key.len() == 0 {
+                continue;
+            }
+            options.insert(key.to_string(), option.next().unwrap_or("1").trim().to_string());
+        }
+
+        Ok(Box::new(Self {
+            name: format!("{}{}", ADDRESS_PREFIX, firmware),
+            baud_rate,
+            timeout: Duration::from_millis(0),
+            simulation: Arc::new(Mutex::new(Simulation::new(SimulationConfig::new(options)))),
+        }))
+    }
+}
+
+impl Read for VirtualPrinter {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            {
+                let mut simulation = self.simulation.lock().unwrap();
+                simulation.update();
+                if simulation.output.len() > 0 {
+                    let mut amount = 0;
+                    while amount < buf.len() {
+                        match simulation.output.pop_front() {
+                            Some(byte) => buf[amount] = byte,
+                            None => break,
+                        }
+                        amount += 1;
+                    }
+                    return Ok(amount);
+                }
+            }
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "Operation timed out",
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+impl Write for VirtualPrinter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut simulation = self.simulation.lock().unwrap();
+        simulation.receive(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SerialPort for VirtualPrinter {
+    fn name(&self) -> Option<String> {
+        Some(self.name.clone())
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(self.baud_rate)
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        Ok(DataBits::Eight)
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        Ok(FlowControl::None)
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        Ok(Parity::None)
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        Ok(StopBits::One)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.baud_rate = baud_rate;
+        Ok(())
+    }
+
+    fn set_data_bits(&mut self, _data_bits: DataBits) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_flow_control(&mut self, _flow_control: FlowControl) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_parity(&mut self, _parity: Parity) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_stop_bits(&mut self, _stop_bits: StopBits) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, _level: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn write_data_terminal_ready(&mut self, _level: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        Ok(self.simulation.lock().unwrap().output.len() as u32)
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        let mut simulation = self.simulation.lock().unwrap();
+        match buffer_to_clear {
+            ClearBuffer::Input => simulation.output.clear(),
+            ClearBuffer::Output => simulation.input.clear(),
+            ClearBuffer::All => {
+                simulation.output.clear();
+                simulation.input.clear();
+            }
+        }
+        Ok(())
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        Ok(Box::new(Self {
+            name: self.name.clone(),
+            baud_rate: self.baud_rate,
+            timeout: self.timeout,
+            simulation: self.simulation.clone(),
+        }))
+    }
+
+    fn set_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn clear_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+}
+
+struct SimulationConfig {
+    capabilities: Vec<String>,
+    advanced_ok: bool,
+    resend_every: usize,
+}
+
+impl SimulationConfig {
+    fn new(options: HashMap<String, String>) -> Self {
+        let mut capabilities: Vec<String> = DEFAULT_CAPABILITIES
+            .iter()
+            .map(|cap| cap.to_string())
+            .collect();
+        let mut advanced_ok = true;
+        let mut resend_every = 0;
+
+        for (key, value) in options {
+            if key == "advanced_ok" {
+                advanced_ok = value != "0";
+            } else if key == "resend_every" {
+                resend_every = value.parse().unwrap_or(0);
+            } else if key.chars().all(|c| c.is_ascii_uppercase() || c == '_') {
+                let prefix = format!("{}:", key);
+                capabilities.retain(|cap| !cap.starts_with(&prefix));
+                capabilities.push(format!("{}{}", prefix, value));
+            }
+        }
+        Self {
+            capabilities,
+            advanced_ok,
+            resend_every,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Heater {
+    current: f64,
+    target: f64,
+    // degrees per second
+    rate: f64,
+}
+
+impl Heater {
+    fn new(rate: f64) -> Self {
+        Self {
+            current: AMBIENT_TEMP,
+            target: 0.0,
+            rate,
+        }
+    }
+
+    fn update(&mut self, elapsed: f64) {
+        let goal = if self.target > 0.0 {
+            self.target
+        } else {
+            AMBIENT_TEMP
+        };
+        let step = self.rate * elapsed;
+        if (goal - self.current).abs() <= step {
+            self.current = goal;
+        } else if goal > self.current {
+            self.current += step;
+        } else {
+            self.current -= step;
+        }
+    }
+
+    fn is_at_target(&self) -> bool {
+        return (self.target - self.current).abs() < 1.0;
+    }
+
+    fn power(&self) -> u8 {
+        if self.target > self.current {
+            return 127;
+        }
+        return 0;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum WaitingFor {
+    Nothing,
+    Hotend,
+    Bed,
+}
+
+struct Simulation {
+    config: SimulationConfig,
+    input: Vec<u8>,
+    output: VecDeque<u8>,
+    last_line: usize,
+    // Whether the command being executed had a line number, only then the ok includes it.
+    numbered: bool,
+    numbered_lines: usize,
+    reject_next_line: bool,
+    drop_next_ok: bool,
+    halted: bool,
+    hotend: Heater,
+    bed: Heater,
+    // X, Y, Z, E
+    position: [f64; 4],
+    relative: bool,
+    relative_extrusion: bool,
+    autoreport_interval: Option<Duration>,
+    last_report: Instant,
+    position_interval: Option<Duration>,
+    last_position_report: Instant,
+    last_update: Instant,
+    waiting: WaitingFor,
+    // files on the SD card & the file M28 (or an open packet) writes to.
+    sd_files: BTreeMap<String, Vec<u8>>,
+    sd_writing: Option<String>,
+    // the input is read as packets of the binary file transfer (M28 B1), instead of as lines.
+    binary: bool,
+    binary_sync: u8,
+}
+
+impl Simulation {
+    fn new(config: SimulationConfig) -> Self {
+        Self {
+            config,
+            input: vec![],
+            output: VecDeque::new(),
+            last_line: 0,
+            numbered: false,
+            numbered_lines: 0,
+            reject_next_line: false,
+            drop_next_ok: false,
+            halted: false,
+            hotend: Heater::new(5.0),
+            bed: Heater::new(1.5),
+            position: [0.0; 4],
+            relative: false,
+            relative_extrusion: false,
+            autoreport_interval: None,
+            last_report: Instant::now(),
+            position_interval: None,
+            last_position_report: Instant::now(),
+            last_update: Instant::now(),
+            waiting: WaitingFor::Nothing,
+            sd_files: BTreeMap::new(),
+            sd_writing: None,
+            binary: false,
+            binary_sync: 0,
+        }
+    }
+
+    /*
+        Advance the heaters, send automatic temperature & position reports
+        and finish a M109 / M190 once the target has been reached.
+    */
+    fn update(&mut self) {
+        let elapsed = self.last_update.elapsed().as_secs_f64();
+        self.last_update = Instant::now();
+        self.hotend.update(elapsed);
+        self.bed.update(elapsed);
+        if self.halted {
+            return;
+        }
+
+        if self.waiting != WaitingFor::Nothing {
+            if self.last_report.elapsed() >= Duration::from_secs(1) {
+                self.last_report = Instant::now();
+                let report = self.temperature_report();
+                self.respond(&format!(" {}", report));
+            }
+            let heater = if self.waiting == WaitingFor::Hotend {
+                self.hotend
+            } else {
+                self.bed
+            };
+            if heater.is_at_target() {
+                self.waiting = WaitingFor::Nothing;
+                self.ok();
+                self.process_input();
+            }
+            return;
+        }
+
+        if let Some(interval) = self.autoreport_interval {
+            if self.last_report.elapsed() >= interval {
+                self.last_report = Instant::now();
+                let report = self.temperature_report();
+                self.respond(&format!(" {}", report));
+            }
+        }
+        if let Some(interval) = self.position_interval {
+            if self.last_position_report.elapsed() >= interval {
+                self.last_position_report = Instant::now();
+                let report = self.position_report();
+                self.respond(&report);
+            }
+        }
+    }
+
+    fn receive(&mut self, data: &[u8]) {
+        self.input.extend(data);
+        self.process_input();
+    }
+
+    // Handle all complete lines (or packets), unless a command is still waiting for a heater.
+    fn process_input(&mut self) {
+        while self.waiting == WaitingFor::Nothing && !self.halted {
+            if self.binary {
+                if !self.process_packet() {
+                    return;
+                }
+                continue;
+            }
+            let end = match self.input.iter().position(|byte| *byte == b'\n') {
+                Some(end) => end,
+                None => return,
+            };
+            let line: Vec<u8> = self.input.drain(..=end).collect();
+            self.handle_line(String::from_utf8_lossy(&line).trim());
+        }
+    }
+
+    /*
+        Handle the next packet of the binary file transfer, returns false when it didn't fully arrive yet.
+        Data in front of the packet's token is skipped, a packet with a wrong checksum is requested again.
+    */
+    fn process_packet(&mut self) -> bool {
+        let token = HEADER_TOKEN.to_le_bytes();
+        let start = self.input.windows(2).position(|bytes| bytes == token);
+        let start = match start {
+            Some(start) => start,
+            None => {
+                // the first byte of the token may already be there.
+                let keep = if self.input.last() == Some(&token[0]) { 1 } else { 0 };
+                self.input.drain(..self.input.len() - keep);
+                return false;
+            }
+        };
+        self.input.drain(..start);
+        if self.input.len() < HEADER_SIZE {
+            return false;
+        }
+        let header_checksum = u16::from_le_bytes([self.input[6], self.input[7]]);
+        if binary_transfer::checksum(0, &self.input[..6]) != header_checksum {
+            self.input.drain(..2);
+            self.respond(&format!("rs{}", self.binary_sync));
+            return true;
+        }
+        let sync = self.input[2];
+        let protocol = self.input[3] >> 4;
+        let packet_type = self.input[3] & 0xF;
+        let size = u16::from_le_bytes([self.input[4], self.input[5]]) as usize;
+        let length = if size > 0 { HEADER_SIZE + size + 2 } else { HEADER_SIZE };
+        if self.input.len() < length {
+            return false;
+        }
+        let packet: Vec<u8> = self.input.drain(..length).collect();
+        if size > 0 {
+            let checksum = u16::from_le_bytes([packet[length - 2], packet[length - 1]]);
+            if binary_transfer::checksum(0, &packet[..length - 2]) != checksum {
+                self.respond(&format!("rs{}", self.binary_sync));
+                return true;
+            }
+        }
+        // the sync packet is accepted with any sync.
+        if protocol == CONTROL && packet_type == CONTROL_SYNC {
+            self.respond(&format!("ss{},{},0.1.0", self.binary_sync, BINARY_BLOCK_SIZE));
+            return true;
+        }
+        if sync != self.binary_sync {
+            // the ok of the previous packet got lost, it's sent again.
+            if sync == self.binary_sync.wrapping_sub(1) {
+                self.respond(&format!("ok{}", sync));
+            } else {
+                self.respond(&format!("rs{}", self.binary_sync));
+            }
+            return true;
+        }
+        self.respond(&format!("ok{}", sync));
+        self.binary_sync = self.binary_sync.wrapping_add(1);
+        let payload = &packet[HEADER_SIZE..HEADER_SIZE + size];
+        self.handle_packet(protocol, packet_type, payload);
+        return true;
+    }
+
+    fn handle_packet(&mut self, protocol: u8, packet_type: u8, payload: &[u8]) {
+        match (protocol, packet_type) {
+            (CONTROL, CONTROL_CLOSE) => self.binary = false,
+            (FILE_TRANSFER, FILE_QUERY) => self.respond("PFT:version:0.1.0:compression:none"),
+            (FILE_TRANSFER, FILE_OPEN) => {
+                // dummy transfer & compression flags, followed by the file name.
+                let name = payload.get(2..).unwrap_or(&[]);
+                let name = String::from_utf8_lossy(name).trim_end_matches('\0').to_uppercase();
+                if self.sd_writing.is_some() {
+                    self.respond("PFT:busy");
+                } else if name.len() == 0 {
+                    self.respond("PFT:fail");
+                } else {
+                    self.sd_files.insert(name.clone(), vec![]);
+                    self.sd_writing = Some(name);
+                    self.respond("PFT:success");
+                }
+            }
+            (FILE_TRANSFER, FILE_WRITE) => match self.sd_writing.clone() {
+                Some(name) => self.sd_files.get_mut(&name).unwrap().extend(payload),
+                None => self.respond("PFT:fail"),
+            },
+            (FILE_TRANSFER, FILE_CLOSE) => match self.sd_writing.take() {
+                Some(_) => self.respond("PFT:success"),
+                None => self.respond("PFT:ioerror"),
+            },
+            (FILE_TRANSFER, FILE_ABORT) => {
+                if let Some(name) = self.sd_writing.take() {
+                    self.sd_files.remove(&name);
+                }
+                self.respond("PFT:success");
+            }
+            _ => (),
+        }
+    }
+
+    fn handle_line(&mut self, line: &str) {
+        if line.len() == 0 {
+            return;
+        }
+        if line == "!resend" {
+            self.reject_next_line = true;
+            return self.ok();
+        }
+        if line == "!drop" {
+            self.drop_next_ok = true;
+            return self.ok();
+        }
+        if line.starts_with("!action") {
+            let action = line.trim_start_matches("!action").trim().to_string();
+            self.respond(&format!("//action:{}", action));
+            return self.ok();
+        }
+        if line.starts_with("!error") {
+            let message = line.trim_start_matches("!error").trim().to_string();
+            self.respond(&format!("Error:{}", message));
+            return self.ok();
+        }
+
+        let mut command = line.to_string();
+        if line.starts_with('N') {
+            let mut parts = line.splitn(2, '*');
+            let body = parts.next().unwrap_or("");
+            let checksum = parts.next().and_then(|cs| cs.trim().parse::<u8>().ok());
+            let calculated = body.bytes().fold(0u8, |cs, byte| cs ^ byte);
+            if checksum != Some(calculated) {
+                return self.request_resend("checksum mismatch");
+            }
+
+            let digits: String = body[1..].chars().take_while(|c| c.is_ascii_digit()).collect();
+            let number = digits.parse::<usize>().unwrap_or(0);
+            command = body[1 + digits.len()..].trim().to_string();
+
+            if !command.starts_with("M110") {
+                if number != self.last_line + 1 {
+                    return self.request_resend("Line Number is not Last Line Number+1");
+                }
+                self.numbered_lines += 1;
+                let inject = self.config.resend_every > 0
+                    && self.numbered_lines % self.config.resend_every == 0;
+                if self.reject_next_line || inject {
+                    self.reject_next_line = false;
+                    return self.request_resend("checksum mismatch");
+                }
+            }
+            self.last_line = number;
+        }
+        self.numbered = line.starts_with('N');
+        // while M28 writes to a file, every command but M29 is written to it instead of executed.
+        if let Some(name) = self.sd_writing.clone() {
+            if !command.to_uppercase().starts_with("M29") {
+                let file = self.sd_files.get_mut(&name).unwrap();
+                file.extend(command.as_bytes());
+                file.push(b'\n');
+                return self.ok();
+            }
+        }
+        self.execute(&command);
+    }
+
+    fn execute(&mut self, command: &str) {
+        let (code, params) = parse_command(command);
+        let param = |letter: char| -> Option<f64> {
+            params
+                .iter()
+                .find(|(key, _)| *key == letter)
+                .map(|(_, value)| *value)
+        };
+
+        match code.as_str() {
+            "M110" => {
+                if let Some(line) = param('N') {
+                    self.last_line = line as usize;
+                }
+            }
+            "M115" => {
+                self.respond("FIRMWARE_NAME:Marlin 2.0.9 (Virtual) SOURCE_CODE_URL:github.com/MarlinFirmware/Marlin PROTOCOL_VERSION:1.0 MACHINE_TYPE:Virtual EXTRUDER_COUNT:1");
+                for capability in self.config.capabilities.clone() {
+                    self.respond(&format!("Cap:{}", capability));
+                }
+            }
+            "M105" => {
+                // M105 responds with the report instead of the regular ok.
+                let report = self.temperature_report();
+                return self.respond(&format!("ok {}", report));
+            }
+            "M155" => {
+                let interval = param('S').unwrap_or(0.0);
+                self.autoreport_interval = if interval > 0.0 {
+                    Some(Duration::from_secs_f64(interval))
+                } else {
+                    None
+                };
+            }
+            "M154" => {
+                let interval = param('S').unwrap_or(0.0);
+                self.position_interval = if interval > 0.0 {
+                    Some(Duration::from_secs_f64(interval))
+                } else {
+                    None
+                };
+            }
+            "M104" | "M109" => {
+                if let Some(target) = param('S') {
+                    self.hotend.target = target;
+                }
+                if code == "M109" {
+                    self.waiting = WaitingFor::Hotend;
+                    return;
+                }
+            }
+            "M140" | "M190" => {
+                if let Some(target) = param('S') {
+                    self.bed.target = target;
+                }
+                if code == "M190" {
+                    self.waiting = WaitingFor::Bed;
+                    return;
+                }
+            }
+            "G0" | "G1" | "G92" => {
+                for (index, axis) in ['X', 'Y', 'Z', 'E'].iter().enumerate() {
+                    if let Some(value) = param(*axis) {
+                        let relative = if index == 3 {
+                            self.relative_extrusion
+                        } else {
+                            self.relative
+                        };
+                        if code != "G92" && relative {
+                            self.position[index] += value;
+                        } else {
+                            self.position[index] = value;
+                        }
+                    }
+                }
+            }
+            "G28" => {
+                for index in 0..3 {
+                    self.position[index] = 0.0;
+                }
+            }
+            "G90" => {
+                self.relative = false;
+                self.relative_extrusion = false;
+            }
+            "G91" => {
+                self.relative = true;
+                self.relative_extrusion = true;
+            }
+            "M82" => self.relative_extrusion = false,
+            "M83" => self.relative_extrusion = true,
+            "M114" => {
+                let report = self.position_report();
+                self.respond(&report);
+            }
+            "M20" if self.sd_card() => {
+                self.respond("Begin file list");
+                for (name, data) in self.sd_files.clone() {
+                    self.respond(&format!("{} {}", name, data.len()));
+                }
+                self.respond("End file list");
+            }
+            "M28"
+                if self.sd_card()
+                    && param('B') == Some(1.0)
+                    && self.supports("BINARY_FILE_TRANSFER") =>
+            {
+                self.respond(binary_transfer::ENTERED_MESSAGE);
+                self.ok();
+                self.binary = true;
+                self.binary_sync = 0;
+                return;
+            }
+            "M28" if self.sd_card() => {
+                let name = command[3..].trim().to_uppercase();
+                if name.len() == 0 {
+                    self.respond(&format!("open failed, File: {}.", name));
+                } else {
+                    self.sd_files.insert(name.clone(), vec![]);
+                    self.respond(&format!("Writing to file: {}", name));
+                    self.sd_writing = Some(name);
+                }
+            }
+            "M29" if self.sd_card() => {
+                if self.sd_writing.take().is_some() {
+                    self.respond("Done saving file.");
+                }
+            }
+            "M30" if self.sd_card() => {
+                let name = command[3..].trim().to_uppercase();
+                if self.sd_files.remove(&name).is_some() {
+                    self.respond(&format!("File deleted:{}", name));
+                } else {
+                    self.respond(&format!("Deletion failed, File: {}.", name));
+                }
+            }
+            "M112" => {
+                self.halted = true;
+                self.hotend.target = 0.0;
+                self.bed.target = 0.0;
+                self.respond("Error:Printer halted. kill() called!");
+                return;
+            }
+            _ => (),
+        }
+        self.ok();
+    }
+
+    fn supports(&self, capability: &str) -> bool {
+        let enabled = format!("{}:1", capability);
+        return self.config.capabilities.contains(&enabled);
+    }
+
+    fn sd_card(&self) -> bool {
+        return self.supports("SDCARD");
+    }
+
+    fn position_report(&self) -> String {
+        let position = self.position;
+        return format!(
+            "X:{:.2} Y:{:.2} Z:{:.2} E:{:.2} Count X:0 Y:0 Z:0",
+            position[0], position[1], position[2], position[3]
+        );
+    }
+
+    fn temperature_report(&self) -> String {
+        return format!(
+            "T:{:.2} /{:.2} B:{:.2} /{:.2} @:{} B@:{}",
+            self.hotend.current,
+            self.hotend.target,
+            self.bed.current,
+            self.bed.target,
+            self.hotend.power(),
+            self.bed.power()
+        );
+    }
+
+    fn request_resend(&mut self, reason: &str) {
+        self.respond(&format!("Error:{}, Last Line: {}", reason, self.last_line));
+        self.respond(&format!("Resend: {}", self.last_line + 1));
+        self.ok();
+    }
+
+    fn ok(&mut self) {
+        if self.drop_next_ok {
+            self.drop_next_ok = false;
+            return;
+        }
+        let mut response = "ok".to_string();
+        if self.config.advanced_ok && self.numbered {
+            response = format!("ok N{} P15 B3", self.last_line);
+        } else if self.config.advanced_ok {
+            response = "ok P15 B3".to_string();
+        }
+        self.respond(&response);
+    }
+
+    fn respond(&mut self, line: &str) {
+        self.output.extend(line.as_bytes());
+        self.output.push_back(b'\n');
+    }
+}
+
+/*
+    Split a command into its code and parameters.
+    Handles commands with and without spaces, for example G1X10Y-2.5 and G1 X10 Y-2.5
+*/
+fn parse_command(command: &str) -> (String, Vec<(char, f64)>) {
+    let command = command.split(';').next().unwrap_or("").to_uppercase();
+    let mut chars = command.chars().filter(|c| !c.is_whitespace()).peekable();
+    let mut code = String::new();
+    let mut params = vec![];
+
+    while let Some(letter) = chars.next() {
+        let mut value = String::new();
+        while let Some(c) = chars.peek() {
+            if c.is_ascii_digit() || *c == '.' || *c == '-' {
+                value.push(*c);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        if code.len() == 0 {
+            code = format!("{}{}", letter, value);
+        } else {
+            params.push((letter, value.parse().unwrap_or(0.0)));
+        }
+    }
+    return (code, params);
+}
@@ -0,0 +1,263 @@
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref COMMAND: Regex = Regex::new(r"^(?:N(\d+)\s*)?([GMT]\d+)").unwrap();
+}
+
+// Unanswered probes before the printer is considered unresponsive.
+pub const PROBE_ATTEMPTS: u32 = 2;
+// While waiting for these commands, the firmware only sends temperature reports until it's done.
+const HEATING_COMMANDS: [&str; 4] = ["M109", "M190", "M191", "M303"];
+// These commands wait for the user, which can take any amount of time.
+const USER_COMMANDS: [&str; 3] = ["M0", "M1", "M600"];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum WatchdogAction {
+    Wait,
+    // send the given command, the firmware has to answer it.
+    Probe { command: String, attempt: u32 },
+    Unresponsive,
+}
+
+/*
+    Keeps track of the commands that are sent to the printer, but not acknowledged with an ok yet.
+
+    When commands are outstanding and nothing is received for the configured timeout,
+    the communication is considered stalled (for example because an ok got lost).
+    Automatic temperature and position reports don't count as a response,
+    as those keep coming while the firmware waits for a line that never arrived.
+    busy: messages do count, the firmware sends those while it's processing a long command.
+
+    Print lines are tracked separately by their line number, as the oks of polled commands (M105, M114)
+    would otherwise hide a lost ok of a print line.
+*/
+pub struct Watchdog {
+    outstanding: usize,
+    last_activity: Instant,
+    // line number, line and time since the last numbered line is waiting for its ok.
+    numbered_line: Option<(usize, String, Instant)>,
+    attempts: u32,
+    heating: bool,
+    // line number of the command waiting for the user, 0 when it isn't numbered.
+    user_command: Option<usize>,
+}
+
+impl Watchdog {
+    pub fn new() -> Self {
+        Self {
+            outstanding: 0,
+            last_activity: Instant::now(),
+            numbered_line: None,
+            attempts: 0,
+            heating: false,
+            user_command: None,
+        }
+    }
+
+    /// Register a command that was written to the printer.
+    pub fn sent(&mut self, command: &str) {
+        let command = command.trim();
+        if command.len() == 0 {
+            return;
+        }
+        if self.outstanding == 0 {
+            self.last_activity = Instant::now();
+        }
+        self.outstanding += 1;
+        if let Some(captures) = COMMAND.captures(command) {
+            let number = captures.get(1).and_then(|nr| nr.as_str().parse().ok());
+            if let Some(number) = number {
+                if self.numbered_line.as_ref().map(|(previous, ..)| *previous) != Some(number) {
+                    self.attempts = 0;
+                }
+                self.numbered_line = Some((number, command.to_string(), Instant::now()));
+            }
+            if HEATING_COMMANDS.contains(&&captures[2]) {
+                self.heating = true;
+            }
+            if USER_COMMANDS.contains(&&captures[2]) && self.user_command.is_none() {
+                self.user_command = Some(number.unwrap_or(0));
+            }
+        }
+    }
+
+    /*
+        Register a line received from the printer.
+        acknowledged: Line number confirmed by the ok on this line (ok N..), if any.
+        report: The line is an automatic temperature or position report.
+    */
+    pub fn received(&mut self, line: &str, acknowledged: Option<usize>, report: bool) {
+        let now = Instant::now();
+        if line.starts_with("ok") {
+            // an answered probe means every earlier command is either handled or lost.
+            if self.attempts > 0 {
+                self.outstanding = 0;
+            } else {
+                self.outstanding = self.outstanding.saturating_sub(1);
+            }
+            if self.outstanding == 0 {
+                self.heating = false;
+                self.user_command = None;
+            }
+            if let (Some(acknowledged), Some((number, ..))) = (acknowledged, &self.numbered_line) {
+                if acknowledged >= *number {
+                    self.numbered_line = None;
+                }
+            }
+            if let (Some(acknowledged), Some(number)) = (acknowledged, self.user_command) {
+                if number > 0 && acknowledged >= number {
+                    self.user_command = None;
+                }
+            }
+            // the oks of polled commands don't answer a waiting print line.
+            if self.numbered_line.is_none() {
+                self.attempts = 0;
+            }
+            self.last_activity = now;
+            return;
+        }
+        if line.contains("busy:") {
+            self.attempts = 0;
+        } else if report && !self.heating {
+            return;
+        }
+        self.last_activity = now;
+        if let Some((_, _, since)) = self.numbered_line.as_mut() {
+            *since = now;
+        }
+    }
+
+    /*
+        Check if the communication stalled, should be called regularly.
+
+        Returns Probe when nothing was received for the timeout while commands are outstanding.
+        A waiting print line is sent again: the firmware either handles it (if it never arrived)
+        or requests a resend of the next line (if only the ok got lost). Otherwise M105 is sent.
+        Returns Unresponsive when PROBE_ATTEMPTS probes went unanswered as well.
+        Nothing is probed while a command waits for the user, as the firmware may not send anything until then.
+    */
+    pub fn check(&mut self, timeout: Duration) -> WatchdogAction {
+        if self.user_command.is_some() {
+            return WatchdogAction::Wait;
+        }
+        let mut command = None;
+        if let Some((_, line, since)) = &self.numbered_line {
+            if since.elapsed() >= timeout {
+                command = Some(line.clone());
+            }
+        }
+        if command.is_none() && self.outstanding > 0 && self.last_activity.elapsed() >= timeout {
+            command = Some("M105".to_string());
+        }
+        if command.is_none() {
+            return WatchdogAction::Wait;
+        }
+        if self.attempts >= PROBE_ATTEMPTS {
+            return WatchdogAction::Unresponsive;
+        }
+        self.attempts += 1;
+        self.last_activity = Instant::now();
+        return WatchdogAction::Probe {
+            command: command.unwrap(),
+            attempt: self.attempts,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+    // waits that stay below the timeout & ones that exceed it.
+    const SHORT: Duration = Duration::from_millis(60);
+    const LONG: Duration = Duration::from_millis(120);
+
+    #[test]
+    fn probes_a_print_line_that_lost_its_ok() {
+        let mut watchdog = Watchdog::new();
+        watchdog.sent("N10 G1 X10*95");
+        watchdog.sent("M105");
+        // the ok of the polled M105 doesn't answer the print line.
+        watchdog.received("ok T:21.00 /0.00", None, false);
+        assert_eq!(watchdog.check(TIMEOUT), WatchdogAction::Wait);
+
+        sleep(LONG);
+        assert_eq!(
+            watchdog.check(TIMEOUT),
+            WatchdogAction::Probe {
+                command: "N10 G1 X10*95".to_string(),
+                attempt: 1
+            }
+        );
+        // the firmware answers the line sent again, the print continues.
+        watchdog.received("ok N10", Some(10), false);
+        sleep(LONG);
+        assert_eq!(watchdog.check(TIMEOUT), WatchdogAction::Wait);
+    }
+
+    #[test]
+    fn counts_busy_messages_as_activity() {
+        let mut watchdog = Watchdog::new();
+        watchdog.sent("G28");
+        sleep(SHORT);
+        watchdog.received("echo:busy: processing", None, false);
+        sleep(SHORT);
+        assert_eq!(watchdog.check(TIMEOUT), WatchdogAction::Wait);
+
+        // automatic reports keep coming while the firmware misses a command, they don't count.
+        watchdog.received("T:21.00 /0.00 B:21.00 /0.00 @:0 B@:0", None, true);
+        sleep(SHORT);
+        assert_eq!(
+            watchdog.check(TIMEOUT),
+            WatchdogAction::Probe {
+                command: "M105".to_string(),
+                attempt: 1
+            }
+        );
+    }
+
+    #[test]
+    fn waits_for_heating_and_the_user() {
+        let mut watchdog = Watchdog::new();
+        watchdog.sent("M109 S200");
+        for _ in 0..2 {
+            sleep(SHORT);
+            // the temperature reports are the only sign of life while heating.
+            watchdog.received("T:150.00 /200.00 B:21.00 /0.00 @:127 B@:0", None, true);
+        }
+        assert_eq!(watchdog.check(TIMEOUT), WatchdogAction::Wait);
+        watchdog.received("ok", None, false);
+
+        watchdog.sent("N20 M600*78");
+        sleep(LONG);
+        assert_eq!(watchdog.check(TIMEOUT), WatchdogAction::Wait);
+        watchdog.received("ok N20", Some(20), false);
+        sleep(LONG);
+        assert_eq!(watchdog.check(TIMEOUT), WatchdogAction::Wait);
+    }
+
+    #[test]
+    fn gives_up_after_the_probe_attempts() {
+        let mut watchdog = Watchdog::new();
+        watchdog.sent("G28");
+        for attempt in 1..=PROBE_ATTEMPTS {
+            sleep(LONG);
+            assert_eq!(
+                watchdog.check(TIMEOUT),
+                WatchdogAction::Probe {
+                    command: "M105".to_string(),
+                    attempt
+                }
+            );
+            assert_eq!(watchdog.check(TIMEOUT), WatchdogAction::Wait);
+        }
+        sleep(LONG);
+        assert_eq!(watchdog.check(TIMEOUT), WatchdogAction::Unresponsive);
+    }
+}